@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::{legacy::LegacyError, logic_parse::LogicParseError};
+
+#[derive(Debug, Error)]
+pub enum RandomizerError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Unable to read {}: {source}", path.display())]
+    ReadFile { path: PathBuf, source: std::io::Error },
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Error parsing requirements for item {name}: {source}")]
+    ItemRequirement { name: String, source: Box<LogicParseError> },
+    #[error("Error parsing requirements for flag {name}: {source}")]
+    FlagRequirement { name: String, source: Box<LogicParseError> },
+    #[error("Error parsing requirements for location {name}: {source}")]
+    LocationRequirement { name: String, source: Box<LogicParseError> },
+    #[error("Error parsing when for item {name}: {source}")]
+    ItemWhen { name: String, source: Box<LogicParseError> },
+    #[error("Error parsing when for location {name}: {source}")]
+    LocationWhen { name: String, source: Box<LogicParseError> },
+    #[error("Count of item {name} must be a number or an integer parameter, not {count}")]
+    InvalidItemCount { name: String, count: String },
+    #[error("Location {location} has unknown vanilla item {item}")]
+    UnknownVanillaItem { location: String, item: String },
+    #[error("Location {0} is fixed, but has no vanilla item")]
+    FixedWithoutVanilla(String),
+    #[error("Error parsing goal: {0}")]
+    GoalRequirement(Box<LogicParseError>),
+    #[error("Unknown parameter {0}")]
+    UnknownParameter(String),
+    #[error("Invalid setting {0}; expected Name=value")]
+    InvalidSetting(String),
+    #[error("Invalid value {value} for parameter {name}; expected {expected}")]
+    InvalidParameterValue { name: String, value: String, expected: String },
+    #[error("Error importing legacy logic: {0}")]
+    Legacy(#[from] LegacyError),
+    #[error("The goal needs items that only the ROM provides: {}", .0.join(", "))]
+    GoalNeedsRomItems(Vec<String>),
+    #[error("Unknown location {0} in exclude list")]
+    UnknownExcludedLocation(String),
+    #[error("Plando places an item in unknown location {0}")]
+    PlandoUnknownLocation(String),
+    #[error("Plando places unknown item {item} in {location}")]
+    PlandoUnknownItem { location: String, item: String },
+    #[error("Plando can't place {item} in {location}: {reason}")]
+    PlandoMismatch { location: String, item: String, reason: String },
+    #[error("Plando places {item} {placed} times, but the item pool only has {count}")]
+    PlandoTooMany { item: String, placed: usize, count: usize },
+    #[error("Plando has no item left to pick for {0}")]
+    PlandoNoItemLeft(String),
+    #[error("Beatable-only accessibility requires the logic to define a goal")]
+    MissingGoal,
+    #[error("Impossible item pool: {0}")]
+    ImpossiblePool(String),
+    #[error("Not making progress after {rounds} rounds; giving up with {unplaced} items unplaced")]
+    AllocationFailed { rounds: usize, unplaced: usize },
+    /// `backtracks` of the attempts were backtracks, and `check_failures` of them finished,
+    /// but failed the assignment check
+    #[error("No valid assignment found after {attempts} attempts")]
+    AttemptsExhausted { attempts: usize, backtracks: usize, check_failures: usize },
+}
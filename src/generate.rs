@@ -0,0 +1,408 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    logic::{
+        AllocationEvent, AllocationObserver, AllocatorSettings, CheckReport, Conditionals,
+        ItemCondition, ItemDef, Location,
+    },
+    Allocator, AssignmentChecker, Logic, PlandoPlacements, RandomizerError,
+};
+
+/// What to do when an allocation gets stuck or fails the assignment check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many attempts to make in total; every restart and every backtrack counts as one
+    pub max_attempts: usize,
+    /// How many placements to undo when the allocator gets stuck; 0 always restarts from scratch
+    pub backtrack: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            backtrack: 0,
+        }
+    }
+}
+
+/// A complete assignment that passed the check for the chosen accessibility
+pub struct Generated {
+    /// The seed that was asked for
+    pub seed: u64,
+    /// The seed of the attempt that succeeded; generating with it again needs no restarts
+    pub attempt_seed: u64,
+    pub attempts: usize,
+    pub backtracks: usize,
+    /// Attempts that finished before this one, but failed the assignment check
+    pub check_failures: usize,
+    pub settings: AllocatorSettings,
+    pub assignments: BTreeMap<Arc<Location>, Arc<ItemDef>>,
+    pub report: CheckReport,
+}
+
+/// Runs allocations until one passes the assignment check
+pub struct Generator<'a> {
+    logic: &'a Logic,
+    item_pool: &'a Conditionals<ItemDef>,
+    settings: AllocatorSettings,
+    policy: RetryPolicy,
+    plando: Option<PlandoPlacements>,
+    /// How many threads `generate_parallel` uses; every core if unset
+    threads: Option<NonZeroUsize>,
+    /// Compiled once, and shared by the threads of `generate_parallel`
+    checker: Arc<AssignmentChecker>,
+    observer: Rc<RefCell<Box<dyn AllocationObserver>>>,
+}
+
+impl<'a> Generator<'a> {
+    pub fn new(logic: &'a Logic, item_pool: &'a Conditionals<ItemDef>, settings: AllocatorSettings) -> Self {
+        let checker = Arc::new(AssignmentChecker::from_logic(logic));
+        Self::with_checker(logic, item_pool, settings, checker)
+    }
+
+    fn with_checker(
+        logic: &'a Logic,
+        item_pool: &'a Conditionals<ItemDef>,
+        settings: AllocatorSettings,
+        checker: Arc<AssignmentChecker>,
+    ) -> Self {
+        Generator {
+            logic,
+            item_pool,
+            settings,
+            policy: Default::default(),
+            plando: None,
+            threads: None,
+            checker,
+            observer: Rc::new(RefCell::new(Box::new(()))),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Places the plando's items before every attempt; its choices are made anew each time
+    pub fn with_plando(mut self, plando: PlandoPlacements) -> Self {
+        self.plando = Some(plando);
+        self
+    }
+
+    /// Limits how many seeds [`Generator::generate_parallel`] works on at once
+    pub fn with_threads(mut self, threads: NonZeroUsize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Sets the observer that receives the events of every attempt
+    pub fn set_observer(&mut self, observer: Box<dyn AllocationObserver>) {
+        self.observer = Rc::new(RefCell::new(observer));
+    }
+
+    pub fn generate(&self, seed: u64) -> Result<Generated, RandomizerError> {
+        let generated = self.attempt(seed);
+        // What was worked out about this seed's requirements won't come up again, and would
+        // keep them alive
+        ItemCondition::clear_memo();
+        generated
+    }
+
+    fn attempt(&self, seed: u64) -> Result<Generated, RandomizerError> {
+        // Later attempts get seeds derived from the requested one, so retries are reproducible too
+        let mut seeds = ChaCha8Rng::seed_from_u64(seed);
+        let mut attempt_seed = seed;
+        let mut attempts = 0;
+        let mut backtracks = 0;
+        let mut check_failures = 0;
+        while attempts < self.policy.max_attempts {
+            if attempts > 0 {
+                attempt_seed = seeds.gen();
+            }
+            attempts += 1;
+            self.observer.borrow_mut().on_event(&AllocationEvent::AttemptStarted {
+                attempt: attempts,
+                seed: attempt_seed,
+            });
+            let mut rng = ChaCha8Rng::seed_from_u64(attempt_seed);
+            let fixed = match &self.plando {
+                Some(plando) => plando.choose(&mut rng)?,
+                None => self.logic.fixed.clone(),
+            };
+            let mut allocator = Allocator::new(self.logic, self.item_pool.clone(), fixed, self.settings)?;
+            allocator.set_observer(Box::new(self.observer.clone()));
+            allocator.set_backtracking(self.policy.backtrack, self.policy.max_attempts - attempts);
+            let result = allocator.allocate(&mut rng);
+            attempts += allocator.backtracks();
+            backtracks += allocator.backtracks();
+            match result {
+                Ok(assignments) => {
+                    let report = self.checker.check_assignments(&assignments);
+                    if report.satisfies(self.settings.accessibility) {
+                        return Ok(Generated {
+                            seed,
+                            attempt_seed,
+                            attempts,
+                            backtracks,
+                            check_failures,
+                            settings: self.settings,
+                            assignments,
+                            report,
+                        });
+                    }
+                    check_failures += 1;
+                    self.observer.borrow_mut().on_event(&AllocationEvent::CheckFailed {
+                        unreachable_locations: report.unreachable_locations.clone(),
+                        goal_reachable: report.goal_reachable,
+                    });
+                }
+                Err(RandomizerError::AllocationFailed { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(RandomizerError::AttemptsExhausted { attempts, backtracks, check_failures })
+    }
+
+    /// Generates every seed, spread over a pool of threads that share the logic. Each result is
+    /// what [`Generator::generate`] gives for that seed, in the order of `seeds`; the observer
+    /// sees none of them.
+    pub fn generate_parallel(&self, seeds: &[u64]) -> Vec<Result<Generated, RandomizerError>> {
+        let threads = self
+            .threads
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
+            .min(seeds.len());
+        let (logic, item_pool, settings, policy) = (self.logic, self.item_pool, self.settings, self.policy);
+        let (plando, checker) = (&self.plando, &self.checker);
+        // Seeds are handed out one at a time, so a thread stuck on a slow one doesn't hold up the rest
+        let next = AtomicUsize::new(0);
+        let next = &next;
+        let mut results: Vec<_> = seeds.iter().map(|_| None).collect();
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(move || {
+                        let mut generator = Generator::with_checker(logic, item_pool, settings, checker.clone())
+                            .with_policy(policy);
+                        generator.plando = plando.clone();
+                        let mut generated = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            match seeds.get(i) {
+                                Some(&seed) => generated.push((i, generator.generate(seed))),
+                                None => return generated,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                for (i, result) in worker.join().expect("Worker thread panicked") {
+                    results[i] = Some(result);
+                }
+            }
+        });
+        results
+            .into_iter()
+            .map(|result| result.expect("Every seed is handed out"))
+            .collect()
+    }
+}
+
+#[test]
+fn parallel_generation_matches_sequential() {
+    use crate::LogicLoader;
+    use crate::AssignmentChecker;
+    fn shareable<T: Send + Sync>(_: &T) {}
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Rupee
+    count: 3
+locations:
+  - name: Start
+  - name: Chest
+  - name: Field
+    requirements: Sword
+  - name: Cave
+    requirements: Sword, Bow
+  - name: Tower
+    requirements: (|Bow, Rupee*2)
+";
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    shareable(&logic);
+    // The compiled requirements are shared, rather than compiled again by each thread
+    shareable(&AssignmentChecker::from_logic(&logic));
+    let settings = AllocatorSettings::default();
+    let policy = RetryPolicy { max_attempts: 5, backtrack: 0 };
+    let generator = Generator::new(&logic, &pool, settings).with_policy(policy);
+    let seeds: Vec<u64> = (0..12).collect();
+    let parallel = generator.with_threads(NonZeroUsize::new(3).unwrap()).generate_parallel(&seeds);
+    let generator = Generator::new(&logic, &pool, settings).with_policy(policy);
+    assert_eq!(parallel.len(), seeds.len());
+    for (&seed, result) in seeds.iter().zip(parallel) {
+        let generated = result.unwrap();
+        assert_eq!(generated.seed, seed);
+        assert_eq!(generated.assignments, generator.generate(seed).unwrap().assignments);
+    }
+}
+
+#[test]
+fn conditions_are_freed_between_seeds() {
+    use crate::{logic::ConditionNode, LogicLoader};
+    use std::collections::HashSet;
+    let (prelogic, pool) = LogicLoader::from_reader(include_str!("../data.yaml").as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    // Other tests build their own items, so only count conditions made of these
+    let items: HashSet<_> = logic.items.iter().map(|(item, _)| Arc::as_ptr(item)).collect();
+    fn mentions(node: &ConditionNode, items: &HashSet<*const ItemDef>) -> bool {
+        match node {
+            ConditionNode::NoRequirements | ConditionNode::Unattainable => false,
+            ConditionNode::Item(item, _) => items.contains(&Arc::as_ptr(item)),
+            ConditionNode::AtLeast(_, weights) => weights.iter().any(|(item, _)| items.contains(&Arc::as_ptr(item))),
+            ConditionNode::And(conds) | ConditionNode::Or(conds) => conds.iter().any(|cond| mentions(cond, items)),
+        }
+    }
+    let live = || ItemCondition::live_nodes(|node| mentions(node, &items));
+    let policy = RetryPolicy { max_attempts: 5, backtrack: 0 };
+    let generator = Generator::new(&logic, &pool, AllocatorSettings::default()).with_policy(policy);
+    // Building leaves what it worked out in the memo
+    ItemCondition::clear_memo();
+    let built = live();
+    for seed in 0..3 {
+        generator.generate(seed).unwrap();
+    }
+    assert_eq!(live(), built);
+    for seed in 3..12 {
+        generator.generate(seed).unwrap();
+    }
+    assert_eq!(live(), built);
+}
+
+/// Run with `cargo test --release -- --ignored throughput` to see how many seeds a second
+/// each algorithm manages on a full logic
+#[test]
+#[ignore]
+fn throughput() {
+    use crate::{logic::Algorithm, LogicLoader};
+    use std::time::Instant;
+    let (prelogic, pool) = LogicLoader::from_reader(include_str!("../alttp.yml").as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    for algorithm in [Algorithm::Forward, Algorithm::Assumed] {
+        let settings = AllocatorSettings { algorithm, ..Default::default() };
+        let generator = Generator::new(&logic, &pool, settings);
+        let start = Instant::now();
+        let generated = (0..100).filter(|&seed| generator.generate(seed).is_ok()).count();
+        let elapsed = start.elapsed().as_secs_f64();
+        eprintln!("{:?}: {} of 100 seeds, {:.0} seeds/s", algorithm, generated, 100.0 / elapsed);
+        assert!(generated > 0);
+    }
+}
+
+#[test]
+fn seeds_pin_the_assignment() {
+    use crate::LogicLoader;
+    let (prelogic, pool) = LogicLoader::from_reader(include_str!("../data.yaml").as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let policy = RetryPolicy { max_attempts: 5, backtrack: 0 };
+    let generator = Generator::new(&logic, &pool, AllocatorSettings::default()).with_policy(policy);
+    let generated = generator.generate(1).unwrap();
+    // The first attempt fails, so this pins the derived seeds as well
+    assert_eq!(generated.attempts, 2);
+    // FNV-1a over every placement, so any change to what a seed produces shows up here. If
+    // the change was meant to move items, it breaks everyone's shared seeds; update the
+    // number only then
+    let fingerprint = generated.assignments.iter().fold(0xcbf29ce484222325u64, |hash, (location, item)| {
+        format!("{}: {}\n", location.name, item.name)
+            .bytes()
+            .fold(hash, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    });
+    assert_eq!(generated.assignments.len(), 265);
+    assert_eq!(fingerprint, 0xd6d7b8835e3498f2);
+}
+
+#[test]
+fn retries_restart_or_backtrack() {
+    use crate::LogicLoader;
+    let (prelogic, pool) = LogicLoader::from_reader(include_str!("../data.yaml").as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let settings = AllocatorSettings::default();
+    let generate = |max_attempts, backtrack, seed| {
+        let policy = RetryPolicy { max_attempts, backtrack };
+        Generator::new(&logic, &pool, settings).with_policy(policy).generate(seed)
+    };
+    // The first attempt at this seed gets stuck
+    assert!(matches!(generate(1, 0, 1), Err(RandomizerError::AttemptsExhausted { attempts: 1, backtracks: 0, check_failures: 0 })));
+
+    // Restarting moves on to a seed derived from the requested one, which works by itself
+    let restarted = generate(10, 0, 1).unwrap();
+    assert_eq!((restarted.attempts, restarted.backtracks), (2, 0));
+    assert_ne!(restarted.attempt_seed, 1);
+    let again = generate(1, 0, restarted.attempt_seed).unwrap();
+    assert_eq!(again.attempts, 1);
+    assert_eq!(again.assignments, restarted.assignments);
+
+    // Backtracking keeps the seed and undoes placements instead, each counting as an attempt
+    let backtracked = generate(10, 3, 1).unwrap();
+    assert_eq!((backtracked.attempts, backtracked.backtracks), (2, 1));
+    assert_eq!(backtracked.attempt_seed, 1);
+    assert_ne!(backtracked.assignments, restarted.assignments);
+}
+
+#[test]
+fn only_checked_assignments_are_returned() {
+    use crate::{logic::Accessibility, LogicLoader};
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Rupee
+    count: 2
+locations:
+  - name: Start
+  - name: Chest
+  - name: Field
+    requirements: Sword
+  - name: Cave
+    requirements: Sword, Bow
+goal: Bow
+";
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let checker = AssignmentChecker::from_logic(&logic);
+    for accessibility in [Accessibility::Full, Accessibility::Beatable, Accessibility::Minimal] {
+        let settings = AllocatorSettings { accessibility, ..Default::default() };
+        for seed in 0..10 {
+            let generated = Generator::new(&logic, &pool, settings).generate(seed).unwrap();
+            assert!(checker.check_assignments(&generated.assignments).satisfies(accessibility));
+        }
+    }
+
+    // Allocations that the checker turns down are retried, and never handed out
+    let strict = Arc::new(AssignmentChecker::from_logic(&logic).with_goal(ItemCondition::unattainable()));
+    let policy = RetryPolicy { max_attempts: 3, backtrack: 0 };
+    let generator = Generator::with_checker(&logic, &pool, AllocatorSettings::default(), strict).with_policy(policy);
+    for seed in 0..10 {
+        assert!(matches!(
+            generator.generate(seed),
+            Err(RandomizerError::AttemptsExhausted { attempts: 3, backtracks: 0, check_failures: 3 })
+        ));
+    }
+}
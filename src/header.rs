@@ -1,21 +1,21 @@
 use crate::RomVersion;
 
 pub struct HeaderData {
-    map: u32,
-    area: u32,
-    tile_offset: u32,
-    palette_set_table_loc: u32,
-    c0_table_loc: u32,
-    a1_c0_table_loc: u32,
-    c1_table_loc: u32,
-    c2_table_loc: u32,
-    swap_base: u32,
-    palette_change_base: u32,
-    area1_swap_base: u32,
-    global_tile_set_table_loc: u32,
-    gfx_source_base: u32,
-    global_meta_tile_set_table_loc: u32,
-    global_tile_data_table_loc: u32
+    pub map: u32,
+    pub area: u32,
+    pub tile_offset: u32,
+    pub palette_set_table_loc: u32,
+    pub c0_table_loc: u32,
+    pub a1_c0_table_loc: u32,
+    pub c1_table_loc: u32,
+    pub c2_table_loc: u32,
+    pub swap_base: u32,
+    pub palette_change_base: u32,
+    pub area1_swap_base: u32,
+    pub global_tile_set_table_loc: u32,
+    pub gfx_source_base: u32,
+    pub global_meta_tile_set_table_loc: u32,
+    pub global_tile_data_table_loc: u32,
 }
 
 pub const EU_HEADER_DATA: HeaderData = HeaderData {
@@ -0,0 +1,1327 @@
+//! Importer for the `.logic.txt` format of the original randomizer
+//!
+//! The format is documented at the top of `default.logic.txt`. Its preprocessor is evaluated
+//! for one choice of settings, so an import is the logic for that choice only.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+};
+
+use rand::{seq::SliceRandom, Rng};
+use thiserror::Error;
+
+use crate::{
+    logic::{
+        AllocatorSettings, FlagData, ItemCategory, ItemCount, ItemData, ItemId, LocationData,
+        LogicData,
+    },
+    logic_parse::{parse_reqs, Bound, LogicParseError, LogicParseErrorKind, Span, Term},
+    Allocator, LogicLoader, PreLogic, Preset, RandomizerError,
+};
+
+/// A problem with a line of a legacy logic file
+#[derive(Debug, Error)]
+#[error("{kind} at line {line}")]
+pub struct LegacyError {
+    pub kind: LegacyErrorKind,
+    /// 1-based line in the logic file
+    pub line: usize,
+}
+
+#[derive(Debug, Error)]
+pub enum LegacyErrorKind {
+    #[error("Unknown directive !{0}")]
+    UnknownDirective(String),
+    #[error("Missing {0}")]
+    MissingArgument(&'static str),
+    #[error("Invalid number {0}")]
+    InvalidNumber(String),
+    #[error("`{0}` is not defined")]
+    UndefinedDefine(String),
+    #[error("Unclosed '`'")]
+    UnclosedBacktick,
+    #[error("!else without an open !ifdef or !ifndef")]
+    UnmatchedElse,
+    #[error("!endif without an open !ifdef or !ifndef")]
+    UnmatchedEndif,
+    #[error("!ifdef or !ifndef is never closed by !endif")]
+    UnclosedIf,
+    #[error("Expected a name and a location type: {0}")]
+    MalformedLocation(String),
+    #[error("Unknown location type {0}")]
+    UnknownLocationType(String),
+    #[error("Location {0} needs an address")]
+    MissingAddress(String),
+    #[error("Invalid address {0}")]
+    InvalidAddress(String),
+    #[error("Invalid item {0}")]
+    InvalidItem(String),
+    #[error("Invalid value {value} for setting {name}; expected {expected}")]
+    InvalidSettingValue {
+        name: String,
+        value: String,
+        expected: Box<str>,
+    },
+    #[error("{0} is defined more than once")]
+    DuplicateName(String),
+    #[error("Item {0} would be restricted to more than one dungeon")]
+    ConflictingRestriction(String),
+}
+
+/// Logic read from a legacy file, for one choice of settings
+#[derive(Debug, Clone)]
+pub struct LegacyImport {
+    /// From `!name`
+    pub name: Option<String>,
+    /// From `!version`
+    pub version: Option<String>,
+    /// Value of every setting the file declares, after applying the preset
+    pub settings: BTreeMap<String, String>,
+    /// `!eventdefine`s, which configure the ROM patch rather than the logic
+    pub events: Vec<(String, String)>,
+    data: LogicData,
+}
+
+impl LegacyImport {
+    /// Reads a legacy logic file. `settings` gives values for its `!flag`, `!dropdown`,
+    /// `!numberbox` and `!color` settings. The format can't say what beats the game, so
+    /// `goal` is a requirement in the legacy syntax. `rng` picks the items of `!replace`.
+    ///
+    /// Locations without an item override keep whatever the ROM has there. The importer
+    /// doesn't read ROMs, so those items aren't in the pool; items that requirements mention
+    /// but no override provides are declared with a count of 0.
+    pub fn from_source<R: Rng + ?Sized>(
+        source: &str,
+        settings: &Preset,
+        goal: &str,
+        rng: &mut R,
+    ) -> Result<Self, RandomizerError> {
+        let mut pre = Preprocessor::new(settings);
+        pre.defines
+            .insert("RAND_INT".into(), format!("{:08X}", rng.gen::<u32>()));
+        for (idx, line) in source.lines().enumerate() {
+            pre.line(idx + 1, line).map_err(|kind| LegacyError {
+                kind,
+                line: idx + 1,
+            })?;
+        }
+        if let Some(branch) = pre.branches.last() {
+            return Err(LegacyError {
+                kind: LegacyErrorKind::UnclosedIf,
+                line: branch.line,
+            }
+            .into());
+        }
+        if let Some(name) = settings
+            .parameters
+            .keys()
+            .find(|name| !pre.settings.contains_key(*name))
+        {
+            return Err(RandomizerError::UnknownParameter(name.clone()));
+        }
+
+        let entries = pre
+            .lines
+            .iter()
+            .map(|line| {
+                Entry::parse(line).map_err(|kind| LegacyError {
+                    kind,
+                    line: line.number,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut flags = HashSet::new();
+        let mut locations = HashSet::new();
+        for entry in &entries {
+            let name = entry.name.as_str();
+            if flags.contains(name) || locations.contains(name) {
+                let kind = LegacyErrorKind::DuplicateName(name.to_string());
+                return Err(LegacyError {
+                    kind,
+                    line: entry.line.number,
+                }
+                .into());
+            }
+            if entry.is_flag() {
+                flags.insert(name);
+            } else {
+                locations.insert(name);
+            }
+        }
+
+        let mut occurrences: Vec<_> = entries.iter().filter_map(Entry::occurrence).collect();
+        for (line, replacement) in &pre.replacements {
+            replacement
+                .apply(&mut occurrences, rng)
+                .map_err(|kind| LegacyError { kind, line: *line })?;
+        }
+        let mut pool = Pool::default();
+        for occurrence in &occurrences {
+            pool.add(
+                &occurrence.item,
+                occurrence.category,
+                occurrence.restriction.as_deref(),
+            )
+            .map_err(|kind| LegacyError {
+                kind,
+                line: occurrence.line,
+            })?;
+        }
+
+        let mut data = LogicData::default();
+        for entry in &entries {
+            let mut converter = Converter {
+                flags: &flags,
+                locations: &locations,
+                pool: &mut pool,
+            };
+            let requirements = converter.requirements(
+                entry.requirements,
+                Some((entry.line, entry.requirements_offset)),
+            );
+            if entry.is_flag() {
+                let requirements =
+                    requirements.map_err(|source| RandomizerError::FlagRequirement {
+                        name: entry.name.clone(),
+                        source: Box::new(source),
+                    })?;
+                data.flags.push(FlagData {
+                    name: entry.name.clone(),
+                    requirements,
+                });
+            } else {
+                let requirements =
+                    requirements.map_err(|source| RandomizerError::LocationRequirement {
+                        name: entry.name.clone(),
+                        source: Box::new(source),
+                    })?;
+                // Unshuffled locations keep their item, after any replacements
+                let vanilla = (entry.typ == LocationType::Unshuffled)
+                    .then(|| occurrences.iter().find(|o| o.line == entry.line.number))
+                    .flatten()
+                    .map(|o| o.item.yaml_name());
+                data.locations.push(LocationData {
+                    name: entry.name.clone(),
+                    category: Some(entry.category()),
+                    requirements,
+                    restriction: entry.restriction(),
+                    address: entry.address.clone(),
+                    fixed: vanilla.is_some().then_some(true),
+                    vanilla,
+                    ..Default::default()
+                });
+            }
+        }
+        let mut converter = Converter {
+            flags: &flags,
+            locations: &locations,
+            pool: &mut pool,
+        };
+        data.goal = converter
+            .requirements(goal, None)
+            .map_err(|source| RandomizerError::GoalRequirement(Box::new(source)))?;
+
+        for (pattern, category) in &pre.set_types {
+            pool.set_type(pattern, *category);
+        }
+        data.item_pool = pool.into_items();
+
+        Ok(LegacyImport {
+            name: pre.name,
+            version: pre.version,
+            settings: pre.settings,
+            events: pre.events,
+            data,
+        })
+    }
+
+    /// Writes the logic as YAML, which `LogicLoader` reads back
+    pub fn write_yaml(&self, mut writer: impl Write) -> Result<(), RandomizerError> {
+        match (&self.name, &self.version) {
+            (Some(name), Some(version)) => {
+                writeln!(writer, "# Imported from legacy logic {} {}", name, version)?
+            }
+            (Some(name), None) => writeln!(writer, "# Imported from legacy logic {}", name)?,
+            _ => writeln!(writer, "# Imported from legacy logic")?,
+        }
+        writeln!(writer, "# Settings:")?;
+        for (name, value) in &self.settings {
+            writeln!(writer, "#   {}: {}", name, value)?;
+        }
+        serde_yaml::to_writer(writer, &self.data)?;
+        Ok(())
+    }
+
+    /// Loads the imported logic the same way as a YAML logic file
+    pub fn load(&self) -> Result<(PreLogic, Vec<ItemId>), RandomizerError> {
+        let mut yaml = vec![];
+        self.write_yaml(&mut yaml)?;
+        LogicLoader::from_source(&String::from_utf8_lossy(&yaml), &Preset::default())
+    }
+
+    /// Checks that seeds can be generated from the imported logic with `settings`, the same
+    /// way the allocator does before placing anything. Items that only the ROM provides have
+    /// a count of 0, so a goal that needs them fails naming them.
+    pub fn check(&self, settings: AllocatorSettings) -> Result<(), RandomizerError> {
+        let (prelogic, pool) = self.load()?;
+        let (logic, pool) = prelogic.build(&pool);
+        if let Some(goal) = &logic.goal {
+            let provided: Vec<_> = (pool.iter().map(|(item, _)| item))
+                .chain(logic.fixed.iter().map(|(_, item)| item))
+                .collect();
+            let mut left = goal.clone();
+            for item in &provided {
+                left.assume_item(item, 1);
+            }
+            let mut needed = BTreeMap::new();
+            left.missing(&mut needed);
+            let rom_items: Vec<_> = needed
+                .into_keys()
+                .filter(|item| !provided.contains(item))
+                .map(|item| item.name.clone())
+                .collect();
+            if !rom_items.is_empty() {
+                return Err(RandomizerError::GoalNeedsRomItems(rom_items));
+            }
+        }
+        Allocator::new(&logic, pool, logic.fixed.clone(), settings).map(|_| ())
+    }
+}
+
+/// A line that survived the preprocessor, with comments removed and defines substituted
+struct Line {
+    number: usize,
+    text: String,
+}
+
+/// An open `!ifdef` or `!ifndef`
+struct Branch {
+    line: usize,
+    parent_active: bool,
+    condition: bool,
+    in_else: bool,
+}
+
+impl Branch {
+    fn active(&self) -> bool {
+        self.parent_active && self.condition != self.in_else
+    }
+}
+
+struct Preprocessor<'a> {
+    preset: &'a Preset,
+    defines: HashMap<String, String>,
+    branches: Vec<Branch>,
+    lines: Vec<Line>,
+    name: Option<String>,
+    version: Option<String>,
+    settings: BTreeMap<String, String>,
+    events: Vec<(String, String)>,
+    replacements: Vec<(usize, Replacement)>,
+    set_types: Vec<(LegacyItem, ItemCategory)>,
+}
+
+impl<'a> Preprocessor<'a> {
+    fn new(preset: &'a Preset) -> Self {
+        Preprocessor {
+            preset,
+            defines: HashMap::new(),
+            branches: vec![],
+            lines: vec![],
+            name: None,
+            version: None,
+            settings: BTreeMap::new(),
+            events: vec![],
+            replacements: vec![],
+            set_types: vec![],
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.branches.last().is_none_or(|branch| branch.active())
+    }
+
+    fn line(&mut self, number: usize, line: &str) -> Result<(), LegacyErrorKind> {
+        // Leading whitespace is kept so errors can point at the right column
+        let line = line.split('#').next().unwrap_or("").trim_end();
+        if line.trim_start().is_empty() {
+            return Ok(());
+        }
+        let Some(directive) = line.trim_start().strip_prefix('!') else {
+            if self.active() {
+                let text = self.substitute(line)?;
+                self.lines.push(Line { number, text });
+            }
+            return Ok(());
+        };
+        let name_end = directive
+            .find(|c: char| c == '-' || c.is_whitespace())
+            .unwrap_or(directive.len());
+        let (name, args) = directive.split_at(name_end);
+        match name {
+            "ifdef" | "ifndef" => {
+                let parent_active = self.active();
+                let define = split_args(args, 1).into_iter().next().unwrap_or("");
+                let condition = self.defines.contains_key(define) == (name == "ifdef");
+                self.branches.push(Branch {
+                    line: number,
+                    parent_active,
+                    condition,
+                    in_else: false,
+                });
+                return Ok(());
+            }
+            "else" => {
+                return match self.branches.last_mut() {
+                    Some(branch) if !branch.in_else => {
+                        branch.in_else = true;
+                        Ok(())
+                    }
+                    _ => Err(LegacyErrorKind::UnmatchedElse),
+                };
+            }
+            "endif" => {
+                return self
+                    .branches
+                    .pop()
+                    .map(|_| ())
+                    .ok_or(LegacyErrorKind::UnmatchedEndif)
+            }
+            _ if !self.active() => return Ok(()),
+            _ => {}
+        }
+
+        let args = self.substitute(args)?;
+        match name {
+            "define" | "eventdefine" => {
+                let args = split_args(&args, 2);
+                let define = required(&args, 0, "define name")?.to_string();
+                let value = args.get(1).copied().unwrap_or("").to_string();
+                if name == "define" {
+                    self.defines.insert(define, value);
+                } else {
+                    self.events.push((define, value));
+                }
+            }
+            "undefine" => {
+                let args = split_args(&args, 1);
+                self.defines.remove(required(&args, 0, "define name")?);
+            }
+            "addition" => {
+                let args = split_args(&args, 2);
+                let define = required(&args, 0, "define name")?;
+                let mut total = 0;
+                for value in required(&args, 1, "values")?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                {
+                    total += parse_number(value)?;
+                }
+                self.defines.insert(define.to_string(), total.to_string());
+            }
+            "flag" => {
+                let args = split_args(&args, 4);
+                let define = required(&args, 1, "define name")?;
+                let default = args.get(3).is_some_and(|d| d.eq_ignore_ascii_case("true"));
+                let value = match self.preset.parameters.get(define) {
+                    Some(value) if value.eq_ignore_ascii_case("true") => true,
+                    Some(value) if value.eq_ignore_ascii_case("false") => false,
+                    Some(value) => return Err(invalid_setting(define, value, "True or False")),
+                    None => default,
+                };
+                if value {
+                    self.defines.insert(define.to_string(), String::new());
+                }
+                self.settings.insert(define.to_string(), value.to_string());
+            }
+            "dropdown" => {
+                let args = split_args(&args, usize::MAX);
+                let define = required(&args, 1, "define name")?;
+                // Pairs of readable text and the value the define gets
+                let options: Vec<_> = args[2..]
+                    .chunks(2)
+                    .filter_map(|pair| Some((pair[0], *pair.get(1)?)))
+                    .collect();
+                let (_, default) = options
+                    .first()
+                    .ok_or(LegacyErrorKind::MissingArgument("dropdown options"))?;
+                let value = match self.preset.parameters.get(define) {
+                    Some(value) => options
+                        .iter()
+                        .find(|(text, option)| option == value || text == value)
+                        .map(|(_, option)| option)
+                        .ok_or_else(|| {
+                            let values: Vec<_> =
+                                options.iter().map(|(_, option)| *option).collect();
+                            invalid_setting(define, value, &format!("one of {}", values.join(", ")))
+                        })?,
+                    None => default,
+                };
+                self.defines.insert(define.to_string(), value.to_string());
+                self.settings.insert(define.to_string(), value.to_string());
+            }
+            "numberbox" => {
+                let args = split_args(&args, 3);
+                let define = required(&args, 1, "define name")?;
+                let value = match self.preset.parameters.get(define) {
+                    Some(value) => parse_number(value)
+                        .map_err(|_| invalid_setting(define, value, "a number"))?,
+                    None => 0,
+                };
+                self.defines.insert(define.to_string(), value.to_string());
+                self.settings.insert(define.to_string(), value.to_string());
+            }
+            "color" => {
+                let args = split_args(&args, usize::MAX);
+                let define = required(&args, 1, "define name")?;
+                let components = match self.preset.parameters.get(define) {
+                    Some(value) => {
+                        let components = value
+                            .split(',')
+                            .map(parse_number)
+                            .collect::<Result<Vec<_>, _>>()
+                            .ok()
+                            .filter(|c| {
+                                !c.is_empty() && c.len() % 3 == 0 && c.iter().all(|&c| c <= 0xFF)
+                            })
+                            .ok_or_else(|| {
+                                invalid_setting(
+                                    define,
+                                    value,
+                                    "red, green and blue components from 0 to 0xFF",
+                                )
+                            })?;
+                        // The define itself is only set when the colour is changed
+                        self.defines.insert(define.to_string(), String::new());
+                        self.settings.insert(define.to_string(), value.clone());
+                        components
+                    }
+                    None => {
+                        let components = args
+                            .get(3..)
+                            .unwrap_or(&[])
+                            .iter()
+                            .map(|c| parse_number(c))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        self.settings
+                            .insert(define.to_string(), "default".to_string());
+                        if components.len() < 3 {
+                            vec![0xFF; 3]
+                        } else {
+                            components
+                        }
+                    }
+                };
+                for (idx, rgb) in components.chunks_exact(3).enumerate() {
+                    // GBA colours are 15 bit BGR
+                    let color = (rgb[0] >> 3) | (rgb[1] >> 3) << 5 | (rgb[2] >> 3) << 10;
+                    self.defines
+                        .insert(format!("{}_{}", define, idx), format!("{:X}", color));
+                }
+            }
+            "replace" => {
+                let args = split_args(&args, 2);
+                let item = LegacyItem::parse(required(&args, 0, "item")?)?.0;
+                let choices = parse_item_list(required(&args, 1, "chance set")?)?
+                    .into_iter()
+                    .map(|(item, weight)| (item, weight.unwrap_or(1)))
+                    .collect::<Vec<_>>();
+                if choices.iter().all(|(_, weight)| *weight == 0) {
+                    return Err(LegacyErrorKind::MissingArgument("chance set"));
+                }
+                self.replacements
+                    .push((number, Replacement::Random { item, choices }));
+            }
+            "replaceamount" | "replaceincrement" => {
+                let args = split_args(&args, 3);
+                let item = LegacyItem::parse(required(&args, 0, "item")?)?.0;
+                let amount = parse_number(required(&args, 1, "amount")?)?;
+                let targets = parse_item_list(required(&args, 2, "items to replace")?)?
+                    .into_iter()
+                    .map(|(item, _)| item)
+                    .collect();
+                let increment = name == "replaceincrement";
+                self.replacements.push((
+                    number,
+                    Replacement::First {
+                        item,
+                        amount,
+                        targets,
+                        increment,
+                    },
+                ));
+            }
+            "settype" => {
+                let args = split_args(&args, 2);
+                let item = LegacyItem::parse(required(&args, 0, "item")?)?.0;
+                let category = match LocationType::parse(required(&args, 1, "location type")?)? {
+                    LocationType::Shuffled(category) => category,
+                    _ => return Err(LegacyErrorKind::UnknownLocationType(args[1].to_string())),
+                };
+                self.set_types.push((item, category));
+            }
+            "name" => self.name = split_args(&args, 1).first().map(|s| s.to_string()),
+            "version" => self.version = split_args(&args, 1).first().map(|s| s.to_string()),
+            // Checksum of the ROM the logic was written for
+            "crc" => {}
+            _ => return Err(LegacyErrorKind::UnknownDirective(name.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Replaces every `` `NAME` `` with the value of the define
+    fn substitute(&self, s: &str) -> Result<String, LegacyErrorKind> {
+        let mut out = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('`') {
+            out.push_str(&rest[..start]);
+            let end = rest[start + 1..]
+                .find('`')
+                .ok_or(LegacyErrorKind::UnclosedBacktick)?;
+            let define = &rest[start + 1..start + 1 + end];
+            let value = self
+                .defines
+                .get(define)
+                .ok_or_else(|| LegacyErrorKind::UndefinedDefine(define.to_string()))?;
+            out.push_str(value);
+            rest = &rest[start + end + 2..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+/// Splits the ` - ` separated arguments of a directive; the last one gets whatever is left
+fn split_args(args: &str, max: usize) -> Vec<&str> {
+    let args = args.trim_start();
+    match args.strip_prefix('-') {
+        Some(args) => args.splitn(max, '-').map(str::trim).collect(),
+        None => vec![],
+    }
+}
+
+fn required<'s>(
+    args: &[&'s str],
+    idx: usize,
+    what: &'static str,
+) -> Result<&'s str, LegacyErrorKind> {
+    args.get(idx)
+        .copied()
+        .filter(|arg| !arg.is_empty())
+        .ok_or(LegacyErrorKind::MissingArgument(what))
+}
+
+fn invalid_setting(name: &str, value: &str, expected: &str) -> LegacyErrorKind {
+    LegacyErrorKind::InvalidSettingValue {
+        name: name.to_string(),
+        value: value.to_string(),
+        expected: expected.into(),
+    }
+}
+
+/// Parses decimal or `0x` prefixed hexadecimal numbers
+fn parse_number(s: &str) -> Result<u32, LegacyErrorKind> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| LegacyErrorKind::InvalidNumber(s.to_string()))
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    let s = s.trim();
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    u32::from_str_radix(hex, 16).ok()
+}
+
+/// Parses a `,` separated list of items, which may end with `;`
+fn parse_item_list(s: &str) -> Result<Vec<(LegacyItem, Option<u32>)>, LegacyErrorKind> {
+    s.trim_end_matches(|c: char| c == ';' || c.is_whitespace())
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(LegacyItem::parse)
+        .collect()
+}
+
+/// An item written as `Items.(type).[subtype]:[dungeon id]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LegacyItem {
+    /// `type.subtype`; a subtype of `*` matches every subtype
+    name: String,
+    dungeon: String,
+}
+
+impl LegacyItem {
+    /// Parses an item and its amount; `:[amount]` follows the dungeon id
+    fn parse(s: &str) -> Result<(Self, Option<u32>), LegacyErrorKind> {
+        let invalid = || LegacyErrorKind::InvalidItem(s.to_string());
+        let mut parts = s
+            .trim()
+            .strip_prefix("Items.")
+            .ok_or_else(invalid)?
+            .split(':');
+        let name = parts
+            .next()
+            .filter(|name| !name.is_empty())
+            .ok_or_else(invalid)?;
+        let mut dungeon = parts.next().unwrap_or("").trim();
+        let mut amount = match parts.next().map(str::trim) {
+            Some(amount) if !amount.is_empty() => Some(parse_number(amount)?),
+            _ => None,
+        };
+        // Dungeon ids are names, so `Items.SmithSword:3` is three swords
+        if let (None, Ok(n)) = (amount, parse_number(dungeon)) {
+            amount = Some(n);
+            dungeon = "";
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        // Requirements can't contain '*', so wildcards are written as '-' there
+        let name = match name.strip_suffix(".-") {
+            Some(prefix) => format!("{}.*", prefix),
+            None => name.to_string(),
+        };
+        Ok((
+            LegacyItem {
+                name,
+                dungeon: dungeon.to_string(),
+            },
+            amount,
+        ))
+    }
+
+    /// The name of the item in YAML logic
+    fn yaml_name(&self) -> String {
+        if self.dungeon.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}:{}", self.name, self.dungeon)
+        }
+    }
+
+    fn is_wildcard(&self) -> bool {
+        self.name.ends_with(".*")
+    }
+
+    fn matches(&self, other: &LegacyItem) -> bool {
+        match self.name.strip_suffix('*') {
+            Some(prefix) => other.name.starts_with(prefix) && self.dungeon == other.dungeon,
+            None => self == other,
+        }
+    }
+
+    /// The item with the next subtype, keeping the subtype's width: `KinstoneBag.09` is
+    /// followed by `KinstoneBag.0A`
+    fn increment(&self) -> Result<Self, LegacyErrorKind> {
+        let invalid = || LegacyErrorKind::InvalidItem(self.name.clone());
+        let (typ, subtype) = self.name.rsplit_once('.').ok_or_else(invalid)?;
+        let next = parse_hex(subtype).ok_or_else(invalid)? + 1;
+        Ok(LegacyItem {
+            name: format!("{}.{:0width$X}", typ, next, width = subtype.len()),
+            dungeon: self.dungeon.clone(),
+        })
+    }
+}
+
+/// One of the items placed by the logic file
+struct Occurrence {
+    line: usize,
+    item: LegacyItem,
+    category: ItemCategory,
+    restriction: Option<String>,
+}
+
+enum Replacement {
+    /// `!replace`: every occurrence of `item` becomes a random item from `choices`
+    Random {
+        item: LegacyItem,
+        choices: Vec<(LegacyItem, u32)>,
+    },
+    /// `!replaceamount` and `!replaceincrement`: the first `amount` occurrences of any of
+    /// `targets` become `item`, whose subtype goes up with each replacement if `increment`
+    First {
+        item: LegacyItem,
+        amount: u32,
+        targets: Vec<LegacyItem>,
+        increment: bool,
+    },
+}
+
+impl Replacement {
+    fn apply<R: Rng + ?Sized>(
+        &self,
+        occurrences: &mut [Occurrence],
+        rng: &mut R,
+    ) -> Result<(), LegacyErrorKind> {
+        match self {
+            Replacement::Random { item, choices } => {
+                for occurrence in occurrences.iter_mut().filter(|o| item.matches(&o.item)) {
+                    let (choice, _) = choices
+                        .choose_weighted(rng, |(_, weight)| *weight)
+                        .expect("Chance sets are checked to have some weight");
+                    occurrence.item = choice.clone();
+                }
+            }
+            Replacement::First {
+                item,
+                amount,
+                targets,
+                increment,
+            } => {
+                let mut next = item.clone();
+                let matching = occurrences
+                    .iter_mut()
+                    .filter(|o| targets.iter().any(|t| t.matches(&o.item)));
+                for occurrence in matching.take(*amount as usize) {
+                    occurrence.item = next.clone();
+                    if *increment {
+                        next = next.increment()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LocationType {
+    Helper,
+    /// The location's item stays where it is
+    Unshuffled,
+    Shuffled(ItemCategory),
+}
+
+impl LocationType {
+    fn parse(s: &str) -> Result<Self, LegacyErrorKind> {
+        Ok(match s {
+            "Helper" => LocationType::Helper,
+            "Unshuffled" => LocationType::Unshuffled,
+            "Major" => LocationType::Shuffled(ItemCategory::Major),
+            "Minor" => LocationType::Shuffled(ItemCategory::Minor),
+            "DungeonItem" => LocationType::Shuffled(ItemCategory::DungeonItem),
+            _ => return Err(LegacyErrorKind::UnknownLocationType(s.to_string())),
+        })
+    }
+}
+
+/// A helper or location line: `(name):[dungeon id]; (type); (address); [logic]; [item override]`
+struct Entry<'l> {
+    line: &'l Line,
+    name: String,
+    dungeon: String,
+    typ: LocationType,
+    address: Option<String>,
+    requirements: &'l str,
+    /// Byte offset of the requirements in the line
+    requirements_offset: usize,
+    item: Option<LegacyItem>,
+}
+
+impl<'l> Entry<'l> {
+    fn parse(line: &'l Line) -> Result<Self, LegacyErrorKind> {
+        let mut fields = vec![];
+        let mut start = 0;
+        for field in line.text.split(';') {
+            fields.push((start, field));
+            start += field.len() + 1;
+        }
+        let field = |idx: usize| fields.get(idx).map_or("", |(_, f)| f.trim());
+        let malformed = || LegacyErrorKind::MalformedLocation(line.text.clone());
+        let (name, dungeon) = field(0).split_once(':').unwrap_or((field(0), ""));
+        if name.is_empty() || fields.len() < 2 {
+            return Err(malformed());
+        }
+        let typ = LocationType::parse(field(1))?;
+        let address = match (field(2), typ) {
+            ("", LocationType::Helper) => None,
+            ("", _) => return Err(LegacyErrorKind::MissingAddress(name.to_string())),
+            (address, _) => Some(
+                parse_address(address)
+                    .ok_or_else(|| LegacyErrorKind::InvalidAddress(address.to_string()))?,
+            ),
+        };
+        let (requirements_offset, requirements) =
+            fields.get(3).copied().unwrap_or((line.text.len(), ""));
+        let item = match field(4) {
+            "" => None,
+            item => Some(LegacyItem::parse(item)?.0),
+        };
+        if fields.len() > 5 {
+            return Err(malformed());
+        }
+        Ok(Entry {
+            line,
+            name: name.trim().to_string(),
+            dungeon: dungeon.trim().to_string(),
+            typ,
+            address,
+            requirements,
+            requirements_offset,
+            item,
+        })
+    }
+
+    /// Helpers become flags, and so do unshuffled locations without an item, since nothing
+    /// is ever placed there
+    fn is_flag(&self) -> bool {
+        match self.typ {
+            LocationType::Helper => true,
+            LocationType::Unshuffled => self.item.is_none(),
+            LocationType::Shuffled(_) => false,
+        }
+    }
+
+    fn category(&self) -> ItemCategory {
+        match self.typ {
+            LocationType::Shuffled(category) => category,
+            _ => ItemCategory::Major,
+        }
+    }
+
+    fn restriction(&self) -> Option<String> {
+        Some(self.dungeon.clone()).filter(|d| !d.is_empty())
+    }
+
+    fn occurrence(&self) -> Option<Occurrence> {
+        if self.typ == LocationType::Helper {
+            return None;
+        }
+        // Dungeon items stay in their dungeon
+        let restriction = match self.typ {
+            LocationType::Shuffled(ItemCategory::DungeonItem) => self.restriction(),
+            _ => None,
+        };
+        Some(Occurrence {
+            line: self.line.number,
+            item: self.item.clone()?,
+            category: self.category(),
+            restriction,
+        })
+    }
+}
+
+/// Checks an address and tidies its whitespace. Addresses are `area-room-chest`, a ROM
+/// offset, or two `(offset or name:Define):FirstByte`/`:SecondByte` halves.
+fn parse_address(s: &str) -> Option<String> {
+    if let Some((first, second)) = s.split_once(',') {
+        let half = |s: &str, byte: &str| {
+            let target = s.trim().strip_suffix(byte)?.strip_suffix(':')?;
+            let valid = match target.strip_suffix(":Define") {
+                Some(name) => {
+                    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+                }
+                None => parse_hex(target).is_some(),
+            };
+            valid.then(|| format!("{}:{}", target, byte))
+        };
+        Some(format!(
+            "{}, {}",
+            half(first, "FirstByte")?,
+            half(second, "SecondByte")?
+        ))
+    } else if s.contains('-') {
+        let parts: Vec<_> = s.split('-').map(str::trim).collect();
+        let valid = parts.len() == 3
+            && parts
+                .iter()
+                .all(|p| parse_hex(p).is_some_and(|n| n <= 0xFF));
+        valid.then(|| parts.join("-"))
+    } else {
+        parse_hex(s).map(|_| s.trim().to_string())
+    }
+}
+
+/// The item pool, in the order items first appear
+#[derive(Default)]
+struct Pool {
+    items: Vec<(LegacyItem, ItemData)>,
+    index: HashMap<String, usize>,
+}
+
+impl Pool {
+    fn entry(&mut self, item: &LegacyItem, category: ItemCategory) -> &mut ItemData {
+        let name = item.yaml_name();
+        let idx = *self.index.entry(name.clone()).or_insert_with(|| {
+            let restriction = Some(item.dungeon.clone()).filter(|d| !d.is_empty());
+            self.items.push((
+                item.clone(),
+                ItemData {
+                    name,
+                    category: Some(category),
+                    restriction,
+                    count: Some(ItemCount::Fixed(0)),
+                    ..Default::default()
+                },
+            ));
+            self.items.len() - 1
+        });
+        &mut self.items[idx].1
+    }
+
+    fn add(
+        &mut self,
+        item: &LegacyItem,
+        category: ItemCategory,
+        restriction: Option<&str>,
+    ) -> Result<(), LegacyErrorKind> {
+        let is_new = !self.index.contains_key(&item.yaml_name());
+        let entry = self.entry(item, category);
+        // The item's own dungeon id takes precedence over the location's
+        if item.dungeon.is_empty() {
+            match (is_new, entry.restriction.as_deref(), restriction) {
+                (true, _, Some(restriction)) => entry.restriction = Some(restriction.to_string()),
+                (false, a, b) if a != b => {
+                    return Err(LegacyErrorKind::ConflictingRestriction(entry.name.clone()))
+                }
+                _ => {}
+            }
+        }
+        // An item that's progression anywhere is progression everywhere
+        if category == ItemCategory::Major {
+            entry.category = Some(ItemCategory::Major);
+        }
+        if let Some(ItemCount::Fixed(count)) = &mut entry.count {
+            *count += 1;
+        }
+        Ok(())
+    }
+
+    /// Declares an item that requirements refer to, if nothing places it
+    fn reference(&mut self, item: &LegacyItem) {
+        self.entry(item, ItemCategory::Major);
+    }
+
+    fn matching(&self, pattern: &LegacyItem) -> Vec<String> {
+        self.items
+            .iter()
+            .filter(|(item, _)| pattern.matches(item))
+            .map(|(_, data)| data.name.clone())
+            .collect()
+    }
+
+    fn set_type(&mut self, pattern: &LegacyItem, category: ItemCategory) {
+        for (item, data) in &mut self.items {
+            if pattern.matches(item) {
+                data.category = Some(category);
+            }
+        }
+    }
+
+    fn into_items(self) -> Vec<ItemData> {
+        self.items
+            .into_iter()
+            .map(|(_, mut data)| {
+                if data.category == Some(ItemCategory::Major) {
+                    data.show_in_graph = Some(true);
+                }
+                data
+            })
+            .collect()
+    }
+}
+
+type TermError = (LogicParseErrorKind, Span);
+
+/// Rewrites legacy requirements in the syntax of YAML logic
+struct Converter<'a> {
+    flags: &'a HashSet<&'a str>,
+    locations: &'a HashSet<&'a str>,
+    pool: &'a mut Pool,
+}
+
+impl Converter<'_> {
+    /// Converts `requirements`. If they start at some byte offset of a line, errors point
+    /// into that line.
+    fn requirements(
+        &mut self,
+        requirements: &str,
+        line: Option<(&Line, usize)>,
+    ) -> Result<Option<String>, LogicParseError> {
+        // '*' is the count operator, so wildcard subtypes are swapped for a character that
+        // can't start a subtype. Both are one byte, which keeps the spans intact.
+        let expression = requirements.replace(".*", ".-");
+        let place = |e: LogicParseError| match line {
+            Some((line, offset)) => {
+                let column = line.text[..offset].chars().count() + e.column;
+                e.at(line.number, column, &line.text)
+            }
+            None => e,
+        };
+        let terms = parse_reqs(&expression).map_err(place)?;
+        if terms.is_empty() {
+            return Ok(None);
+        }
+        let converted = terms
+            .iter()
+            .map(|term| self.term(term, false))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|(kind, span)| place(LogicParseError::new(kind, &expression, span)))?;
+        Ok(Some(converted.join(", ")))
+    }
+
+    fn term(&mut self, term: &Term, counted: bool) -> Result<String, TermError> {
+        let join = |this: &mut Self, terms: &[Term], counted| {
+            terms
+                .iter()
+                .map(|term| this.term(term, counted))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|terms| terms.join(", "))
+        };
+        Ok(match term {
+            Term::Lit {
+                name,
+                count: None,
+                span,
+            } => match name.split_once('.') {
+                Some(("Items", _)) => {
+                    let unknown = || {
+                        (
+                            LogicParseErrorKind::UnrecognizedItem(name.to_string()),
+                            span.clone(),
+                        )
+                    };
+                    let (item, amount) = LegacyItem::parse(name).map_err(|_| unknown())?;
+                    if item.is_wildcard() {
+                        let matching = self.pool.matching(&item);
+                        if matching.is_empty() {
+                            return Err(unknown());
+                        }
+                        if counted {
+                            return Err((LogicParseErrorKind::ThresholdRequireItems, span.clone()));
+                        }
+                        let items: Vec<_> = matching
+                            .iter()
+                            .map(|name| format!("Items.{}", name))
+                            .collect();
+                        format!("(+{}, {})", amount.unwrap_or(1), items.join(", "))
+                    } else {
+                        self.pool.reference(&item);
+                        match amount {
+                            Some(amount) if amount != 1 => {
+                                format!("Items.{}*{}", item.yaml_name(), amount)
+                            }
+                            _ => format!("Items.{}", item.yaml_name()),
+                        }
+                    }
+                }
+                // Helpers. and Locations. mean the same thing in legacy logic
+                Some(("Helpers" | "Locations", rest)) if !counted => {
+                    if self.flags.contains(rest) {
+                        format!("Helpers.{}", rest)
+                    } else if self.locations.contains(rest) {
+                        format!("Locations.{}", rest)
+                    } else {
+                        return Err((
+                            LogicParseErrorKind::UnrecognizedName(name.to_string()),
+                            span.clone(),
+                        ));
+                    }
+                }
+                _ if counted => {
+                    return Err((LogicParseErrorKind::ThresholdRequireItems, span.clone()))
+                }
+                _ => {
+                    return Err((
+                        LogicParseErrorKind::UnrecognizedName(name.to_string()),
+                        span.clone(),
+                    ))
+                }
+            },
+            Term::And(terms, _) => format!("(& {})", join(self, terms, false)?),
+            Term::Or(terms, _) => format!("(| {})", join(self, terms, false)?),
+            Term::Count(threshold, terms, _) => {
+                let threshold = match threshold {
+                    Bound::Number(n) => *n,
+                    Bound::Name(name, span) => parse_number(name).map_err(|_| {
+                        (
+                            LogicParseErrorKind::InvalidThreshold(name.to_string()),
+                            span.clone(),
+                        )
+                    })?,
+                };
+                format!("(+{}, {})", threshold, join(self, terms, true)?)
+            }
+            _ if counted => return Err((LogicParseErrorKind::ThresholdRequireItems, term.span())),
+            _ => {
+                let found = format!("'{}'", term);
+                return Err((
+                    LogicParseErrorKind::Expected {
+                        expected: "an item, helper or location",
+                        found,
+                    },
+                    term.span(),
+                ));
+            }
+        })
+    }
+}
+
+#[test]
+fn legacy_preprocessor_and_items() {
+    let source = "
+!name - Test
+!flag - Setting - KEYSANITY - Keysanity - false
+!dropdown - Setting - SWORDS - No swords - NOSWORDS - Swords - SWORDS_ON
+!define - `SWORDS`
+!ifdef - KEYSANITY
+    !define - KEY - .0x18:
+    !define - DUNGEON_TYPE - Minor
+!else
+    !define - KEY - :Deepwood
+    !define - DUNGEON_TYPE - DungeonItem
+!endif
+!define - SWAP - Items.Rupee5::1 ;
+!replace - Items.Rupee1 - `SWAP`
+!settype - Items.Rupee5 - Major
+
+HasSword;     Helper;; (|Items.SmithSword:2, Items.SmithSword.1)
+Start;        Major;   0x0F252B;                  ;                                 Items.SmithSword
+Chest;        Minor;   0x22-0x11-0x00;            Helpers.HasSword;                 Items.Rupee1
+Pedestal;     Unshuffled; 0x01:FirstByte, pedSub:Define:SecondByte; Items.SmithSword; Items.Shield
+Cave;         Unshuffled; 0x0DB55F;               Locations.Pedestal
+Door:Deepwood; `DUNGEON_TYPE`; 0x48-0x00-0x01;    Locations.Cave;                   Items.SmallKey`KEY`
+!ifdef - SWORDS_ON
+Boss:Deepwood; Helper;;                           Items.SmallKey`KEY`:2, Items.Shield
+!else
+Boss:Deepwood; Helper;;                           Items.SmallKey`KEY`, Items.Shield
+!endif
+";
+    let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+    let mut preset = Preset::default();
+    preset.set("SWORDS", "Swords");
+    let import = LegacyImport::from_source(source, &preset, "Helpers.Boss", &mut rng).unwrap();
+    assert_eq!(import.name.as_deref(), Some("Test"));
+    assert_eq!(import.settings["KEYSANITY"], "false");
+    assert_eq!(import.settings["SWORDS"], "SWORDS_ON");
+    let data = &import.data;
+    let flags: Vec<_> = data
+        .flags
+        .iter()
+        .map(|f| (f.name.as_str(), f.requirements.as_deref()))
+        .collect();
+    assert_eq!(
+        flags,
+        [
+            (
+                "HasSword",
+                Some("(| Items.SmithSword*2, Items.SmithSword.1)")
+            ),
+            ("Cave", Some("Locations.Pedestal")),
+            ("Boss", Some("Items.SmallKey:Deepwood*2, Items.Shield")),
+        ]
+    );
+    let pool: Vec<_> = data
+        .item_pool
+        .iter()
+        .map(|i| {
+            (
+                i.name.as_str(),
+                i.category,
+                i.restriction.as_deref(),
+                matches!(i.count, Some(ItemCount::Fixed(n)) if n > 0),
+            )
+        })
+        .collect();
+    assert_eq!(
+        pool,
+        [
+            ("SmithSword", Some(ItemCategory::Major), None, true),
+            ("Rupee5", Some(ItemCategory::Major), None, true),
+            ("Shield", Some(ItemCategory::Major), None, true),
+            (
+                "SmallKey:Deepwood",
+                Some(ItemCategory::DungeonItem),
+                Some("Deepwood"),
+                true
+            ),
+            ("SmithSword.1", Some(ItemCategory::Major), None, false),
+        ]
+    );
+    let door = data.locations.iter().find(|l| l.name == "Door").unwrap();
+    assert_eq!(door.requirements.as_deref(), Some("Helpers.Cave"));
+    assert_eq!(door.address.as_deref(), Some("0x48-0x00-0x01"));
+    let pedestal = data.locations.iter().find(|l| l.name == "Pedestal").unwrap();
+    assert_eq!(
+        (pedestal.vanilla.as_deref(), pedestal.fixed),
+        (Some("Shield"), Some(true))
+    );
+    let (logic, pool) = import.load().unwrap();
+    let (logic, _) = logic.build(&pool);
+    assert_eq!(logic.locations.len(), 4);
+    assert_eq!(logic.fixed.len(), 1);
+    // The boss needs both keys, but the file only places one; the ROM has nothing to do with it
+    assert!(matches!(
+        import.check(AllocatorSettings::default()),
+        Err(RandomizerError::ImpossiblePool(_))
+    ));
+    let settings = AllocatorSettings {
+        accessibility: crate::logic::Accessibility::Minimal,
+        ..Default::default()
+    };
+    let import = LegacyImport::from_source(source, &preset, "Items.Shield", &mut rng).unwrap();
+    import.check(settings).unwrap();
+
+    preset.set("KEYSANITY", "true");
+    let import = LegacyImport::from_source(source, &preset, "Helpers.Boss", &mut rng).unwrap();
+    let key = import
+        .data
+        .item_pool
+        .iter()
+        .find(|i| i.name.starts_with("SmallKey"))
+        .unwrap();
+    assert_eq!(
+        (key.name.as_str(), key.restriction.as_deref()),
+        ("SmallKey.0x18", None)
+    );
+    import.load().unwrap();
+
+    let err =
+        LegacyImport::from_source("!ifdef - X\n!else\n!else\n", &preset, "", &mut rng).unwrap_err();
+    assert!(matches!(
+        err,
+        RandomizerError::Legacy(LegacyError {
+            kind: LegacyErrorKind::UnmatchedElse,
+            line: 3
+        })
+    ));
+    let err = LegacyImport::from_source(
+        "A; Major; 0x01; Helpers.B\n",
+        &Preset::default(),
+        "",
+        &mut rng,
+    )
+    .unwrap_err();
+    match err {
+        RandomizerError::LocationRequirement { source, .. } => {
+            assert_eq!((source.line, source.column), (Some(1), 17))
+        }
+        e => panic!("Unexpected error {}", e),
+    }
+}
+
+#[test]
+fn default_logic_imports() {
+    let source = include_str!("default.logic.txt");
+    let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+    for keysanity in ["false", "true"] {
+        let mut preset = Preset::default();
+        preset.set("KEYSANITY", keysanity);
+        let import =
+            LegacyImport::from_source(source, &preset, "Helpers.BeatVaati", &mut rng).unwrap();
+        let (logic, pool) = import.load().unwrap();
+        logic.build(&pool);
+        // Without a ROM to read vanilla items from, the bosses' items are nowhere to be found
+        match import.check(AllocatorSettings::default()) {
+            Err(RandomizerError::GoalNeedsRomItems(items)) => {
+                assert!(items.iter().any(|item| item == "GustJar"), "{:?}", items)
+            }
+            other => panic!("Expected the check to fail: {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+pub mod logic_parse;
+pub mod header;
+pub mod logic;
+pub mod spoiler;
+pub mod generate;
+pub mod stats;
+pub mod preset;
+pub mod legacy;
+pub mod plando;
+mod error;
+
+pub use error::RandomizerError;
+pub use logic::{Allocator, AssignmentChecker, Logic, LogicLoader, PreLogic};
+pub use generate::{Generated, Generator, RetryPolicy};
+pub use spoiler::{Spoiler, SpoilerFormat};
+pub use stats::{BatchStats, StatsFormat};
+pub use preset::Preset;
+pub use legacy::LegacyImport;
+pub use plando::{Plando, PlandoPlacements};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileEntityType {
+    None = 0x00,
+    TestA = 0x01,
+    Chest = 0x02,
+    BigChest = 0x03,
+    TestB = 0x04,
+    TestC = 0x05,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomVersion {
+    EU,
+    JP,
+    US,
+    Unknown
+}
+pub struct Rom {
+    buf: Vec<u8>,
+    pub version: RomVersion
+}
+
+impl Rom {
+    pub fn new(fname: &Path) -> Result<Self, std::io::Error> {
+        let buf = std::fs::read(fname)?;
+        let version = match &buf[0xAC..0xAC + 4] {
+            b"BZMP" => RomVersion::EU,
+            b"BZMJ" => RomVersion::JP,
+            b"BZME" => RomVersion::US,
+            _ => RomVersion::Unknown
+        };
+        Ok(Self { buf, version })
+    }
+    pub fn read_at<const N: usize>(&self, pos: usize) -> [u8; N] {
+        let mut rv = [0; N];
+        rv[..N].clone_from_slice(&self.buf[pos..(N + pos)]);
+        rv
+    }
+    
+}
@@ -1,5 +1,13 @@
-use serde::Deserialize;
-use std::{collections::HashMap, rc::Rc};
+use serde::{Deserialize, Serialize};
+
+use crate::RandomizerError;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 mod condition;
 pub use condition::*;
@@ -9,18 +17,113 @@ mod logic_loader;
 pub use logic_loader::*;
 mod allocation_checker;
 pub use allocation_checker::*;
+mod events;
+pub use events::*;
+mod reachability;
+pub use reachability::*;
+mod bitset;
+pub use bitset::*;
+/// Compares, orders and hashes by `build` and `index`, which together are unique in the process
+macro_rules! by_index {
+    ($t:ty) => {
+        impl PartialEq for $t {
+            fn eq(&self, other: &Self) -> bool {
+                (self.build, self.index) == (other.build, other.index)
+            }
+        }
+        impl Eq for $t {}
+        impl PartialOrd for $t {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for $t {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                (self.build, self.index).cmp(&(other.build, other.index))
+            }
+        }
+        impl std::hash::Hash for $t {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                (self.build, self.index).hash(state);
+            }
+        }
+    };
+}
+
+/// How many times [`PreLogic::build`] has run, so each build can tell its items apart from
+/// those of any other
+static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+/// A build number that no other build has; see [`ItemDef::build`]
+pub(crate) fn new_build() -> usize {
+    BUILDS.fetch_add(1, Ordering::Relaxed)
+}
+
 pub struct PreLogic {
     last_id: usize,
-    parameters: HashMap<String, bool>,
-    item_map: HashMap<ItemId, PreItemDef>,
-    flag_map: HashMap<FlagId, PreFlag>,
-    location_map: HashMap<LocationId, PreLocation>,
+    parameters: BTreeMap<String, Parameter>,
+    restriction_map: BTreeMap<Restriction, String>,
+    item_map: BTreeMap<ItemId, PreItemDef>,
+    flag_map: BTreeMap<FlagId, PreFlag>,
+    location_map: BTreeMap<LocationId, PreLocation>,
+    goal: Option<Condition>,
 }
 
 pub struct Logic {
-    pub items: Vec<(Rc<ItemDef>, ItemCondition)>,
-    pub flags: Vec<(Rc<Flag>, ItemCondition)>,
-    pub locations: Vec<(Rc<Location>, ItemCondition)>,
+    pub items: Vec<(Arc<ItemDef>, ItemCondition)>,
+    pub flags: Vec<(Arc<Flag>, ItemCondition)>,
+    pub locations: Vec<(Arc<Location>, ItemCondition)>,
+    pub parameters: BTreeMap<String, ParameterValue>,
+    pub restrictions: BTreeMap<Restriction, String>,
+    /// What needs to be obtained to beat the game, if the logic defines it
+    pub goal: Option<ItemCondition>,
+    /// Items that stay in their vanilla location; the item pool still includes them
+    pub fixed: Placements,
+}
+
+/// Items put in specific locations ahead of allocation
+pub type Placements = Vec<(Arc<Location>, Arc<ItemDef>)>;
+
+/// Everything an inventory gives access to; see [`Logic::reachable`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reachability {
+    /// Items whose own requirements are met
+    pub items: Vec<Arc<ItemDef>>,
+    pub flags: Vec<Arc<Flag>>,
+    pub locations: Vec<Arc<Location>>,
+    /// Whether the goal is met, if the logic defines it
+    pub goal: Option<bool>,
+}
+
+impl Logic {
+    /// An empty inventory with room for every item of the logic
+    pub fn inventory(&self) -> Inventory {
+        Inventory::new(self.items.len())
+    }
+
+    /// Evaluates every requirement against an inventory, leaving the logic untouched.
+    /// Items found in the reachable locations aren't collected; that's up to the caller.
+    pub fn reachable(&self, inventory: &Inventory) -> Reachability {
+        fn met<T>(conditionals: &[Conditional<T>], inventory: &Inventory) -> Vec<Arc<T>> {
+            conditionals
+                .iter()
+                .filter(|(_, req)| req.satisfied_by(inventory))
+                .map(|(thing, _)| thing.clone())
+                .collect()
+        }
+        Reachability {
+            items: met(&self.items, inventory),
+            flags: met(&self.flags, inventory),
+            locations: met(&self.locations, inventory),
+            goal: self.goal.as_ref().map(|goal| goal.satisfied_by(inventory)),
+        }
+    }
+}
+
+impl Default for PreLogic {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PreLogic {
@@ -28,29 +131,72 @@ impl PreLogic {
         PreLogic {
             last_id: 0,
             parameters: Default::default(),
-            item_map: HashMap::new(),
-            location_map: HashMap::new(),
-            flag_map: HashMap::new(),
+            restriction_map: Default::default(),
+            item_map: BTreeMap::new(),
+            location_map: BTreeMap::new(),
+            flag_map: BTreeMap::new(),
+            goal: None,
         }
     }
 
-    pub fn set_parameter(&mut self, name: impl Into<String>, value: bool) {
-        self.parameters.insert(name.into(), value);
+    pub fn add_parameter(&mut self, name: impl Into<String>, typ: ParameterType, value: ParameterValue) {
+        self.parameters.insert(name.into(), Parameter { typ, value });
+    }
+    /// Changes the value of a declared parameter, parsing it according to the parameter's type
+    pub fn set_parameter(&mut self, name: &str, value: &str) -> Result<(), RandomizerError> {
+        let param = self
+            .parameters
+            .get_mut(name)
+            .ok_or_else(|| RandomizerError::UnknownParameter(name.to_string()))?;
+        param.value = param.typ.parse(value).ok_or_else(|| RandomizerError::InvalidParameterValue {
+            name: name.to_string(),
+            value: value.to_string(),
+            expected: param.typ.to_string(),
+        })?;
+        Ok(())
+    }
+    /// Whether a condition made only of parameters holds; see `Condition::is_static`
+    pub fn evaluate_static(&self, condition: &Condition) -> bool {
+        condition.expand(self, &mut HashMap::new(), &HashMap::new()).satisfied()
+    }
+    pub fn get_parameter(&self, name: &str) -> Option<&Parameter> {
+        self.parameters.get(name)
+    }
+    /// Whether a boolean parameter is on
+    pub fn parameter_enabled(&self, name: &str) -> bool {
+        matches!(self.get_parameter(name), Some(Parameter { value: ParameterValue::Boolean(true), .. }))
     }
-    pub fn get_parameter(&self, name: &str) -> bool {
-        self.parameters.get(name).copied().unwrap_or_default()
+    /// The value of a parameter as a number, for comparisons and thresholds
+    pub fn parameter_number(&self, name: &str) -> Option<i64> {
+        self.get_parameter(name).and_then(|param| param.typ.number(&param.value))
     }
-    pub fn build(&self, item_pool_ids: &[ItemId]) -> (Logic, Vec<(Rc<ItemDef>, ItemCondition)>) {
+    /// Returns the restriction with the given name, creating it if it doesn't exist yet
+    pub fn add_restriction(&mut self, name: &str) -> Restriction {
+        if let Some((&restriction, _)) = self.restriction_map.iter().find(|(_, n)| *n == name) {
+            restriction
+        } else {
+            let restriction = Restriction(self.restriction_map.len() + 1);
+            self.restriction_map.insert(restriction, name.to_string());
+            restriction
+        }
+    }
+    pub fn set_goal(&mut self, goal: Condition) {
+        self.goal = Some(goal);
+    }
+    pub fn build(&self, item_pool_ids: &[ItemId]) -> (Logic, Vec<(Arc<ItemDef>, ItemCondition)>) {
+        let build = new_build();
         let mut condition_cache: HashMap<&Condition, ItemCondition> = HashMap::new();
         let mut item_cache = HashMap::new();
         let mut items = vec![];
         for preitem in self.item_map.values() {
-            let item = Rc::new(ItemDef {
+            let item = Arc::new(ItemDef {
                 name: preitem.name.clone(),
                 category: preitem.category,
                 restriction: preitem.restriction,
                 weight: preitem.weight,
                 show_in_graph: preitem.show_in_graph,
+                build,
+                index: items.len(),
             });
             items.push(item.clone());
             item_cache.insert(preitem.id, item);
@@ -63,37 +209,59 @@ impl PreLogic {
                 item,
                 preitem
                     .requirement
-                    .expand(&self, &mut condition_cache, &item_cache),
+                    .expand(self, &mut condition_cache, &item_cache),
             ))
         }
         let mut flags = vec![];
         for flag in self.flag_map.values() {
             flags.push((
-                Rc::new(Flag {
+                Arc::new(Flag {
                     name: flag.name.clone(),
+                    build,
+                    index: flags.len(),
                 }),
                 flag.requirement
-                    .expand(&self, &mut condition_cache, &item_cache),
+                    .expand(self, &mut condition_cache, &item_cache),
             ));
         }
 
         let mut locations = vec![];
-        for location in self.location_map.values() {
+        let mut fixed = vec![];
+        for location in self.location_map.values().filter(|location| location.enabled) {
+            let loc = Arc::new(Location {
+                name: location.name.clone(),
+                category: location.category,
+                restriction: location.restriction,
+                excluded: location.excluded,
+                build,
+                index: locations.len(),
+            });
+            if let Some(item) = location.fixed {
+                fixed.push((loc.clone(), item_cache[&item].clone()));
+            }
             locations.push((
-                Rc::new(Location {
-                    name: location.name.clone(),
-                    category: location.category,
-                    restriction: location.restriction,
-                }),
+                loc,
                 location
                     .requirement
-                    .expand(&self, &mut condition_cache, &item_cache),
+                    .expand(self, &mut condition_cache, &item_cache),
             ));
         }
+        let goal = self
+            .goal
+            .as_ref()
+            .map(|goal| goal.expand(self, &mut condition_cache, &item_cache));
         let logic = Logic {
             items,
             flags,
             locations,
+            parameters: self
+                .parameters
+                .iter()
+                .map(|(name, param)| (name.clone(), param.value.clone()))
+                .collect(),
+            restrictions: self.restriction_map.clone(),
+            goal,
+            fixed,
         };
         let item_pool = item_pool_ids
             .iter()
@@ -167,10 +335,31 @@ impl PreLogic {
             restriction,
             category,
             id,
+            enabled: true,
+            fixed: None,
+            excluded: false,
         };
         self.location_map.insert(id, location);
         id
     }
+    /// Keeps nothing from being placed in a location, while requirements can still refer to it
+    pub fn disable_location(&mut self, id: LocationId) {
+        if let Some(location) = self.location_map.get_mut(&id) {
+            location.enabled = false;
+        }
+    }
+    /// Only lets items that no requirement mentions go into a location
+    pub fn exclude_location(&mut self, id: LocationId) {
+        if let Some(location) = self.location_map.get_mut(&id) {
+            location.excluded = true;
+        }
+    }
+    /// Keeps an item in a location instead of shuffling it
+    pub fn fix_item(&mut self, location: LocationId, item: ItemId) {
+        if let Some(location) = self.location_map.get_mut(&location) {
+            location.fixed = Some(item);
+        }
+    }
 
     pub fn add_flag(&mut self, name: impl Into<String>) -> FlagId {
         let name = name.into();
@@ -187,6 +376,109 @@ impl PreLogic {
     }
 }
 
+/// The values a parameter can take
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ParameterType {
+    Boolean,
+    Enum { values: Vec<String> },
+    Integer { min: i64, max: i64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(untagged)]
+pub enum ParameterValue {
+    Boolean(bool),
+    Integer(i64),
+    /// One of the values of an enum parameter
+    Enum(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parameter {
+    pub typ: ParameterType,
+    pub value: ParameterValue,
+}
+
+impl ParameterType {
+    /// Parses a value written in a logic file or on the command line
+    pub fn parse(&self, s: &str) -> Option<ParameterValue> {
+        match self {
+            ParameterType::Boolean => match s {
+                "True" | "true" => Some(ParameterValue::Boolean(true)),
+                "False" | "false" => Some(ParameterValue::Boolean(false)),
+                _ => None,
+            },
+            ParameterType::Enum { values } => values
+                .iter()
+                .find(|v| *v == s)
+                .map(|v| ParameterValue::Enum(v.clone())),
+            ParameterType::Integer { min, max } => s
+                .parse()
+                .ok()
+                .filter(|n| (min..=max).contains(&n))
+                .map(ParameterValue::Integer),
+        }
+    }
+
+    /// Parses the right hand side of a comparison. Unlike `parse`, enum values can be given
+    /// by index, and integers aren't limited to the parameter's range.
+    pub fn parse_operand(&self, s: &str) -> Option<i64> {
+        match self {
+            ParameterType::Enum { values } => s
+                .parse()
+                .ok()
+                .filter(|&n: &i64| n >= 0 && (n as usize) < values.len())
+                .or_else(|| self.number(&self.parse(s)?)),
+            ParameterType::Integer { .. } => s.parse().ok(),
+            ParameterType::Boolean => self.number(&self.parse(s)?),
+        }
+    }
+
+    /// The value as a number: booleans are 0 or 1, enum values are their index
+    pub fn number(&self, value: &ParameterValue) -> Option<i64> {
+        match (self, value) {
+            (ParameterType::Boolean, ParameterValue::Boolean(b)) => Some(*b as i64),
+            (ParameterType::Integer { .. }, ParameterValue::Integer(n)) => Some(*n),
+            (ParameterType::Enum { values }, ParameterValue::Enum(v)) => {
+                values.iter().position(|value| value == v).map(|idx| idx as i64)
+            }
+            _ => None,
+        }
+    }
+
+    /// The value used when the logic file doesn't give one
+    pub fn default_value(&self) -> ParameterValue {
+        match self {
+            ParameterType::Boolean => ParameterValue::Boolean(false),
+            ParameterType::Enum { values } => {
+                ParameterValue::Enum(values.first().cloned().unwrap_or_default())
+            }
+            ParameterType::Integer { min, .. } => ParameterValue::Integer(*min),
+        }
+    }
+}
+
+impl std::fmt::Display for ParameterType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParameterType::Boolean => write!(f, "True or False"),
+            ParameterType::Enum { values } => write!(f, "one of {}", values.join(", ")),
+            ParameterType::Integer { min, max } => write!(f, "a whole number from {} to {}", min, max),
+        }
+    }
+}
+
+impl std::fmt::Display for ParameterValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParameterValue::Boolean(true) => write!(f, "True"),
+            ParameterValue::Boolean(false) => write!(f, "False"),
+            ParameterValue::Integer(n) => write!(f, "{}", n),
+            ParameterValue::Enum(v) => write!(f, "{}", v),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PreFlag {
     name: String,
@@ -194,12 +486,19 @@ pub struct PreFlag {
     requirement: Condition,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Flag {
-    name: String,
+    pub name: String,
+    /// See [`ItemDef::build`]
+    #[serde(skip)]
+    pub build: usize,
+    /// Position among the logic's flags; see [`ItemDef::index`]
+    #[serde(skip)]
+    pub index: usize,
 }
+by_index!(Flag);
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
 pub enum ItemCategory {
     Minor,
     Major,
@@ -226,18 +525,27 @@ pub struct PreItemDef {
     pub requirement: Condition,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Clone, Debug, Serialize)]
 pub struct ItemDef {
     pub name: String,
     pub category: ItemCategory,
     pub restriction: Option<Restriction>,
     pub weight: u32,
     pub show_in_graph: bool,
+    /// The [`PreLogic::build`] call that made the item. Conditions are shared by the whole
+    /// process, so this keeps separately built logics from sharing them.
+    #[serde(skip)]
+    pub build: usize,
+    /// Position among the logic's items, assigned by [`PreLogic::build`]. Together with
+    /// `build`, it's all that comparing and hashing look at; inventories count by it.
+    #[serde(skip)]
+    pub index: usize,
 }
+by_index!(ItemDef);
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Item {
-    pub def: Rc<ItemDef>,
+    pub def: Arc<ItemDef>,
     pub count: usize,
 }
 impl std::fmt::Display for ItemDef {
@@ -250,14 +558,14 @@ impl std::fmt::Display for ItemDef {
     }
 }
 impl Item {
-    pub fn new(def: Rc<ItemDef>) -> Self {
+    pub fn new(def: Arc<ItemDef>) -> Self {
         Item { def, count: 1 }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 pub struct Restriction(usize);
-impl<'a> std::fmt::Display for Restriction {
+impl std::fmt::Display for Restriction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[{}]", self.0)
     }
@@ -279,14 +587,28 @@ pub struct PreLocation {
     category: ItemCategory,
     requirement: Condition,
     restriction: Option<Restriction>,
+    enabled: bool,
+    /// Item that stays in this location instead of being shuffled
+    fixed: Option<ItemId>,
+    excluded: bool,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Location {
-    name: String,
-    category: ItemCategory,
-    restriction: Option<Restriction>,
+    pub name: String,
+    pub category: ItemCategory,
+    pub restriction: Option<Restriction>,
+    /// Only items that no requirement mentions may go here
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub excluded: bool,
+    /// See [`ItemDef::build`]
+    #[serde(skip)]
+    pub build: usize,
+    /// Position among the logic's enabled locations; see [`ItemDef::index`]
+    #[serde(skip)]
+    pub index: usize,
 }
+by_index!(Location);
 impl std::fmt::Display for Location {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}{}", self.category.decoration(), self.name)?;
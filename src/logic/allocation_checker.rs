@@ -1,39 +1,131 @@
-use std::{collections::{HashMap, HashSet}, rc::Rc};
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::Arc};
 
-use super::{Conditionals, Location, Flag, ItemDef};
+use super::{Accessibility, Conditionals, Location, Flag, Inventory, ItemDef, ItemCondition, ItemSet, Logic, ReachabilityEngine, Unlock};
+
+/// Everything that becomes available in one step of a playthrough
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sphere {
+    /// Flags that became satisfied by the items of earlier spheres
+    pub flags: Vec<Arc<Flag>>,
+    /// Items collected in this sphere, and where they were found
+    pub items: Vec<(Arc<ItemDef>, Arc<Location>)>,
+}
+
+/// The outcome of playing through a set of assignments
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub spheres: Vec<Sphere>,
+    /// Locations that can never be reached
+    pub unreachable_locations: Vec<Arc<Location>>,
+    /// Reachable locations that weren't assigned an item
+    pub empty_locations: Vec<Arc<Location>>,
+    pub unsatisfied_flags: Vec<Arc<Flag>>,
+    /// Items placed in unreachable locations
+    pub unobtainable_items: Vec<(Arc<ItemDef>, Arc<Location>)>,
+    /// Unobtainable items that some location, flag or the goal depends on
+    pub unobtainable_progression_items: Vec<(Arc<ItemDef>, Arc<Location>)>,
+    /// Items that some location, flag or the goal depends on, placed in excluded locations
+    pub excluded_progression_items: Vec<(Arc<ItemDef>, Arc<Location>)>,
+    /// Whether the goal can be reached, if the checker was given one
+    pub goal_reachable: Option<bool>,
+    /// Dependency graph of the playthrough, in Graphviz format
+    pub graph: String,
+}
+
+impl CheckReport {
+    /// Whether every location and item can be reached, as well as the goal, if any.
+    /// Unsatisfied flags are fine; they may depend on parameters that are turned off.
+    pub fn passed(&self) -> bool {
+        self.unreachable_locations.is_empty()
+            && self.unobtainable_items.is_empty()
+            && self.goal_reachable != Some(false)
+    }
+
+    /// Whether the goal can be reached, regardless of what else is left locked
+    pub fn beatable(&self) -> bool {
+        self.goal_reachable == Some(true)
+    }
+
+    /// Whether the assignments meet the guarantees of the given accessibility mode, and
+    /// excluded locations hold no progression
+    pub fn satisfies(&self, accessibility: Accessibility) -> bool {
+        self.excluded_progression_items.is_empty()
+            && match accessibility {
+                Accessibility::Full => self.passed(),
+                Accessibility::Beatable => self.beatable() && self.unobtainable_progression_items.is_empty(),
+                Accessibility::Minimal => self.beatable(),
+            }
+    }
+}
+
+/// Minimal sets with more items than this are left out of the graph, so that thresholds like
+/// `Rupee*100` don't have every combination spelled out
+const GRAPH_SET_CAP: usize = 12;
+
+/// The items that a requirement the inventory meets depends on: the smallest of its minimal
+/// sets the inventory holds. Past [`GRAPH_SET_CAP`], settles for a greedy pick of held items.
+fn satisfiers(req: &ItemCondition, inventory: &Inventory) -> Option<ItemSet> {
+    let held = req.prune_sat(inventory)?;
+    let smallest = held.minimal_held_sets(inventory, GRAPH_SET_CAP).into_iter().next();
+    Some(smallest.unwrap_or_else(|| {
+        let mut greedy = HashMap::new();
+        held.min_sat(inventory, &mut greedy);
+        greedy.into_iter().map(|(item, count)| (item.clone(), count)).collect()
+    }))
+}
 
 pub struct AssignmentChecker {
     locations: Conditionals<Location>,
     flags: Conditionals<Flag>,
+    goal: Option<ItemCondition>,
+    /// Compiled once; every check restarts it
+    reach: ReachabilityEngine,
 }
 impl AssignmentChecker {
     pub fn new(locations: Conditionals<Location>, flags: Conditionals<Flag>) -> Self {
-        AssignmentChecker { locations, flags }
+        let reach = ReachabilityEngine::new(&locations, &flags, &Vec::new(), None);
+        AssignmentChecker { locations, flags, goal: None, reach }
+    }
+
+    /// Checks the locations and flags of the logic, and its goal if it has one
+    pub fn from_logic(logic: &Logic) -> Self {
+        let checker = Self::new(logic.locations.clone(), logic.flags.clone());
+        match &logic.goal {
+            Some(goal) => checker.with_goal(goal.clone()),
+            None => checker,
+        }
+    }
+
+    /// Also check that this condition is satisfied by the end of the playthrough
+    pub fn with_goal(mut self, goal: ItemCondition) -> Self {
+        self.reach = ReachabilityEngine::new(&self.locations, &self.flags, &Vec::new(), Some(&goal));
+        self.goal = Some(goal);
+        self
     }
 
-    
-    pub fn check_assignments(&self, assignments: &HashMap<Rc<Location>, Rc<ItemDef>>) {
-        let mut locations = self.locations.clone();
-        let orig_locs: HashMap<_, _> = locations.iter().cloned().collect();
-        let mut flags = self.flags.clone();
-        let orig_flags: HashMap<_, _> = flags.iter().cloned().collect();
+    pub fn check_assignments(&self, assignments: &BTreeMap<Arc<Location>, Arc<ItemDef>>) -> CheckReport {
+        let mut reach = self.reach.restart();
+        let orig_locs: HashMap<_, _> = self.locations.iter().cloned().collect();
+        let orig_flags: HashMap<_, _> = self.flags.iter().cloned().collect();
         let mut graph = "digraph G {\n".to_string();
         let mut open_locations = HashSet::new();
-        let mut acquired_items = HashMap::new();
+        // Flags that hold from the start show up in the sphere after the first one
+        let mut unlocked: HashSet<Unlock> = reach.unlocked().cloned().collect();
         let mut new_locations: Vec<_> = self
             .locations
             .iter()
-            .filter(|(_l, req)| req.satisfied())
+            .filter(|(loc, _)| unlocked.remove(&Unlock::Location(loc.clone())))
             .map(|(loc, _)| loc.clone())
             .collect();
-        let mut generations = vec![];
-        let mut item_indices: HashMap<&Rc<ItemDef>, usize> = HashMap::new();
-        let mut completed_flags: HashSet<Rc<Flag>> = HashSet::new();
+        let mut spheres = vec![];
+        let mut item_indices: HashMap<&Arc<ItemDef>, usize> = HashMap::new();
+        let mut completed_flags: HashSet<Arc<Flag>> = HashSet::new();
         let mut new_flags: Conditionals<Flag> = vec![];
+        let mut empty_locations = vec![];
 
         while !new_locations.is_empty() || !new_flags.is_empty() {
             let mut this_gen = vec![];
-            for (flag, _req) in &new_flags {
+            for (flag, _) in &new_flags {
                 completed_flags.insert(flag.clone());
                 graph.push_str(&format!(
                     r#"  "flag{}" [label="{}", shape="octagon"];"#,
@@ -41,11 +133,9 @@ impl AssignmentChecker {
                 ));
                 graph.push('\n');
                 let orig_flag_req = &orig_flags[flag];
-                let orig_flag_req = orig_flag_req.prune_sat(&acquired_items).map_err(|_| {
-                    eprintln!("Flag not actually satisfied? {} {} {}", flag.name, _req, orig_flag_req);
-                }).unwrap();
-                let mut satisfiers = HashMap::new();
-                orig_flag_req.min_sat(&acquired_items, &mut satisfiers);
+                let satisfiers = satisfiers(orig_flag_req, reach.inventory()).unwrap_or_else(|| {
+                    panic!("Flag not actually satisfied? {} {}", flag.name, orig_flag_req)
+                });
                 for (sat, max_idx) in satisfiers {
                     if !sat.show_in_graph {
                         continue;
@@ -72,17 +162,14 @@ impl AssignmentChecker {
                         item.name, idx, loc, item.name
                     ));
                     graph.push('\n');
-                    let orig_loc_req = &orig_locs[loc];
-                    let orig_loc_req = orig_loc_req.prune_sat(&acquired_items).expect("New location not satisifed?");
-                    let mut satisfiers = HashMap::new();
-                    orig_loc_req.min_sat(&acquired_items, &mut satisfiers);
+                    let satisfiers = satisfiers(&orig_locs[loc], reach.inventory()).expect("New location not satisifed?");
                     for (sat, max_idx) in satisfiers {
                         if !sat.show_in_graph {
                             continue;
                         }
                         // let max_idx = *item_indices.get(&sat).unwrap();
                         for i in 1..=max_idx {
-                            if i == idx && sat == item {
+                            if i == idx && sat == *item {
                                 // Don't link an item to itself
                                 continue;
                             }
@@ -100,48 +187,149 @@ impl AssignmentChecker {
             for loc in new_locations {
                 open_locations.insert(loc.clone());
                 if let Some(item) = assignments.get(&loc) {
-                    *acquired_items.entry(item).or_default() += 1;
-                    for (_, req) in &mut locations {
-                        req.assume_item(item, 1);
-                    }
-                    for (_, req) in &mut flags {
-                        req.assume_item(item, 1);
-                    }
+                    unlocked.extend(reach.add_item(item));
                     this_gen.push((item.clone(), loc));
                 } else {
-                    eprintln!("Empty location?");
+                    empty_locations.push(loc);
                 }
             }
-            for (flag, _) in new_flags {
-                print!("{{{}}}, ", flag.name);
-            }
-            for (item, loc) in &this_gen {
-                print!("{} ({}), ", item, loc);
-            }
-            println!();
-            generations.push(this_gen);
+            spheres.push(Sphere {
+                flags: new_flags.into_iter().map(|(flag, _)| flag).collect(),
+                items: this_gen,
+            });
 
-            new_locations = locations
+            // Each unlock comes up once, so nothing here is open or completed yet
+            new_locations = self
+                .locations
                 .iter()
-                .filter(|(l, _)| !open_locations.contains(l))
-                .filter(|(_, req)| req.satisfied())
+                .filter(|(loc, _)| unlocked.contains(&Unlock::Location(loc.clone())))
                 .map(|(loc, _)| loc.clone())
                 .collect();
-            new_flags = flags
+            new_flags = self
+                .flags
                 .iter()
-                .filter(|(f, _)| !completed_flags.contains(&**f))
-                .filter(|(_, req)| req.satisfied())
+                .filter(|(flag, _)| unlocked.contains(&Unlock::Flag(flag.clone())))
                 .cloned()
                 .collect();
+            unlocked.clear();
         }
         graph.push('}');
-        std::fs::write("graph.dot", graph.as_bytes());
-        for (flag, _) in self
-            .flags
+        let unreachable_locations: Vec<_> = self
+            .locations
+            .iter()
+            .filter(|(l, _)| !open_locations.contains(l))
+            .map(|(l, _)| l.clone())
+            .collect();
+        let unobtainable_items: Vec<_> = unreachable_locations
+            .iter()
+            .filter_map(|l| assignments.get(l).map(|item| (item.clone(), l.clone())))
+            .collect();
+        let mut progression_items = BTreeMap::new();
+        let requirements = (self.locations.iter().map(|(_, req)| req))
+            .chain(self.flags.iter().map(|(_, req)| req))
+            .chain(&self.goal);
+        for req in requirements {
+            req.missing(&mut progression_items);
+        }
+        let excluded_progression_items = assignments
             .iter()
-            .filter(|(f, _)| !completed_flags.contains(&**f))
-        {
-            println!("Unsatisfied flag: {}", flag.name);
+            .filter(|(loc, item)| loc.excluded && progression_items.contains_key(*item))
+            .map(|(loc, item)| (item.clone(), loc.clone()))
+            .collect();
+        CheckReport {
+            spheres,
+            excluded_progression_items,
+            unobtainable_progression_items: unobtainable_items
+                .iter()
+                .filter(|(item, _)| progression_items.contains_key(item))
+                .cloned()
+                .collect(),
+            unobtainable_items,
+            unreachable_locations,
+            empty_locations,
+            unsatisfied_flags: self
+                .flags
+                .iter()
+                .filter(|(f, _)| !completed_flags.contains(&**f))
+                .map(|(f, _)| f.clone())
+                .collect(),
+            goal_reachable: self.goal.as_ref().map(|_| reach.is_met(&Unlock::Goal)),
+            graph,
         }
     }
 }
+
+#[test]
+fn graph_links_the_smallest_held_set() {
+    let yaml = "
+flags: []
+item_pool:
+  - name: A
+    show_in_graph: true
+  - name: B
+    show_in_graph: true
+  - name: Z
+    show_in_graph: true
+  - name: Prize
+    show_in_graph: true
+locations:
+  - name: Start
+  - name: Chest
+  - name: Shelf
+  - name: Gate
+    requirements: (| A, Z), (| B, Z)
+";
+    let (prelogic, pool) = crate::LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let item = |name: &str| pool.iter().find(|(item, _)| item.name == name).unwrap().0.clone();
+    let location = |name: &str| logic.locations.iter().find(|(loc, _)| loc.name == name).unwrap().0.clone();
+    let assignments: BTreeMap<_, _> = [("Start", "A"), ("Chest", "B"), ("Shelf", "Z"), ("Gate", "Prize")]
+        .into_iter()
+        .map(|(loc, item_name)| (location(loc), item(item_name)))
+        .collect();
+    let report = AssignmentChecker::from_logic(&logic).check_assignments(&assignments);
+    assert_eq!(report.spheres.len(), 2);
+    // Taking A for the first choice and B for the second needs two items, where Z alone does
+    let edges: Vec<_> = report.graph.lines().filter(|line| line.contains("->")).collect();
+    assert_eq!(edges, [r#"  "Prize1" -> "Z1";"#]);
+}
+
+#[test]
+fn reports_are_judged_per_accessibility() {
+    use Accessibility::{Beatable, Full, Minimal};
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Map
+  - name: Rupee
+    count: 2
+locations:
+  - name: Start
+  - name: Chest
+  - name: Field
+  - name: Cave
+    requirements: Bow
+  - name: Shop
+    requirements: Rupee*3
+goal: Sword
+";
+    let verdicts = |yaml: &str, placements: [&str; 5]| {
+        let (prelogic, pool) = crate::LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+        let (logic, pool) = prelogic.build(&pool);
+        let item = |name: &str| pool.iter().find(|(item, _)| item.name == name).unwrap().0.clone();
+        let assignments = logic.locations.iter().zip(placements).map(|((loc, _), name)| (loc.clone(), item(name))).collect();
+        let report = AssignmentChecker::from_logic(&logic).check_assignments(&assignments);
+        [Full, Beatable, Minimal].map(|accessibility| report.satisfies(accessibility))
+    };
+    // Everything can be reached
+    let open = yaml.replace("Rupee*3", "Rupee*2");
+    assert_eq!(verdicts(&open, ["Sword", "Bow", "Rupee", "Rupee", "Map"]), [true, true, true]);
+    // Only junk is locked away: the shop needs more rupees than there are
+    assert_eq!(verdicts(yaml, ["Sword", "Bow", "Rupee", "Rupee", "Map"]), [false, true, true]);
+    // The bow is locked away, but the goal doesn't need it
+    assert_eq!(verdicts(yaml, ["Sword", "Map", "Rupee", "Rupee", "Bow"]), [false, false, true]);
+    // The sword is locked away
+    assert_eq!(verdicts(yaml, ["Bow", "Map", "Rupee", "Rupee", "Sword"]), [false, false, false]);
+}
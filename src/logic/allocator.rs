@@ -1,91 +1,343 @@
 pub use super::condition::*;
 use super::*;
+use crate::RandomizerError;
 use rand::prelude::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
-pub struct Allocator {
+mod assumed;
+
+/// Which locations a finished allocation must make reachable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Accessibility {
+    /// Every location can be reached
+    Full,
+    /// The goal can be reached; some locations may stay locked forever, but only hold junk
+    Beatable,
+    /// The goal can be reached; anything not needed for it may end up locked away
+    Minimal,
+}
+
+impl std::str::FromStr for Accessibility {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Accessibility::Full),
+            "beatable" => Ok(Accessibility::Beatable),
+            "minimal" => Ok(Accessibility::Minimal),
+            _ => Err(format!(
+                "Unknown accessibility {}; expected full, beatable or minimal",
+                s
+            )),
+        }
+    }
+}
+
+/// How the allocator decides where items go
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Algorithm {
+    /// Grow the set of reachable locations by placing items that open up new ones
+    Forward,
+    /// Place progression items where they can be reached with every unplaced item assumed
+    Assumed,
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forward" => Ok(Algorithm::Forward),
+            "assumed" => Ok(Algorithm::Assumed),
+            _ => Err(format!(
+                "Unknown algorithm {}; expected forward or assumed",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AllocatorSettings {
+    /// Place progression items in the most recently opened locations instead of at random. With
+    /// assumed fill, keep them out of the locations that are open from the start instead.
+    pub prefer_new_locations: bool,
+    /// Only place items in locations of the same category
+    pub match_category: bool,
+    /// How much randomness to add to item weights
+    pub temperature: u32,
+    pub accessibility: Accessibility,
+    pub algorithm: Algorithm,
+}
+
+impl Default for AllocatorSettings {
+    fn default() -> Self {
+        AllocatorSettings {
+            prefer_new_locations: false,
+            match_category: false,
+            temperature: 5,
+            accessibility: Accessibility::Full,
+            algorithm: Algorithm::Forward,
+        }
+    }
+}
+
+/// How many rounds an allocation may take before it counts as stuck
+const MAX_ROUNDS: usize = 150;
+
+/// Everything a placement changes, so that it can be undone
+#[derive(Clone)]
+struct Snapshot {
     item_pool: Conditionals<ItemDef>,
-    open_locations: Vec<Rc<Location>>,
-    closed_locations: Conditionals<Location>,
-    locations: Conditionals<Location>,
-    flags: Conditionals<Flag>,
-    prefer_new_locations: bool,
-    match_category: bool,
-    temperature: u32,
-    assigned_items: HashMap<Rc<ItemDef>, usize>,
-
-    assignments: HashMap<Rc<Location>, Rc<ItemDef>>,
+    open_locations: Vec<Arc<Location>>,
+    closed_locations: BitSet,
+    reach: ReachabilityEngine,
+    assignments: BTreeMap<Arc<Location>, Arc<ItemDef>>,
 }
 
-impl Allocator {
+pub struct Allocator<'a> {
+    /// Requirements as given; `reach` tracks what's left of them
+    logic: &'a Logic,
+    item_pool: Conditionals<ItemDef>,
+    open_locations: Vec<Arc<Location>>,
+    /// Locations that can't be reached yet, by [`Location::index`]
+    closed_locations: BitSet,
+    /// What the collected items unlock
+    reach: ReachabilityEngine,
+    settings: AllocatorSettings,
+    /// Items that stay in their vanilla location; collected as soon as it's reached
+    fixed: BTreeMap<Arc<Location>, Arc<ItemDef>>,
+    /// Items that show up in some requirement
+    progression_items: HashSet<Arc<ItemDef>>,
+
+    assignments: BTreeMap<Arc<Location>, Arc<ItemDef>>,
+    observer: Box<dyn AllocationObserver>,
+
+    /// State before each of the most recent placements, oldest first
+    history: VecDeque<Snapshot>,
+    backtrack_depth: usize,
+    max_backtracks: usize,
+    backtracks: usize,
+}
+
+impl<'a> Allocator<'a> {
+    /// `fixed` items stay in their location; the pool must include a copy of each of them
     pub fn new(
-        item_pool: Conditionals<ItemDef>,
-        locations: Conditionals<Location>,
-        flags: Conditionals<Flag>,
-        prefer_new_locations: bool,
-        match_category: bool,
-        temperature: u32,
-    ) -> Self {
+        logic: &'a Logic,
+        mut item_pool: Conditionals<ItemDef>,
+        fixed: Placements,
+        settings: AllocatorSettings,
+    ) -> Result<Self, RandomizerError> {
+        let mut progression_items = BTreeMap::new();
+        let requirements = (item_pool.iter().map(|(_, req)| req))
+            .chain(logic.locations.iter().map(|(_, req)| req))
+            .chain(logic.flags.iter().map(|(_, req)| req))
+            .chain(&logic.goal);
+        for req in requirements {
+            req.missing(&mut progression_items);
+        }
+        let progression_items: HashSet<_> = progression_items.into_keys().cloned().collect();
+        for (location, item) in &fixed {
+            if location.excluded && progression_items.contains(item) {
+                return Err(RandomizerError::ImpossiblePool(format!(
+                    "{} stays in {}, but the location is excluded",
+                    item, location
+                )));
+            }
+            match item_pool.iter().position(|(i, _)| i == item) {
+                Some(idx) => {
+                    item_pool.remove(idx);
+                }
+                None => {
+                    return Err(RandomizerError::ImpossiblePool(format!(
+                        "{} stays in {}, but there's no {} left in the pool",
+                        item, location, item
+                    )))
+                }
+            }
+        }
+        let fixed: BTreeMap<_, _> = fixed.into_iter().collect();
+        let reach = ReachabilityEngine::new(
+            &logic.locations,
+            &logic.flags,
+            &item_pool,
+            logic.goal.as_ref(),
+        );
         let mut me = Allocator {
+            logic,
+            progression_items,
             item_pool,
-            prefer_new_locations,
-            match_category,
-            temperature,
-            locations,
-            flags,
+            settings,
+            reach,
             open_locations: Default::default(),
-            closed_locations: Default::default(),
-            assigned_items: Default::default(),
-            assignments: Default::default(),
+            closed_locations: BitSet::new(logic.locations.len()),
+            assignments: fixed.clone(),
+            fixed,
+            observer: Box::new(()),
+            history: Default::default(),
+            backtrack_depth: 0,
+            max_backtracks: 0,
+            backtracks: 0,
         };
+        me.preflight_check()?;
         me.find_open_locs();
-        // me.preflight_check();
-        me
+        Ok(me)
+    }
+
+    /// Sets the observer that receives an [`AllocationEvent`] for every decision the allocator makes
+    pub fn set_observer(&mut self, observer: Box<dyn AllocationObserver>) {
+        self.observer = observer;
+    }
+
+    /// When allocation gets stuck, undo the last `depth` placements and try again,
+    /// up to `max_backtracks` times, instead of failing straight away
+    pub fn set_backtracking(&mut self, depth: usize, max_backtracks: usize) {
+        self.backtrack_depth = depth;
+        self.max_backtracks = max_backtracks;
+    }
+
+    /// How many times allocation backtracked
+    pub fn backtracks(&self) -> usize {
+        self.backtracks
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            item_pool: self.item_pool.clone(),
+            open_locations: self.open_locations.clone(),
+            closed_locations: self.closed_locations.clone(),
+            reach: self.reach.clone(),
+            assignments: self.assignments.clone(),
+        }
     }
 
-    fn preflight_check(&self) {
-        for (_, req) in &self.locations {
-            let mut req = req.clone();
-            for (item, _) in &self.item_pool {
-                req.assume_item(item, 1)
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.item_pool = snapshot.item_pool;
+        self.open_locations = snapshot.open_locations;
+        self.closed_locations = snapshot.closed_locations;
+        self.reach = snapshot.reach;
+        self.assignments = snapshot.assignments;
+    }
+
+    /// Undoes as many recent placements as the history allows; returns false if there are none
+    fn backtrack(&mut self) -> bool {
+        let placements = self.history.len();
+        let oldest = self.history.drain(..).next();
+        match oldest {
+            Some(snapshot) => {
+                self.restore(snapshot);
+                self.backtracks += 1;
+                self.observer
+                    .on_event(&AllocationEvent::Backtracked { placements });
+                true
             }
-            assert!(req == ItemCondition::NoRequirements);
+            None => false,
         }
-        for (_, req) in &self.flags {
-            let mut req = req.clone();
-            for (item, _) in &self.item_pool {
-                req.assume_item(item, 1)
+    }
+
+    fn preflight_check(&self) -> Result<(), RandomizerError> {
+        let mut reach = self.collect_everything();
+        // Only worked out for error messages, since rewriting the conditions is slow
+        let remaining = |reach: &mut ReachabilityEngine, unlock: Unlock| {
+            reach
+                .requirement(&unlock)
+                .expect("The allocator tracks all of its requirements")
+        };
+        match &self.logic.goal {
+            Some(_) if !reach.is_met(&Unlock::Goal) => {
+                let given = if self.fixed.is_empty() {
+                    ""
+                } else {
+                    ", given the fixed placements"
+                };
+                return Err(RandomizerError::ImpossiblePool(format!(
+                    "Goal is unreachable even with every item{}: {}",
+                    given,
+                    remaining(&mut reach, Unlock::Goal)
+                )));
+            }
+            Some(_) => {}
+            None if self.settings.accessibility != Accessibility::Full => {
+                return Err(RandomizerError::MissingGoal);
             }
-            assert!(req == ItemCondition::NoRequirements);
+            None => {}
         }
-        for (_, req) in &self.item_pool {
-            let mut req = req.clone();
-            for (item, _) in &self.item_pool {
-                req.assume_item(item, 1)
+        // Flags are allowed to stay locked (e.g. they depend on a disabled parameter),
+        // but in full accessibility every location and every item must become reachable
+        // with the whole pool
+        let locations = if self.settings.accessibility == Accessibility::Full {
+            &self.logic.locations[..]
+        } else {
+            &[]
+        };
+        for (loc, _) in locations {
+            let unlock = Unlock::Location(loc.clone());
+            if !reach.is_met(&unlock) {
+                return Err(RandomizerError::ImpossiblePool(format!(
+                    "Location {} is unreachable even with every item: {}",
+                    loc,
+                    remaining(&mut reach, unlock)
+                )));
+            }
+        }
+        let items = if self.settings.accessibility == Accessibility::Full {
+            &self.item_pool[..]
+        } else {
+            &[]
+        };
+        for (pool_item, _) in items {
+            let unlock = Unlock::Item(pool_item.clone());
+            if !reach.is_met(&unlock) {
+                return Err(RandomizerError::ImpossiblePool(format!(
+                    "Item {} can never be obtained: {}",
+                    pool_item,
+                    remaining(&mut reach, unlock)
+                )));
             }
-            assert!(req == ItemCondition::NoRequirements);
         }
-        if self.match_category {
+        // Locations with a fixed item have no room for anything from the pool
+        let free_locations: Vec<_> = self
+            .logic
+            .locations
+            .iter()
+            .map(|(loc, _)| loc)
+            .filter(|loc| !self.fixed.contains_key(*loc))
+            .collect();
+        if self.settings.match_category {
             for cat in [
                 ItemCategory::Minor,
                 ItemCategory::Major,
                 ItemCategory::DungeonItem,
             ] {
-                assert_eq!(
-                    self.item_pool
-                        .iter()
-                        .filter_map(|(i, _)| if i.category == cat { Some(1) } else { None })
-                        .count(),
-                    self.locations
-                        .iter()
-                        .filter(|(l, _)| l.category == cat)
-                        .count(),
-                    "Category count mismatch: {:?}",
-                    cat
-                );
+                let items = self
+                    .item_pool
+                    .iter()
+                    .filter(|(i, _)| i.category == cat)
+                    .count();
+                let locations = free_locations.iter().filter(|l| l.category == cat).count();
+                if items > locations {
+                    return Err(RandomizerError::ImpossiblePool(format!(
+                        "{} {:?} items but only {} {:?} locations",
+                        items, cat, locations, cat
+                    )));
+                }
             }
-        } else {
-            assert_eq!(self.item_pool.len(), self.locations.len());
+        } else if self.item_pool.len() > free_locations.len() {
+            return Err(RandomizerError::ImpossiblePool(format!(
+                "{} items but only {} locations",
+                self.item_pool.len(),
+                free_locations.len()
+            )));
+        }
+        let progression = self
+            .item_pool
+            .iter()
+            .filter(|(item, _)| self.progression_items.contains(item))
+            .count();
+        let homes = free_locations.iter().filter(|l| !l.excluded).count();
+        if progression > homes {
+            return Err(RandomizerError::ImpossiblePool(format!(
+                "{} progression items but only {} locations that aren't excluded",
+                progression, homes
+            )));
         }
         let mut restrictions: Vec<_> = self
             .item_pool
@@ -93,72 +345,103 @@ impl Allocator {
             .filter_map(|(item, _)| item.restriction)
             .collect();
         restrictions.sort_unstable();
+        restrictions.dedup();
         for restriction in restrictions {
-            if self.match_category {
+            if self.settings.match_category {
                 for cat in [
                     ItemCategory::Minor,
                     ItemCategory::Major,
                     ItemCategory::DungeonItem,
                 ] {
-                    assert!(
-                        self.item_pool
-                            .iter()
-                            .filter(
-                                |(i, _)| i.category == cat && i.restriction == Some(restriction)
-                            )
-                            .count()
-                            <= self
-                                .locations
-                                .iter()
-                                .filter(|(l, _)| l.category == cat && l.restriction == Some(restriction))
-                                .count(),
-                        "Not enough homes for locations with restriction {} and category {:?}",
-                        restriction,
-                        cat
-                    );
-                }
-            } else {
-                assert!(
-                    self.item_pool
+                    if self
+                        .item_pool
                         .iter()
-                        .filter(|(i, _)| i.restriction == Some(restriction))
+                        .filter(|(i, _)| i.category == cat && i.restriction == Some(restriction))
                         .count()
-                        <= self
-                            .locations
+                        > free_locations
                             .iter()
-                            .filter(|(l, _)| l.restriction == Some(restriction))
-                            .count(),
-                    "Not enough homes for locations with restriction {}",
+                            .filter(|l| l.category == cat && l.restriction == Some(restriction))
+                            .count()
+                    {
+                        return Err(RandomizerError::ImpossiblePool(format!(
+                            "Not enough homes for items with restriction {} and category {:?}",
+                            restriction, cat
+                        )));
+                    }
+                }
+            } else if self
+                .item_pool
+                .iter()
+                .filter(|(i, _)| i.restriction == Some(restriction))
+                .count()
+                > free_locations
+                    .iter()
+                    .filter(|l| l.restriction == Some(restriction))
+                    .count()
+            {
+                return Err(RandomizerError::ImpossiblePool(format!(
+                    "Not enough homes for items with restriction {}",
                     restriction
-                );
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects the whole pool, and the fixed items in every location that opens up along the way
+    fn collect_everything(&self) -> ReachabilityEngine {
+        let mut reach = self.reach.clone();
+        for (item, _) in &self.item_pool {
+            reach.add_item(item);
+        }
+        let mut fixed: Vec<_> = self.fixed.iter().collect();
+        loop {
+            let (reached, rest): (Vec<_>, Vec<_>) = fixed
+                .into_iter()
+                .partition(|(loc, _)| reach.is_met(&Unlock::Location((*loc).clone())));
+            fixed = rest;
+            if reached.is_empty() {
+                break;
+            }
+            for (_, item) in reached {
+                reach.add_item(item);
             }
         }
+        reach
     }
 
     fn find_open_locs(&mut self) {
-        for (location, req) in self.locations.iter() {
-            if req.satisfied() {
-                if !self.assignments.contains_key(location) {
+        let mut reached = vec![];
+        for (location, _) in self.logic.locations.iter() {
+            if self.reach.is_met(&Unlock::Location(location.clone())) {
+                if let Some(item) = self.fixed.get(location) {
+                    reached.push(item.clone());
+                } else if !self.assignments.contains_key(location) {
                     self.open_locations.push(location.clone());
                 }
             } else {
-                self.closed_locations.push((location.clone(), req.clone()));
+                self.closed_locations.insert(location.index);
             }
         }
+        self.collect_items(reached);
     }
 
-    fn single_item_location_unlocks(&self) -> HashMap<Rc<ItemDef>, Vec<Rc<Location>>> {
-        let mut things: HashMap<Rc<ItemDef>, Vec<_>> = HashMap::new();
+    fn single_item_location_unlocks(&self) -> BTreeMap<Arc<ItemDef>, Vec<Arc<Location>>> {
+        let mut things: BTreeMap<Arc<ItemDef>, Vec<_>> = BTreeMap::new();
         for item in self.placeable_items() {
-            for (loc, req) in &self.closed_locations {
-                if req.would_be_satisfied_by(&item) {
-                    let entry = things.entry(item.clone()).or_default();
-                    entry.push(loc.clone());
-                    entry.sort_unstable();
-                    entry.dedup();
+            for unlock in self.reach.unlocked_by(&item) {
+                match unlock {
+                    Unlock::Location(loc) if self.closed_locations.contains(loc.index) => {
+                        things.entry(item.clone()).or_default().push(loc.clone());
+                    }
+                    _ => {}
                 }
             }
         }
+        for locations in things.values_mut() {
+            locations.sort_unstable();
+            locations.dedup();
+        }
         things
     }
 
@@ -173,100 +456,214 @@ impl Allocator {
     fn spare_space_for_restriction(&self, cat: ItemCategory, restriction: Restriction) -> bool {
         self.open_locations
             .iter()
-            .filter(|l| l.category == cat && l.restriction == Some(restriction))
+            .filter(|l| self.counts_as(l.category, cat) && l.restriction == Some(restriction))
             .count()
             > self
                 .item_pool
                 .iter()
-                .filter(|(item, _)| item.category == cat && item.restriction == Some(restriction))
+                .filter(|(item, _)| self.counts_as(item.category, cat) && item.restriction == Some(restriction))
                 .count()
     }
 
+    /// Whether something of category `cat` competes for space in a location of category
+    /// `location`. Without `match_category`, every restricted item can take every location of
+    /// its restriction, so they all do.
+    fn counts_as(&self, cat: ItemCategory, location: ItemCategory) -> bool {
+        !self.settings.match_category || cat == location
+    }
+
     fn can_place_in(&self, item: &ItemDef, loc: &Location) -> bool {
-        if self.match_category && loc.category != item.category {
+        self.placement_allowed(item, loc, |cat, n| self.spare_space_for_restriction(cat, n))
+            && (loc.excluded || self.progression_items.contains(item) || self.spare_junk())
+    }
+
+    /// Whether there's more junk left than excluded locations that still need some; excluded
+    /// locations tend to open late, when it might have all gone elsewhere
+    fn spare_junk(&self) -> bool {
+        let excluded = self
+            .logic
+            .locations
+            .iter()
+            .filter(|(loc, _)| loc.excluded && !self.assignments.contains_key(loc))
+            .count();
+        excluded == 0
+            || self
+                .item_pool
+                .iter()
+                .filter(|(item, _)| !self.progression_items.contains(item))
+                .count()
+                > excluded
+    }
+
+    /// The category and restriction rules every algorithm follows. Items without a restriction
+    /// may only go into a restricted location if `spare_space` says there's room left over.
+    /// Excluded locations only take items that no requirement mentions.
+    fn placement_allowed(
+        &self,
+        item: &ItemDef,
+        loc: &Location,
+        spare_space: impl Fn(ItemCategory, Restriction) -> bool,
+    ) -> bool {
+        let excluded = loc.excluded && self.progression_items.contains(item);
+        if excluded || (self.settings.match_category && loc.category != item.category) {
             false
         } else {
             match (loc.restriction, item.restriction) {
                 (Some(a), Some(b)) if a == b => true,
                 (_, Some(_)) => false,
-                (Some(n), _) if self.spare_space_for_restriction(loc.category, n) => true,
+                (Some(n), _) if spare_space(loc.category, n) => true,
                 (Some(_), _) => false,
                 _ => true,
             }
         }
     }
 
-    fn find_item_home<R: Rng + ?Sized>(&self, item: &ItemDef, rng: &mut R) -> Option<Rc<Location>> {
+    fn find_item_home<R: Rng + ?Sized>(
+        &self,
+        item: &ItemDef,
+        rng: &mut R,
+    ) -> Option<Arc<Location>> {
         let mut locations = self.open_locations.clone();
-        if !self.prefer_new_locations || item.category == ItemCategory::Minor {
+        if !self.settings.prefer_new_locations || item.category == ItemCategory::Minor {
             locations.shuffle(rng);
         }
 
         for loc in locations.iter().rev() {
-            if self.can_place_in(item, &*loc) {
+            if self.can_place_in(item, loc) {
                 return Some(loc.clone());
             }
         }
         None
     }
-    fn place_item(&mut self, item: &Rc<ItemDef>, location: &Rc<Location>) {
+    fn place_item(
+        &mut self,
+        item: &Arc<ItemDef>,
+        location: &Arc<Location>,
+        reason: PlacementReason,
+    ) {
+        if self.backtrack_depth > 0 {
+            self.history.push_back(self.snapshot());
+            if self.history.len() > self.backtrack_depth {
+                self.history.pop_front();
+            }
+        }
+        self.observer.on_event(&AllocationEvent::ItemPlaced {
+            item: item.clone(),
+            location: location.clone(),
+            reason,
+        });
         if location.restriction.is_some() && item.restriction.is_none() {
-            println!(
-                "!!! Placing a non-dungeon item ({}) in a dungeon slot ({})",
-                item, location
-            );
+            self.observer
+                .on_event(&AllocationEvent::NonDungeonItemInDungeon {
+                    item: item.clone(),
+                    location: location.clone(),
+                });
         }
         self.assignments.insert(location.clone(), item.clone());
         self.open_locations.retain(|l| l != location);
-        let mut opened = vec![];
-        for (loc, req) in &mut self.closed_locations {
-            let old_req = req.clone();
-            req.assume_item(item, 1);
-            *req = req.simplify();
-            if req.satisfied() {
-                opened.push(loc.clone());
-                println!("  Unlocked location {}: {}", loc, old_req);
-            }
+        if let Some(idx) = self.item_pool.iter().position(|(i, _)| i == item) {
+            self.item_pool.swap_remove(idx);
         }
-        if !opened.is_empty() {
-            self.open_locations.extend(opened);
+        self.collect_items(vec![item.clone()]);
+    }
+
+    /// Assumes the player has `items`, and opens up whatever they unlock. Fixed items in
+    /// locations that open up are collected in turn.
+    fn collect_items(&mut self, mut items: Vec<Arc<ItemDef>>) {
+        let explain = self.observer.enabled();
+        while let Some(item) = items.pop() {
+            // Working out what each unlock required only pays off if someone is listening
+            let unlocked: Vec<_> = if explain {
+                self.reach
+                    .add_item_explained(&item)
+                    .into_iter()
+                    .map(|(unlock, requirement)| (unlock, Some(requirement)))
+                    .collect()
+            } else {
+                self.reach
+                    .add_item(&item)
+                    .into_iter()
+                    .map(|unlock| (unlock, None))
+                    .collect()
+            };
+            for (unlock, requirement) in unlocked {
+                match unlock {
+                    Unlock::Location(location) => {
+                        if !self.closed_locations.remove(location.index) {
+                            continue;
+                        }
+                        match self.fixed.get(&location) {
+                            Some(fixed) => items.push(fixed.clone()),
+                            None => self.open_locations.push(location.clone()),
+                        }
+                        if let Some(requirement) = requirement {
+                            self.observer.on_event(&AllocationEvent::LocationUnlocked {
+                                location,
+                                requirement,
+                            });
+                        }
+                    }
+                    Unlock::Flag(flag) => {
+                        if let Some(requirement) = requirement {
+                            self.observer
+                                .on_event(&AllocationEvent::FlagUnlocked { flag, requirement });
+                        }
+                    }
+                    Unlock::Goal => self.observer.on_event(&AllocationEvent::GoalReached),
+                    Unlock::Item(_) => {}
+                }
+            }
         }
+    }
 
-        self.closed_locations.retain(|(_, req)| !req.satisfied());
-        if let Some((idx, _)) = self
-            .item_pool
+    /// The locations that can't be reached yet, in the order of the logic
+    fn closed(&self) -> impl Iterator<Item = &Arc<Location>> + '_ {
+        self.closed_locations
             .iter()
-            .enumerate()
-            .find(|(_, (i, _))| i == item)
-        {
-            self.item_pool.swap_remove(idx);
-        }
-        *self.assigned_items.entry(item.clone()).or_default() += 1;
+            .map(|idx| &self.logic.locations[idx].0)
+    }
 
-        for (_, req) in &mut self.item_pool {
-            req.assume_item(item, 1);
-            *req = req.simplify();
+    fn goal_reached(&self) -> bool {
+        self.reach.is_met(&Unlock::Goal)
+    }
+
+    /// Whether the remaining closed locations can be left locked forever. Beatable seeds
+    /// only allow this once every progression item has a home; minimal seeds as soon as the
+    /// goal is reachable.
+    fn can_give_up_on_closed_locations(&self) -> bool {
+        match self.settings.accessibility {
+            Accessibility::Full => false,
+            Accessibility::Beatable => {
+                self.goal_reached()
+                    && !self
+                        .item_pool
+                        .iter()
+                        .any(|(item, _)| self.progression_items.contains(item))
+            }
+            Accessibility::Minimal => self.goal_reached(),
         }
-        for (flag, req) in &mut self.flags {
-            let old_req = req.clone();
-            req.assume_item(item, 1);
-            *req = req.simplify();
-            if *req == ItemCondition::NoRequirements && *req != old_req {
-                println!("  Unlocked flag {}: {}", flag.name, old_req);
+    }
+
+    /// Treats the remaining closed locations as open, so whatever is left can go anywhere
+    fn give_up_on_closed_locations(&mut self) {
+        for idx in self.closed_locations.iter() {
+            let loc = &self.logic.locations[idx].0;
+            if !self.fixed.contains_key(loc) {
+                self.open_locations.push(loc.clone());
             }
         }
-        for (_, req) in &mut self.locations {
-            req.assume_item(item, 1);
-            *req = req.simplify();
+        self.closed_locations.clear();
+        for (item, _) in &self.item_pool {
+            self.reach.release(&Unlock::Item(item.clone()));
         }
     }
 
     fn probably_safe_to_backfill(
         &self,
-        progress_items: &HashSet<Rc<ItemDef>>,
+        progress_items: &HashSet<Arc<ItemDef>>,
         cat: ItemCategory,
     ) -> bool {
-        if self.match_category {
+        if self.settings.match_category {
             progress_items
                 .iter()
                 .filter(|item| item.category == cat && item.restriction.is_none())
@@ -291,15 +688,17 @@ impl Allocator {
         }
     }
 
-    fn progression_affecting_items(&self) -> HashMap<Rc<ItemDef>, usize> {
+    fn progression_affecting_items(&self) -> HashSet<Arc<ItemDef>> {
+        let closed: Vec<_> = self.closed().map(|loc| Unlock::Location(loc.clone())).collect();
         let mut rv = HashMap::new();
-        for (_, req) in &self.closed_locations {
-            req.missing(&mut rv)
-        }
-        rv.into_iter().filter(|&(_, count)| count == 1).collect()
+        self.reach.missing(&closed, &mut rv);
+        rv.into_iter()
+            .filter(|&(_, count)| count == 1)
+            .map(|(item, _)| item.clone())
+            .collect()
     }
 
-    fn placeable_items(&self) -> impl Iterator<Item = Rc<ItemDef>> + '_ {
+    fn placeable_items(&self) -> impl Iterator<Item = Arc<ItemDef>> + '_ {
         let open_restrictions: HashSet<_> = self
             .open_locations
             .iter()
@@ -307,18 +706,17 @@ impl Allocator {
             .collect();
         self.item_pool
             .iter()
-            .filter(move |(item, req)| {
+            .filter(move |(item, _)| {
                 item.restriction
                     .map(|res| open_restrictions.contains(&res))
                     .unwrap_or(true)
-                    && req.satisfied()
+                    && self.reach.is_met(&Unlock::Item(item.clone()))
             })
             .map(|(item, _)| item.clone())
     }
 
     fn backfill<R: Rng + ?Sized>(&mut self, rng: &mut R) {
-        let progress_items: HashSet<_> =
-            self.progression_affecting_items().keys().cloned().collect();
+        let progress_items = self.progression_affecting_items();
         for cat in [
             ItemCategory::Minor,
             ItemCategory::Major,
@@ -333,7 +731,7 @@ impl Allocator {
                             && item.restriction.is_none()
                     })
                     .map(|item| {
-                        let weight = item.weight + rng.gen_range(0..self.temperature);
+                        let weight = item.weight + rng.gen_range(0..self.settings.temperature);
                         (item, weight)
                     })
                     .collect();
@@ -356,17 +754,16 @@ impl Allocator {
                 while self.probably_safe_to_backfill(&progress_items, cat) {
                     if let Some((item, weight)) = placeable.pop() {
                         // Locations are sorted by when they were opened, so we'll try to fill earlier locations first
-                        if let Some(location) = self.open_locations.iter().find(|&loc| {
-                            self.can_place_in(&*item, &**loc) && loc.restriction.is_none()
-                        }) {
+                        if let Some(location) = self
+                            .open_locations
+                            .iter()
+                            .find(|&loc| self.can_place_in(&item, loc) && loc.restriction.is_none())
+                        {
                             let location = location.clone();
-                            println!("Backfilling {} ({}) in {}", item, weight, location);
-                            self.place_item(&item, &location);
+                            self.place_item(&item, &location, PlacementReason::Backfill { weight });
                         } else {
-                            println!(
-                                "(Wanted to backfill {} ({}) but couldn't find a home for it)",
-                                item, weight
-                            );
+                            self.observer
+                                .on_event(&AllocationEvent::BackfillSkipped { item, weight });
                         }
                     } else {
                         break;
@@ -376,95 +773,81 @@ impl Allocator {
         }
     }
     fn allocation_round<R: Rng + ?Sized>(&mut self, rng: &mut R) {
-        // Check that closed locations are still actually closed
-        for (loc, req) in &self.closed_locations {
-            if req.satisfied() {
-                println!("!!! Closed location {} should be open! {}", loc, req);
-            }
+        if !self.closed_locations.is_empty() && self.can_give_up_on_closed_locations() {
+            self.give_up_on_closed_locations();
         }
-
         self.backfill(rng);
-        // And again
-        for (loc, req) in &self.closed_locations {
-            if req.satisfied() {
-                println!("!!!2 Closed location {} should be open! {}", loc, req);
-            }
-        }
+        // Check that closed locations are still actually closed
+        debug_assert!(
+            self.closed()
+                .all(|loc| !self.reach.is_met(&Unlock::Location(loc.clone()))),
+            "Closed location should be open"
+        );
         let unlocks = self.single_item_location_unlocks();
         let mut unlock_items: Vec<_> = unlocks
-            .iter()
-            .map(|(item, _)| (item, item.weight + rng.gen_range(0..self.temperature)))
+            .keys()
+            .map(|item| {
+                (
+                    item,
+                    item.weight + rng.gen_range(0..self.settings.temperature),
+                )
+            })
             .collect();
         unlock_items.sort_unstable_by_key(|(_, weight)| *weight);
 
-        print!("Capabilities: ");
-        for (flag, req) in self.flags.iter() {
-            if req.satisfied() {
-                print!("{} ", flag.name);
-            }
-        }
-        println!();
-
-        // println!("{:?}", unlock_items);
-
         for (item, weight) in unlock_items {
-            if let Some(location) = self.find_item_home(&*item, rng) {
-                println!(
-                    "Placing {} ({}) in {} to unlock new locations",
-                    item, weight, location
-                );
-                // for loc in &unlocks[item] {
-                //     println!(" - {}", loc);
-                // }
-                self.place_item(item, &location);
+            if let Some(location) = self.find_item_home(item, rng) {
+                self.place_item(item, &location, PlacementReason::Unlock { weight });
                 return;
             } else {
-                println!(
-                    "(Wanted to place {} ({}) but couldn't find a home for it)",
-                    item, weight
-                );
+                self.observer
+                    .on_event(&AllocationEvent::PlacementSkipped { item: item.clone() });
             }
         }
 
         // No single item unlocks a new location; pick one that at least shows up in an unsatisfied goal
-        let mut missing_items = HashMap::new();
-        for (_, req) in &self.closed_locations {
-            req.missing(&mut missing_items);
-        }
+        let closed: Vec<_> = self.closed().map(|loc| Unlock::Location(loc.clone())).collect();
+        let mut missing_items = BTreeMap::new();
+        self.reach.missing(&closed, &mut missing_items);
         let (mut restricted_missing_items, mut general_missing_items): (Vec<_>, Vec<_>) =
             missing_items
-                .into_iter()
-                .map(|(item, _)| item)
+                .into_keys()
+                .cloned()
                 .partition(|item| item.restriction.is_some());
         // Nothing missing for locations? Pick a flag instead
-        let mut flag_items = HashMap::new();
-        for (_flag, req) in self.flags.iter() {
-            req.missing(&mut flag_items);
-        }
+        let flags: Vec<_> = self.logic.flags.iter().map(|(flag, _)| Unlock::Flag(flag.clone())).collect();
+        let mut flag_items = BTreeMap::new();
+        self.reach.missing(&flags, &mut flag_items);
+        // Beatable and minimal seeds mostly care about what the goal still needs
+        let mut goal_items = BTreeMap::new();
+        if self.settings.accessibility != Accessibility::Full {
+            self.reach.missing([&Unlock::Goal], &mut goal_items);
+        }
+        let mut goal_items: Vec<_> = goal_items.into_keys().cloned().collect();
         let (mut restricted_flag_items, mut general_flag_items): (Vec<_>, Vec<_>) = flag_items
-            .into_iter()
-            .map(|(item, _)| item)
+            .into_keys()
+            .cloned()
             .partition(|item| item.restriction.is_some());
         restricted_missing_items.shuffle(rng);
         general_missing_items.shuffle(rng);
         restricted_flag_items.shuffle(rng);
         general_flag_items.shuffle(rng);
+        goal_items.shuffle(rng);
 
-        for to_add in restricted_missing_items
+        // Nothing changes until something is placed, and then we're done
+        let placeable_items: Vec<_> = self.placeable_items().collect();
+        for to_add in goal_items
             .into_iter()
+            .chain(restricted_missing_items)
             .chain(restricted_flag_items)
             .chain(general_missing_items)
             .chain(general_flag_items)
         {
             // Find a matching item in the pool
-            let possible_match = self.placeable_items().find(|i| i == &to_add);
-            if let Some(item) = possible_match {
-                if let Some(location) = self.find_item_home(&*item, rng) {
-                    println!(
-                        "Placing {} in {}, hoping that it frees things up",
-                        item, location
-                    );
-                    self.place_item(&item, &location);
+            let possible_match = placeable_items.iter().find(|i| **i == to_add);
+            if let Some(item) = possible_match.cloned() {
+                if let Some(location) = self.find_item_home(&item, rng) {
+                    self.place_item(&item, &location, PlacementReason::Missing);
                     return;
                 } else {
                     if let Some(n) = item.restriction {
@@ -472,24 +855,8 @@ impl Allocator {
                             continue;
                         }
                     }
-                    // if let ItemCategory::Class(n) = item.def.category {
-                    //     println!("Open locations with matching class: ");
-                    //     for loc in &self.open_locations {
-                    //         if loc.category == ItemCategory::Class(n) {
-                    //             println!("  * {}", loc.name);
-                    //         }
-                    //     }
-                    //     println!("Closed locations with matching class: ");
-                    //     for loc in &self.closed_locations {
-                    //         if loc.category == ItemCategory::Class(n) {
-                    //             println!("  * {}", loc.name);
-                    //         }
-                    //     }
-                    // }
-                    println!(
-                        "!! Wanted to place {} but couldn't find space for it!",
-                        item
-                    );
+                    self.observer
+                        .on_event(&AllocationEvent::PlacementSkipped { item });
                 }
             }
         }
@@ -498,245 +865,126 @@ impl Allocator {
         // At this point there *should* only be minor items left
         let placeable_items: Vec<_> = self.placeable_items().collect();
         for item in placeable_items {
-            if let Some(location) = self.find_item_home(&*item, rng) {
-                println!("Placing {} in {}, to fill up space", item, location);
-                self.place_item(&item, &location);
+            if let Some(location) = self.find_item_home(&item, rng) {
+                self.place_item(&item, &location, PlacementReason::Filler);
                 return;
             } else {
-                println!(
-                    "Unable to find home for item {}; location pool size: {}",
-                    item,
-                    self.open_locations.len()
-                );
+                self.observer
+                    .on_event(&AllocationEvent::PlacementSkipped { item });
                 self.item_pool.shuffle(rng);
-                if let Some(n) = item.restriction {
-                    println!("Open locations with matching class: ");
-                    for loc in &self.open_locations {
-                        if loc.restriction == Some(n) {
-                            println!("  * {}", loc);
-                        }
-                    }
-                    println!("Closed locations with matching class: ");
-                    for (loc, req) in &self.closed_locations {
-                        if loc.restriction == Some(n) {
-                            println!("  * {}: {}", loc, req);
-                        }
-                    }
-                }
             }
         }
-        println!("Open locations: ");
-        for loc in &self.open_locations {
-            println!(" - {}", loc);
-        }
-        println!("Unassigned items:");
-        for (item, _) in &self.item_pool {
-            println!(" - {}", item);
-        }
     }
 
-    fn alloc_progress(&self) -> Result<String, std::fmt::Error> {
-        use std::fmt::Write;
+    fn alloc_progress(&self) -> AllocationProgress {
         let mut restrictions: Vec<_> = self
+            .logic
             .locations
             .iter()
             .filter_map(|(l, _)| l.restriction)
             .collect();
         restrictions.sort_unstable();
         restrictions.dedup();
-        let mut s = "Open: ".to_string();
-        write!(
-            &mut s,
-            "Open: {} ({})",
-            self.open_locations.len(),
-            self.open_locations
-                .iter()
-                .filter(|l| l.category == ItemCategory::Major)
-                .count()
-        )?;
-        if !restrictions.is_empty() {
-            write!(&mut s, " [")?;
-            let mut first = true;
-            for &restriction in &restrictions {
-                if !first {
-                    write!(&mut s, ", ")?;
-                }
-                first = false;
-                write!(
-                    &mut s,
-                    "{} ({})",
-                    self.open_locations
-                        .iter()
-                        .filter(|l| l.restriction == Some(restriction))
-                        .count(),
-                    self.open_locations
-                        .iter()
-                        .filter(|l| l.category == ItemCategory::Major
-                            && l.restriction == Some(restriction))
-                        .count()
-                )?;
-            }
-            write!(&mut s, "]")?;
-        }
-        write!(&mut s, "; ")?;
-        write!(
-            &mut s,
-            "Closed: {} ({})",
-            self.closed_locations.len(),
-            self.closed_locations
-                .iter()
-                .filter(|(l, _)| l.category == ItemCategory::Major)
-                .count()
-        )?;
-        if !restrictions.is_empty() {
-            write!(&mut s, " [")?;
-            let mut first = true;
-            for &restriction in &restrictions {
-                if !first {
-                    write!(&mut s, ", ")?;
-                }
-                first = false;
-                write!(
-                    &mut s,
-                    "{} ({})",
-                    self.closed_locations
-                        .iter()
-                        .filter(|(l, _)| l.restriction == Some(restriction))
-                        .count(),
-                    self.closed_locations
-                        .iter()
-                        .filter(|(l, _)| l.category == ItemCategory::Major
-                            && l.restriction == Some(restriction))
-                        .count()
-                )?;
+        let mut progress = AllocationProgress {
+            overall: Default::default(),
+            restrictions: restrictions
+                .into_iter()
+                .map(|r| (r, Default::default()))
+                .collect(),
+        };
+        let mut count = |restriction: Option<Restriction>,
+                         category: ItemCategory,
+                         n: usize,
+                         get: fn(&mut ProgressCounters) -> &mut Counter| {
+            get(&mut progress.overall).add(category, n);
+            if let Some((_, counters)) = progress
+                .restrictions
+                .iter_mut()
+                .find(|(r, _)| Some(*r) == restriction)
+            {
+                get(counters).add(category, n);
             }
-            write!(&mut s, "]")?;
+        };
+        for loc in &self.open_locations {
+            count(loc.restriction, loc.category, 1, |c| &mut c.open_locations);
         }
-        write!(
-            &mut s,
-            "; Unassigned items {} ({})",
-            self.item_pool.len(),
-            self.item_pool
-                .iter()
-                .filter(|(i, _)| i.category == ItemCategory::Major)
-                .count(),
-        )?;
-        if !restrictions.is_empty() {
-            write!(&mut s, " [")?;
-            let mut first = true;
-            for &restriction in &restrictions {
-                if !first {
-                    write!(&mut s, ", ")?;
-                }
-                first = false;
-                write!(
-                    &mut s,
-                    "{} ({})",
-                    self.item_pool
-                        .iter()
-                        .filter(|(i, _)| i.restriction == Some(restriction))
-                        .count(),
-                    self.item_pool
-                        .iter()
-                        .filter(|(i, _)| i.category == ItemCategory::Major
-                            && i.restriction == Some(restriction))
-                        .count()
-                )?;
-            }
-            write!(&mut s, "]")?;
+        for loc in self.closed() {
+            count(loc.restriction, loc.category, 1, |c| {
+                &mut c.closed_locations
+            });
         }
-        write!(
-            &mut s,
-            "; Assigned items {} ({})",
-            self.assigned_items.values().sum::<usize>(),
-            self.assigned_items
-                .iter()
-                .filter_map(|(item, count)| {
-                    if item.category == ItemCategory::Major {
-                        Some(count)
-                    } else {
-                        None
-                    }
-                })
-                .sum::<usize>()
-        )?;
-        if !restrictions.is_empty() {
-            write!(&mut s, " [")?;
-            let mut first = true;
-            for &restriction in &restrictions {
-                if !first {
-                    write!(&mut s, ", ")?;
-                }
-                first = false;
-                write!(
-                    &mut s,
-                    "{} ({})",
-                    self.assigned_items
-                        .iter()
-                        .filter_map(|(item, count)| {
-                            if item.restriction == Some(restriction) {
-                                Some(count)
-                            } else {
-                                None
-                            }
-                        })
-                        .sum::<usize>(),
-                    self.assigned_items
-                        .iter()
-                        .filter_map(|(item, count)| {
-                            if item.category == ItemCategory::Major
-                                && item.restriction == Some(restriction)
-                            {
-                                Some(count)
-                            } else {
-                                None
-                            }
-                        })
-                        .sum::<usize>()
-                )?;
-            }
-            write!(&mut s, "]")?;
+        for (item, _) in &self.item_pool {
+            count(item.restriction, item.category, 1, |c| {
+                &mut c.unassigned_items
+            });
         }
-        //self.assigned_items.values().sum::<usize>()
-        Ok(s)
+        for item in self.assignments.values() {
+            count(item.restriction, item.category, 1, |c| {
+                &mut c.assigned_items
+            });
+        }
+        progress
     }
 
-    pub fn allocate<R: Rng + ?Sized>(&mut self, rng: &mut R) -> HashMap<Rc<Location>, Rc<ItemDef>> {
-        println!("Open locations: ");
-        for loc in &self.open_locations {
-            println!("  * {}", loc);
-        }
-        println!("Closed locations: ");
-        for (loc, req) in &self.closed_locations {
-            println!("  * {} {}", loc, req);
+    pub fn allocate<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<BTreeMap<Arc<Location>, Arc<ItemDef>>, RandomizerError> {
+        for (location, item) in &self.fixed {
+            self.observer.on_event(&AllocationEvent::ItemPlaced {
+                item: item.clone(),
+                location: location.clone(),
+                reason: PlacementReason::Fixed,
+            });
+        }
+        match self.settings.algorithm {
+            Algorithm::Forward => self.forward_fill(rng),
+            Algorithm::Assumed => self.assumed_fill(rng),
         }
+    }
+
+    fn forward_fill<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<BTreeMap<Arc<Location>, Arc<ItemDef>>, RandomizerError> {
         let mut n = 0;
+        let mut rounds_since_backtrack = 0;
         self.item_pool.shuffle(rng);
         while !self.item_pool.is_empty() {
             self.allocation_round(rng);
-            println!("{}", self.alloc_progress().unwrap());
-
             n += 1;
-            if n > 150 {
-                eprintln!("Not making progress; giving up");
-                println!("Closed locations:");
-                for (loc, req) in &self.closed_locations {
-                    println!("  - {}", loc);
-                    print!("    ");
-                    let mut req = req.clone();
-                    for (id, count) in &self.assigned_items {
-                        req.assume_item(id, *count);
-                    }
-                    println!("{}", req);
+            rounds_since_backtrack += 1;
+            let progress = self.alloc_progress();
+            self.observer
+                .on_event(&AllocationEvent::RoundCompleted { round: n, progress });
+
+            if rounds_since_backtrack > MAX_ROUNDS {
+                if self.backtracks < self.max_backtracks && self.backtrack() {
+                    rounds_since_backtrack = 0;
+                    continue;
                 }
-                break;
+                // Show what's missing even with the items that were placed out of reach
+                let closed_locations = self
+                    .logic
+                    .locations
+                    .iter()
+                    .filter(|(loc, _)| self.closed_locations.contains(loc.index))
+                    .map(|(loc, req)| {
+                        let mut req = req.clone();
+                        for item in self.assignments.values() {
+                            req.assume_item(item, 1);
+                        }
+                        (loc.clone(), req)
+                    })
+                    .collect();
+                self.observer
+                    .on_event(&AllocationEvent::Stuck { closed_locations });
+                return Err(RandomizerError::AllocationFailed {
+                    rounds: n,
+                    unplaced: self.item_pool.len(),
+                });
             }
         }
-        println!("\n\nAssignments: ");
-        for (loc, item) in &self.assignments {
-            println!("  {} -> {}", loc, item);
-        }
-
-        self.assignments.clone()
+        Ok(self.assignments.clone())
     }
 }
@@ -0,0 +1,232 @@
+use super::*;
+
+/// Where a playthrough can get to, given some starting items
+struct Sweep {
+    reachable: HashSet<Arc<Location>>,
+    /// Everything collected along the way
+    reach: ReachabilityEngine,
+}
+
+impl Allocator<'_> {
+    /// Assumed fill: progression items are placed one at a time, each into a location that can
+    /// be reached with every still unplaced item assumed. Everything else is then scattered over
+    /// whatever locations are left. Item weights don't apply here; `prefer_new_locations` keeps
+    /// progression out of the locations that are open from the start while there's a choice.
+    ///
+    /// Every placement keeps the seed completable, but an item can run out of homes if the
+    /// locations it could go in were filled earlier. When that happens we start over with that
+    /// item placed earlier, which converges quickly in practice.
+    pub(super) fn assumed_fill<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<BTreeMap<Arc<Location>, Arc<ItemDef>>, RandomizerError> {
+        let initial_pool = self.item_pool.clone();
+        // Items that ran out of homes before, most recent first
+        let mut priority: Vec<Arc<ItemDef>> = vec![];
+        let max_restarts = 4 * initial_pool.len();
+        for _ in 0..max_restarts {
+            let item = match self.assumed_fill_pass(rng, &priority) {
+                Ok(()) => return Ok(self.assignments.clone()),
+                Err(item) => item,
+            };
+            self.observer
+                .on_event(&AllocationEvent::AssumedFillRestarted { item: item.clone() });
+            priority.retain(|i| i != &item);
+            priority.insert(0, item);
+            self.item_pool = initial_pool.clone();
+            self.assignments = self.fixed.clone();
+        }
+
+        let mut sweep = self.sweep(&[]);
+        let closed_locations = self
+            .logic
+            .locations
+            .iter()
+            .filter(|(loc, _)| !sweep.reachable.contains(loc))
+            .map(|(loc, _)| {
+                let req = sweep.reach.requirement(&Unlock::Location(loc.clone()));
+                (loc.clone(), req.expect("Every location is tracked").clone())
+            })
+            .collect();
+        self.observer
+            .on_event(&AllocationEvent::Stuck { closed_locations });
+        Err(RandomizerError::AllocationFailed {
+            rounds: max_restarts,
+            unplaced: self.item_pool.len(),
+        })
+    }
+
+    /// Places every item, or returns the first one that had nowhere to go
+    fn assumed_fill_pass<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+        priority: &[Arc<ItemDef>],
+    ) -> Result<(), Arc<ItemDef>> {
+        self.item_pool.shuffle(rng);
+        let (mut progression, mut filler): (Vec<_>, Vec<_>) = self
+            .item_pool
+            .iter()
+            .map(|(item, _)| item.clone())
+            .partition(|item| self.progression_items.contains(item));
+        let placed_early = |item: &Arc<ItemDef>| {
+            priority
+                .iter()
+                .position(|i| i == item)
+                .map_or(0, |p| priority.len() - p)
+        };
+        // Items that got stuck before go first, then restricted items, since they have the
+        // fewest possible homes. Items with requirements go before the items they depend on,
+        // so those are still assumed when looking for a home.
+        // We pop from the end, so the first items to place go at the back.
+        progression.sort_by_key(|item| {
+            (
+                placed_early(item),
+                item.restriction.is_some(),
+                !self.reach.is_met(&Unlock::Item(item.clone())),
+            )
+        });
+        filler.sort_by_key(|item| (placed_early(item), item.restriction.is_some()));
+
+        // Locations reachable with nothing assumed, for `prefer_new_locations`. Placing an item
+        // outside them opens none of them, so they only need working out again after a
+        // placement inside.
+        let mut shallow: Option<HashSet<Arc<Location>>> = None;
+        while let Some(item) = progression.pop() {
+            let sweep = self.sweep(&progression);
+            let obtainable = sweep.reach.is_met(&Unlock::Item(item.clone()));
+            let homes: Vec<_> = self
+                .logic
+                .locations
+                .iter()
+                .map(|(loc, _)| loc)
+                .filter(|loc| {
+                    obtainable
+                        && sweep.reachable.contains(*loc)
+                        && !self.assignments.contains_key(*loc)
+                        && self.placement_allowed(&item, loc, |cat, n| self.spare_space(cat, n))
+                })
+                .cloned()
+                .collect();
+            let homes = if self.settings.prefer_new_locations {
+                let shallow = shallow.get_or_insert_with(|| self.sweep(&[]).reachable);
+                let deep: Vec<_> = homes
+                    .iter()
+                    .filter(|loc| !shallow.contains(*loc))
+                    .cloned()
+                    .collect();
+                if deep.is_empty() {
+                    homes
+                } else {
+                    deep
+                }
+            } else {
+                homes
+            };
+            match homes.choose(rng) {
+                Some(location) => {
+                    if shallow.as_ref().is_some_and(|shallow| shallow.contains(location)) {
+                        shallow = None;
+                    }
+                    self.record_placement(&item, location, PlacementReason::Assumed)
+                }
+                None => return Err(item),
+            }
+        }
+
+        while let Some(item) = filler.pop() {
+            let homes: Vec<_> = self
+                .logic
+                .locations
+                .iter()
+                .map(|(loc, _)| loc)
+                .filter(|loc| {
+                    !self.assignments.contains_key(*loc)
+                        && self.placement_allowed(&item, loc, |cat, n| self.spare_space(cat, n))
+                })
+                .cloned()
+                .collect();
+            match homes.choose(rng) {
+                Some(location) => self.record_placement(&item, location, PlacementReason::Filler),
+                None => return Err(item),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a restriction has more empty locations than unplaced items that need them,
+    /// counting only the category when categories must match
+    fn spare_space(&self, cat: ItemCategory, restriction: Restriction) -> bool {
+        self.logic
+            .locations
+            .iter()
+            .filter(|(l, _)| {
+                self.counts_as(l.category, cat)
+                    && l.restriction == Some(restriction)
+                    && !self.assignments.contains_key(l)
+            })
+            .count()
+            > self
+                .item_pool
+                .iter()
+                .filter(|(item, _)| self.counts_as(item.category, cat) && item.restriction == Some(restriction))
+                .count()
+    }
+
+    /// Starting with `assumed`, collect every placed item that can be reached
+    fn sweep(&self, assumed: &[Arc<ItemDef>]) -> Sweep {
+        // Start from scratch; fixed items that are already collected get picked up again
+        let mut reach = self.reach.restart();
+        for item in assumed {
+            reach.add_item(item);
+        }
+
+        let mut reachable = HashSet::new();
+        // Items in reachable locations that can't be obtained yet
+        let mut pending: Vec<Arc<ItemDef>> = vec![];
+        let mut unlocked: Vec<_> = reach.unlocked().cloned().collect();
+        while !unlocked.is_empty() {
+            for unlock in unlocked.drain(..) {
+                if let Unlock::Location(loc) = unlock {
+                    if let Some(item) = self.assignments.get(&loc) {
+                        pending.push(item.clone());
+                    }
+                    reachable.insert(loc);
+                }
+            }
+            // Items whose every copy is fixed aren't tracked; like the checker, ignore their requirements
+            let (ready, waiting): (Vec<_>, Vec<_>) = pending.into_iter().partition(|item| {
+                let unlock = Unlock::Item(item.clone());
+                !reach.tracks(&unlock) || reach.is_met(&unlock)
+            });
+            pending = waiting;
+            for item in ready {
+                unlocked.extend(reach.add_item(&item));
+            }
+        }
+        Sweep { reachable, reach }
+    }
+
+    fn record_placement(
+        &mut self,
+        item: &Arc<ItemDef>,
+        location: &Arc<Location>,
+        reason: PlacementReason,
+    ) {
+        self.observer.on_event(&AllocationEvent::ItemPlaced {
+            item: item.clone(),
+            location: location.clone(),
+            reason,
+        });
+        if location.restriction.is_some() && item.restriction.is_none() {
+            self.observer
+                .on_event(&AllocationEvent::NonDungeonItemInDungeon {
+                    item: item.clone(),
+                    location: location.clone(),
+                });
+        }
+        self.assignments.insert(location.clone(), item.clone());
+        if let Some(idx) = self.item_pool.iter().position(|(i, _)| i == item) {
+            self.item_pool.swap_remove(idx);
+        }
+    }
+}
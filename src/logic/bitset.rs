@@ -0,0 +1,72 @@
+/// A set of indices below a size fixed up front, such as [`Location::index`](super::Location::index)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitSet {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitSet {
+    /// An empty set with room for indices below `size`
+    pub fn new(size: usize) -> Self {
+        BitSet {
+            words: vec![0; size.div_ceil(64)],
+            len: 0,
+        }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.words
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+
+    /// Adds an index, and returns whether it wasn't there yet
+    pub fn insert(&mut self, index: usize) -> bool {
+        let word = &mut self.words[index / 64];
+        let bit = 1 << (index % 64);
+        let added = *word & bit == 0;
+        *word |= bit;
+        self.len += added as usize;
+        added
+    }
+
+    /// Removes an index, and returns whether it was there
+    pub fn remove(&mut self, index: usize) -> bool {
+        let Some(word) = self.words.get_mut(index / 64) else {
+            return false;
+        };
+        let bit = 1 << (index % 64);
+        let removed = *word & bit != 0;
+        *word &= !bit;
+        self.len -= removed as usize;
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+        self.len = 0;
+    }
+
+    /// Every index in the set, smallest first
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(i * 64 + bit)
+            })
+        })
+    }
+}
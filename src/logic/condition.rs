@@ -1,10 +1,39 @@
-use std::{collections::{HashMap, HashSet}, rc::Rc, vec};
+use std::{cell::RefCell, cmp::Ordering, collections::{hash_map::DefaultHasher, BTreeMap, HashMap}, hash::{Hash, Hasher}, sync::{Arc, LazyLock, Mutex, PoisonError, Weak}};
 use joinery::JoinableIterator;
+use serde::Serialize;
 
 use super::{ItemId, FlagId, LocationId, ItemDef, PreLogic};
 
-pub type Conditional<T> = (Rc<T>, ItemCondition);
+pub type Conditional<T> = (Arc<T>, ItemCondition);
 pub type Conditionals<T> = Vec<Conditional<T>>;
+/// Collected items and how many of each, counted by [`ItemDef::index`]. The counts are sized
+/// for one build up front; see [`Logic::inventory`](super::Logic::inventory).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inventory {
+    counts: Box<[usize]>,
+}
+
+impl Inventory {
+    /// An empty inventory for items with indices below `items`
+    pub fn new(items: usize) -> Self {
+        Inventory {
+            counts: vec![0; items].into_boxed_slice(),
+        }
+    }
+    /// How many of the item have been collected. Only the index is looked at, so items from
+    /// another build get the count of whatever item shares it.
+    pub fn count(&self, item: &ItemDef) -> usize {
+        self.counts.get(item.index).copied().unwrap_or(0)
+    }
+    /// Panics if the item's index is out of range
+    pub fn add(&mut self, item: &ItemDef, count: usize) {
+        self.counts[item.index] += count;
+    }
+}
+/// A multiset of items, in a fixed order
+pub type ItemSet = BTreeMap<Arc<ItemDef>, usize>;
+/// Item counts by [`ItemDef::index`], for working out what conditions have in common
+type Counts = BTreeMap<usize, usize>;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Condition {
@@ -12,50 +41,249 @@ pub enum Condition {
     Flag(FlagId),
     Item(ItemId, usize),
     Parameter(String),
-    AtLeast(usize, Vec<(ItemId, usize)>),
+    /// Compares the numeric value of a parameter; see `ParameterType::number`
+    Compare(String, Comparison, i64),
+    AtLeast(Threshold, Vec<(ItemId, usize)>),
     Location(LocationId),
     And(Vec<Self>),
     Or(Vec<Self>),
+    /// Only allowed on static conditions; see `is_static`
+    Not(Box<Self>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn holds(self, lhs: i64, rhs: i64) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
 }
 
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        })
+    }
+}
+
+/// How many items an `AtLeast` condition needs
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum ItemCondition {
+pub enum Threshold {
+    Fixed(usize),
+    /// The value of an integer parameter
+    Parameter(String),
+}
+
+/// A requirement in terms of items only, after flags, locations and parameters are expanded.
+///
+/// Conditions are hash-consed: every node is built through the constructors below, which keep
+/// it in a canonical form (flattened, sorted, deduplicated, with absorbed terms dropped) and
+/// share it with every structurally equal condition, on any thread. Equality and hashing are
+/// therefore by pointer, and assuming items is memoized per node.
+#[derive(Clone)]
+pub struct ItemCondition(Arc<ConditionNode>);
+
+/// The shape of an `ItemCondition`; match on `&*condition` to look inside one
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum ConditionNode {
     NoRequirements,
     Unattainable,
-    Item(Rc<ItemDef>, usize),
-    AtLeast(usize, Vec<(Rc<ItemDef>, usize)>),
-    And(Vec<Self>),
-    Or(Vec<Self>),
+    Item(Arc<ItemDef>, usize),
+    AtLeast(usize, Vec<(Arc<ItemDef>, usize)>),
+    And(Vec<ItemCondition>),
+    Or(Vec<ItemCondition>),
+}
+
+/// Every condition that's alive, split into shards so that threads building conditions at
+/// the same time rarely wait on each other. The table only holds weak references, so a
+/// condition is freed as soon as nothing else uses it, and its entry is pruned later on.
+static NODES: LazyLock<[Mutex<Shard>; SHARDS]> = LazyLock::new(Default::default);
+const SHARDS: usize = 32;
+/// How many entries a shard starts pruning dead ones at
+const MIN_PRUNE: usize = 64;
+
+#[derive(Default)]
+struct Shard {
+    nodes: HashMap<NodeKey, Weak<ConditionNode>>,
+    prune_at: usize,
+}
+
+/// A node as the interning table sees it, with children by address. Children live as long
+/// as their parent, so an entry whose node is still alive can't be mistaken for another.
+#[derive(PartialEq, Eq, Hash)]
+enum NodeKey {
+    NoRequirements,
+    Unattainable,
+    Item(Arc<ItemDef>, usize),
+    AtLeast(usize, Vec<(Arc<ItemDef>, usize)>),
+    And(Vec<usize>),
+    Or(Vec<usize>),
+}
+
+impl NodeKey {
+    fn of(node: &ConditionNode) -> Self {
+        let addresses = |conds: &[ItemCondition]| conds.iter().map(|c| Arc::as_ptr(&c.0) as usize).collect();
+        match node {
+            ConditionNode::NoRequirements => NodeKey::NoRequirements,
+            ConditionNode::Unattainable => NodeKey::Unattainable,
+            ConditionNode::Item(item, count) => NodeKey::Item(item.clone(), *count),
+            ConditionNode::AtLeast(threshold, items) => NodeKey::AtLeast(*threshold, items.clone()),
+            ConditionNode::And(conds) => NodeKey::And(addresses(conds)),
+            ConditionNode::Or(conds) => NodeKey::Or(addresses(conds)),
+        }
+    }
+
+    fn shard(&self) -> &'static Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        &NODES[hasher.finish() as usize % SHARDS]
+    }
+}
+
+thread_local! {
+    static MEMO: RefCell<Memo> = RefCell::default();
+}
+
+/// How many answers each memo table keeps before it starts over
+const MEMO_LIMIT: usize = 1 << 16;
+
+/// What's been worked out about conditions on this thread. The answers would hold on any
+/// thread, but keeping them apart means lookups don't wait on each other. The tables keep
+/// the conditions in them alive, so they're bounded, and [`ItemCondition::clear_memo`]
+/// empties them once a seed is done.
+#[derive(Default)]
+struct Memo {
+    assumed: HashMap<(ItemCondition, Arc<ItemDef>, usize), ItemCondition>,
+    implies: HashMap<(ItemCondition, ItemCondition), bool>,
+    given: HashMap<(ItemCondition, ItemCondition), ItemCondition>,
+}
+
+fn remember<K: Eq + Hash, V>(table: &mut HashMap<K, V>, key: K, value: V) {
+    if table.len() >= MEMO_LIMIT {
+        table.clear();
+    }
+    table.insert(key, value);
+}
+
+impl std::ops::Deref for ItemCondition {
+    type Target = ConditionNode;
+    fn deref(&self) -> &ConditionNode {
+        &self.0
+    }
+}
+
+impl PartialEq for ItemCondition {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for ItemCondition {}
+
+impl std::hash::Hash for ItemCondition {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::ptr::hash(Arc::as_ptr(&self.0), state)
+    }
+}
+
+/// Ordered by structure rather than by address, so canonical forms don't depend on the order
+/// conditions were built in
+impl Ord for ItemCondition {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self == other {
+            Ordering::Equal
+        } else {
+            self.0.cmp(&other.0)
+        }
+    }
+}
+impl PartialOrd for ItemCondition {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Debug for ItemCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for ItemCondition {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
 }
 
 
 impl Condition {
-    pub fn expand<'a>(&'a self, prelogic: &'a PreLogic, condition_cache: &mut HashMap<&'a Condition, ItemCondition>, item_cache: &HashMap<ItemId, Rc<ItemDef>>) -> ItemCondition {
+    /// Whether the condition is settled by parameters alone, before any items are collected.
+    /// Only these can be negated, so that expanded conditions never depend on *not* having an item.
+    pub fn is_static(&self) -> bool {
+        match self {
+            Condition::NoRequirements | Condition::Parameter(_) | Condition::Compare(..) => true,
+            Condition::Not(cond) => cond.is_static(),
+            Condition::And(conds) | Condition::Or(conds) => conds.iter().all(|c| c.is_static()),
+            Condition::Flag(_) | Condition::Item(_, _) | Condition::AtLeast(_, _) | Condition::Location(_) => false,
+        }
+    }
+    pub fn expand<'a>(&'a self, prelogic: &'a PreLogic, condition_cache: &mut HashMap<&'a Condition, ItemCondition>, item_cache: &HashMap<ItemId, Arc<ItemDef>>) -> ItemCondition {
         if let Some(condition) = condition_cache.get(self) {
             condition.clone()
         } else {
             let condition = match self {
-                Condition::NoRequirements => ItemCondition::NoRequirements,
+                Condition::NoRequirements => ItemCondition::no_requirements(),
                 Condition::Flag(id) => {
                     let flag = prelogic.get_flag(*id).unwrap();
                     flag.requirement.expand(prelogic, condition_cache, item_cache)
                 },
-                Condition::AtLeast(count, items) => ItemCondition::AtLeast(
-                    *count,
-                    items.iter().map(|(id, weight)| (item_cache[id].clone(), *weight)).collect()
-                ),
-                Condition::Parameter(name) => if prelogic.get_parameter(name) { ItemCondition::NoRequirements } else { ItemCondition::Unattainable },
-                Condition::Item(id, count) => ItemCondition::Item(item_cache[id].clone(), *count),
+                Condition::AtLeast(threshold, items) => {
+                    let count = match threshold {
+                        Threshold::Fixed(count) => *count,
+                        Threshold::Parameter(name) => prelogic.parameter_number(name).unwrap_or_default().max(0) as usize,
+                    };
+                    ItemCondition::at_least(count, items.iter().map(|(id, weight)| (item_cache[id].clone(), *weight)).collect())
+                },
+                Condition::Parameter(name) => if prelogic.parameter_enabled(name) { ItemCondition::no_requirements() } else { ItemCondition::unattainable() },
+                Condition::Compare(name, comparison, value) => match prelogic.parameter_number(name) {
+                    Some(n) if comparison.holds(n, *value) => ItemCondition::no_requirements(),
+                    _ => ItemCondition::unattainable(),
+                },
+                Condition::Item(id, count) => ItemCondition::item(item_cache[id].clone(), *count),
                 Condition::Location(id) => {
                     let location = prelogic.get_location(*id).unwrap();
                     location.requirement.expand(prelogic, condition_cache, item_cache)
                 }
                 Condition::And(conds) => {
-                    ItemCondition::And(conds.iter().map(|c| c.expand(prelogic, condition_cache, item_cache)).collect())
+                    ItemCondition::and(conds.iter().map(|c| c.expand(prelogic, condition_cache, item_cache)))
+                },
+                Condition::Or(conds) => ItemCondition::or(conds.iter().map(|c| c.expand(prelogic, condition_cache, item_cache))),
+                Condition::Not(cond) => match &*cond.expand(prelogic, condition_cache, item_cache) {
+                    ConditionNode::NoRequirements => ItemCondition::unattainable(),
+                    ConditionNode::Unattainable => ItemCondition::no_requirements(),
+                    other => unreachable!("Negated condition depends on items: {:?}", other),
                 },
-                Condition::Or(conds) => ItemCondition::Or(conds.iter().map(|c| c.expand(prelogic, condition_cache, item_cache)).collect()),
             };
-            let condition = condition.simplify().flatten();
             condition_cache.insert(self, condition.clone());
             condition
         }
@@ -63,30 +291,30 @@ impl Condition {
 }
 impl ItemCondition {
     pub fn complexity(&self) -> u32 {
-        match self {
-            Self::NoRequirements | Self::Unattainable => 0,
-            Self::Item(_, _) => 1,
-            Self::AtLeast(_, items) => items.len() as _,
-            Self::And(conds) => conds.iter().map(|c| c.complexity()).sum::<u32>() + 1,
-            Self::Or(conds) => conds.iter().map(|c| c.complexity()).min().unwrap_or(0) + 1
+        match &**self {
+            ConditionNode::NoRequirements | ConditionNode::Unattainable => 0,
+            ConditionNode::Item(_, _) => 1,
+            ConditionNode::AtLeast(_, items) => items.len() as _,
+            ConditionNode::And(conds) => conds.iter().map(|c| c.complexity()).sum::<u32>() + 1,
+            ConditionNode::Or(conds) => conds.iter().map(|c| c.complexity()).min().unwrap_or(0) + 1
         }
     }
-    pub fn missing(&self, rv: &mut HashMap<Rc<ItemDef>, usize>) {
+    /// Adds the items the condition mentions to `rv`, each with how many of it the mention asks
+    /// for; later mentions replace earlier ones.
+    pub fn missing<'a>(&'a self, rv: &mut impl Extend<(&'a Arc<ItemDef>, usize)>) {
         // if !self.satisfied(items) {
-            match self {
-                ItemCondition::NoRequirements | Self::Unattainable => {},
-                ItemCondition::Item(id, count) => {rv.insert(id.clone(), *count);}
-                ItemCondition::AtLeast(threshold, req_items) => {
-                    for (id, weight) in req_items {
-                        rv.insert(id.clone(), (threshold + weight - 1) / weight);
-                    }                    
+            match &**self {
+                ConditionNode::NoRequirements | ConditionNode::Unattainable => {},
+                ConditionNode::Item(id, count) => rv.extend([(id, *count)]),
+                ConditionNode::AtLeast(threshold, req_items) => {
+                    rv.extend(req_items.iter().map(|(id, weight)| (id, threshold.div_ceil(*weight))));
                 }
-                ItemCondition::And(conds) => {
+                ConditionNode::And(conds) => {
                     for cond in conds {
                         cond.missing(rv);
                     }
                 },
-                ItemCondition::Or(conds) => {
+                ConditionNode::Or(conds) => {
                     for cond in conds {
                         cond.missing(rv);
                     }
@@ -95,234 +323,306 @@ impl ItemCondition {
         // }
         
     }
-    pub fn flatten(&self) -> Self {
-        match self {
-            Self::NoRequirements | Self::Unattainable  => self.clone(),
-            Self::Item(_, _) => self.clone(),
-            Self::AtLeast(_, _) => self.clone(),
-            Self::And(conds) => {
-                let mut new_conds = vec![];
-                for cond in conds {
-                    let new_cond = cond.flatten();
-                    match new_cond {
-                        Self::And(conds) => new_conds.extend(conds),
-                        _ => new_conds.push(new_cond)
-                    }
-                }
-                new_conds.sort();
-                new_conds.dedup();
-                match new_conds.len() {
-                    0 => Self::NoRequirements,
-                    1 => new_conds.pop().unwrap(),
-                    _ => Self::And(new_conds)
-                }
-            },
-            Self::Or(conds) => {
-                let mut new_conds = vec![];
-                for cond in conds {
-                    let new_cond = cond.flatten();
-                    match new_cond {
-                        Self::Or(conds) => new_conds.extend(conds),
-                        _ => new_conds.push(new_cond)
-                    }
-                }
-                new_conds.sort();
-                new_conds.dedup();
-                match new_conds.len() {
-                    0 => Self::Unattainable,
-                    1 => new_conds.pop().unwrap(),
-                    _ => Self::Or(new_conds)
-                }
-            }
+    fn intern(node: ConditionNode) -> Self {
+        let key = NodeKey::of(&node);
+        // Nothing panics while the lock is held, so a poisoned shard is still whole
+        let mut shard = key.shard().lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(node) = shard.nodes.get(&key).and_then(Weak::upgrade) {
+            return ItemCondition(node);
+        }
+        if shard.nodes.len() >= shard.prune_at {
+            shard.nodes.retain(|_, node| node.strong_count() > 0);
+            shard.prune_at = (2 * shard.nodes.len()).max(MIN_PRUNE);
+        }
+        let node = Arc::new(node);
+        shard.nodes.insert(key, Arc::downgrade(&node));
+        ItemCondition(node)
+    }
+    /// Forgets what's been worked out about conditions on this thread, so that the conditions
+    /// only the memo still holds on to are freed
+    pub fn clear_memo() {
+        MEMO.with(|memo| *memo.borrow_mut() = Memo::default());
+    }
+    /// How many conditions are alive that `f` picks out
+    #[cfg(test)]
+    pub(crate) fn live_nodes(f: impl Fn(&ConditionNode) -> bool) -> usize {
+        let shards = NODES.iter().map(|shard| shard.lock().unwrap_or_else(PoisonError::into_inner));
+        shards.map(|shard| shard.nodes.values().filter_map(Weak::upgrade).filter(|node| f(node)).count()).sum()
+    }
+    pub fn no_requirements() -> Self {
+        Self::intern(ConditionNode::NoRequirements)
+    }
+    pub fn unattainable() -> Self {
+        Self::intern(ConditionNode::Unattainable)
+    }
+    pub fn item(item: Arc<ItemDef>, count: usize) -> Self {
+        if count == 0 {
+            Self::no_requirements()
+        } else {
+            Self::intern(ConditionNode::Item(item, count))
+        }
+    }
+    /// Items are merged and sorted, and a threshold that a single item meets on its own
+    /// becomes a plain item count or a choice of items
+    pub fn at_least(threshold: usize, items: Vec<(Arc<ItemDef>, usize)>) -> Self {
+        if threshold == 0 {
+            return Self::no_requirements();
+        }
+        let mut weights: BTreeMap<Arc<ItemDef>, usize> = BTreeMap::new();
+        for (item, weight) in items.into_iter().filter(|(_, weight)| *weight > 0) {
+            let entry = weights.entry(item).or_default();
+            *entry = (*entry + weight).min(threshold);
+        }
+        if weights.len() == 1 {
+            let (item, weight) = weights.pop_first().expect("There's exactly one item");
+            Self::item(item, threshold.div_ceil(weight))
+        } else if weights.values().all(|weight| *weight == threshold) {
+            Self::or(weights.into_keys().map(|item| Self::item(item, 1)))
+        } else {
+            Self::intern(ConditionNode::AtLeast(threshold, weights.into_iter().collect()))
         }
     }
-    fn remove_redundant_ors(&mut self, or: &[Self]) -> bool {
+    /// Drops whatever the other conditions already imply, so `A & (A | B)` is just `A`
+    pub fn and(conds: impl IntoIterator<Item = Self>) -> Self {
+        let mut flat = vec![];
+        for cond in conds {
+            match &*cond {
+                ConditionNode::NoRequirements => {}
+                ConditionNode::Unattainable => return Self::unattainable(),
+                ConditionNode::And(conds) => flat.extend(conds.iter().cloned()),
+                _ => flat.push(cond),
+            }
+        }
+        flat.sort();
+        flat.dedup();
         let mut changed = false;
-        match self {
-            Self::And(conds) => {
-                for cond in &mut *conds {
-                    changed |= cond.remove_redundant_ors(or);
+        let mut guaranteed: Vec<_> = flat.iter().map(|cond| cond.guaranteed()).collect();
+        for i in 0..flat.len() {
+            let mut cond = flat[i].clone();
+            for (j, fact) in flat.iter().enumerate() {
+                if i != j {
+                    cond = cond.given(fact);
                 }
-                if changed {
-                    conds.retain(|c| *c != Self::NoRequirements);
-                    match conds.len() {
-                        0 => *self = Self::NoRequirements,
-                        1 => *self = conds.pop().expect("Looked before we lept"),
-                        _ => {}
-                    }
-                }
-            },
-            Self::Or(conds) => {
-                if or.iter().all(|c| conds.contains(c)) {
-                    *self = Self::NoRequirements;
-                    changed = true;
+            }
+            // The other items may be enough together, as in `A & B & (+2, A, B, C)`
+            let held = |item: &ItemDef| {
+                let others = guaranteed.iter().enumerate().filter(|&(j, _)| j != i);
+                others.filter_map(|(_, counts)| counts.get(&item.index).copied()).max().unwrap_or(0)
+            };
+            if cond.met_by(held) {
+                cond = Self::no_requirements();
+            }
+            if cond != flat[i] {
+                guaranteed[i] = cond.guaranteed();
+                flat[i] = cond;
+                changed = true;
+            }
+        }
+        if changed {
+            return Self::and(flat);
+        }
+        match flat.len() {
+            0 => Self::no_requirements(),
+            1 => flat.pop().expect("Looked before we lept"),
+            _ => Self::intern(ConditionNode::And(flat)),
+        }
+    }
+    /// Drops whatever implies one of the other choices, so `A | (A & B)` is just `A`
+    pub fn or(conds: impl IntoIterator<Item = Self>) -> Self {
+        let mut flat = vec![];
+        for cond in conds {
+            match &*cond {
+                ConditionNode::NoRequirements => return Self::no_requirements(),
+                ConditionNode::Unattainable => {}
+                ConditionNode::Or(conds) => flat.extend(conds.iter().cloned()),
+                _ => flat.push(cond),
+            }
+        }
+        flat.sort();
+        flat.dedup();
+        let mut i = 0;
+        while i < flat.len() {
+            if (0..flat.len()).any(|j| i != j && flat[i].implies(&flat[j])) {
+                flat.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        match flat.len() {
+            0 => Self::unattainable(),
+            1 => flat.pop().expect("Looked before we lept"),
+            _ => Self::intern(ConditionNode::Or(flat)),
+        }
+    }
+    /// Rebuilds an `And` or `Or` from new children, or returns the node itself if none changed
+    fn map_children(&self, f: impl Fn(&Self) -> Self) -> Self {
+        match &**self {
+            ConditionNode::And(conds) | ConditionNode::Or(conds) => {
+                let new_conds: Vec<_> = conds.iter().map(f).collect();
+                if new_conds == *conds {
+                    self.clone()
+                } else if matches!(**self, ConditionNode::And(_)) {
+                    Self::and(new_conds)
                 } else {
-                    for cond in &mut *conds {
-                        changed |= cond.remove_redundant_ors(or);
-                    }
-                    if changed {
-                        conds.retain(|c| *c != Self::NoRequirements);
-                        match conds.len() {
-                            0 => *self = Self::NoRequirements,
-                            1 => *self = conds.pop().expect("Looked before we lept"),
-                            _ => {}
-                        }
-                    }
+                    Self::or(new_conds)
                 }
             }
-            _ => {}
+            _ => self.clone(),
         }
-        changed
     }
-    pub fn simplify(&self) -> Self {
-        match self {
-            Self::NoRequirements | Self::Unattainable  => self.clone(),
-            Self::Item(_, _) => self.clone(),
-            Self::AtLeast(_, _) => self.clone(),
-            Self::And(conds) => {
-                let mut new_conds = vec![];
+    /// The items every way of meeting the condition needs
+    fn guaranteed(&self) -> Counts {
+        match &**self {
+            ConditionNode::Item(item, count) => Counts::from([(item.index, *count)]),
+            ConditionNode::And(conds) => {
+                let mut rv = Counts::new();
                 for cond in conds {
-                    let new_cond = cond.flatten();
-                    match new_cond {
-                        Self::And(conds) => new_conds.extend(conds),
-                        _ => new_conds.push(new_cond)
-                    }
-                }
-                let items: Vec<_> = new_conds.iter().filter_map(|c| match c { Self::Item(id, count) => Some((id.clone(), *count)), _ => None }).collect();
-                for (item, count) in items {
-                    for cond in &mut new_conds {
-                        if matches!(cond, Self::Or(_) | Self::And(_)) {
-                            cond.assume_item(&item, count);
-                        }
-                    }
-                }
-                new_conds.retain(|c| *c != Self::NoRequirements);
-                new_conds.sort();
-                new_conds.dedup();
-                let disj: Vec<_> = new_conds.iter().filter_map(|c| match c { Self::Or(conds) => Some(conds.clone()), _ => None }).collect();
-                for cond in &mut new_conds {
-                    if matches!(cond, Self::And(_) | Self::Or(_)) {
-                        for or in &disj {
-                            if matches!(cond, Self::Or(cond) if cond == or) {
-                                continue;
-                            }
-                            cond.remove_redundant_ors(or);
-                        }
-                    }                    
+                    merge_max(&mut rv, &cond.guaranteed());
                 }
-                new_conds.retain(|c| *c != Self::NoRequirements);
-                new_conds.sort();
-                new_conds.dedup();
-                if new_conds.contains(&ItemCondition::Unattainable) {
-                    ItemCondition::Unattainable
-                } else {
-                    Self::And(new_conds).flatten()
-                }
-            },
-            Self::Or(conds) => {
-                let mut conds = conds.clone();
-                conds.sort();
-                conds.dedup();
-                conds.retain(|c| *c != ItemCondition::Unattainable);
-                if conds.contains(&ItemCondition::NoRequirements) {
-                    ItemCondition::NoRequirements
-                } else {
-                    Self::Or(conds).flatten()
-                }                
+                rv
             }
+            _ => Counts::new(),
         }
     }
-    pub fn assume_item(&mut self, id: &Rc<ItemDef>, count: usize) {
-        match self {
-            Self::NoRequirements | Self::Unattainable  => {}
-            Self::Item(my_id, my_count) => if id == my_id {
-                if count >= *my_count {
-                    *self = Self::NoRequirements;
-                } else {
-                    *my_count -= count;
-                }
-            },
-            Self::AtLeast(threshold, items) => {
-                if let Some((_, weight)) = items.iter().find(|(i, _)| i == id) {
-                    if *threshold <= count * *weight {
-                        *self = Self::NoRequirements;
-                    } else {
-                        *threshold -= count * *weight;
-                    }
-                }
+    /// Whether an item or threshold is met by `held`; compound conditions never are
+    fn met_by(&self, held: impl Fn(&ItemDef) -> usize) -> bool {
+        match &**self {
+            ConditionNode::Item(item, count) => held(item) >= *count,
+            ConditionNode::AtLeast(threshold, items) => {
+                items.iter().map(|(item, weight)| held(item) * weight).sum::<usize>() >= *threshold
             }
-            Self::And(conds) => {
-                for cond in &mut *conds {
-                    cond.assume_item(id, count);
-                }
-                conds.retain(|c| *c != Self::NoRequirements);
-                if conds.is_empty() {
-                    *self = Self::NoRequirements;
-                } else if conds.len() == 1 {
-                    *self = conds.pop().expect("We just checked that conds is non-empty");
-                }
+            _ => false,
+        }
+    }
+    /// Whether meeting this condition always meets `other` too. This only goes by structure,
+    /// so `false` can also mean it couldn't tell.
+    pub fn implies(&self, other: &Self) -> bool {
+        if self == other {
+            return true;
+        }
+        // Two leaves are quicker to compare than to look up
+        match (&**self, &**other) {
+            (ConditionNode::Item(item, count), ConditionNode::Item(other_item, other_count)) => {
+                return item == other_item && count >= other_count;
             }
-            Self::Or(conds) => {
-                for cond in &mut *conds {
-                    cond.assume_item(id, count);
-                }
-                if conds.iter().any(|c| *c == Self::NoRequirements) {
-                    *self = Self::NoRequirements;
-                }
+            (ConditionNode::Item(item, count), ConditionNode::AtLeast(threshold, items)) => {
+                return items.iter().filter(|(i, _)| i == item).map(|(_, weight)| weight * count).sum::<usize>() >= *threshold;
             }
+            (ConditionNode::AtLeast(..), ConditionNode::Item(..)) => return false,
+            _ => {}
         }
+        let key = (self.clone(), other.clone());
+        if let Some(implies) = MEMO.with(|memo| memo.borrow().implies.get(&key).copied()) {
+            return implies;
+        }
+        let implies = match (&**self, &**other) {
+            (_, ConditionNode::NoRequirements) | (ConditionNode::Unattainable, _) => true,
+            (ConditionNode::Or(conds), _) => conds.iter().all(|cond| cond.implies(other)),
+            (_, ConditionNode::And(conds)) => conds.iter().all(|cond| self.implies(cond)),
+            (ConditionNode::And(conds), _) if conds.iter().any(|cond| cond.implies(other)) => true,
+            (_, ConditionNode::Or(conds)) if conds.iter().any(|cond| self.implies(cond)) => true,
+            (ConditionNode::AtLeast(threshold, items), ConditionNode::AtLeast(other_threshold, other_items)) => {
+                threshold >= other_threshold && items.iter().all(|(item, weight)| {
+                    other_items.iter().any(|(other_item, other_weight)| item == other_item && weight <= other_weight)
+                })
+            }
+            (_, ConditionNode::Item(..) | ConditionNode::AtLeast(..)) => {
+                let guaranteed = self.guaranteed();
+                other.met_by(|item| guaranteed.get(&item.index).copied().unwrap_or(0))
+            },
+            _ => false,
+        };
+        MEMO.with(|memo| remember(&mut memo.borrow_mut().implies, key, implies));
+        implies
     }
-    pub fn min_sat<'a>(&'a self, items: &HashMap<&'a Rc<ItemDef>, usize>, rv: &mut HashMap<&'a Rc<ItemDef>, usize>) {
-        match self {
-            ItemCondition::NoRequirements => {},
-            ItemCondition::Unattainable => {},
-            ItemCondition::Item(item, count) => {
-                if items.contains_key(item) {
+    /// What's left of the condition once `fact` is known to hold
+    fn given(&self, fact: &Self) -> Self {
+        if fact.implies(self) {
+            return Self::no_requirements();
+        }
+        if !matches!(**self, ConditionNode::And(_) | ConditionNode::Or(_)) {
+            return self.clone();
+        }
+        let key = (self.clone(), fact.clone());
+        if let Some(given) = MEMO.with(|memo| memo.borrow().given.get(&key).cloned()) {
+            return given;
+        }
+        let given = self.map_children(|cond| cond.given(fact));
+        MEMO.with(|memo| remember(&mut memo.borrow_mut().given, key, given.clone()));
+        given
+    }
+    pub fn assume_item(&mut self, id: &Arc<ItemDef>, count: usize) {
+        *self = self.assumed(id, count);
+    }
+    /// The condition that's left after collecting `count` more of `id`
+    pub fn assumed(&self, id: &Arc<ItemDef>, count: usize) -> Self {
+        match &**self {
+            ConditionNode::NoRequirements | ConditionNode::Unattainable => return self.clone(),
+            ConditionNode::Item(my_id, my_count) => {
+                return if my_id != id {
+                    self.clone()
+                } else if count >= *my_count {
+                    Self::no_requirements()
+                } else {
+                    Self::item(my_id.clone(), my_count - count)
+                };
+            }
+            ConditionNode::AtLeast(threshold, items) => {
+                return match items.iter().find(|(i, _)| i == id) {
+                    Some((_, weight)) if *threshold <= count * weight => Self::no_requirements(),
+                    Some((_, weight)) => Self::at_least(threshold - count * weight, items.clone()),
+                    None => self.clone(),
+                };
+            }
+            ConditionNode::And(_) | ConditionNode::Or(_) => {}
+        }
+        let key = (self.clone(), id.clone(), count);
+        if let Some(assumed) = MEMO.with(|memo| memo.borrow().assumed.get(&key).cloned()) {
+            return assumed;
+        }
+        let assumed = self.map_children(|cond| cond.assumed(id, count));
+        MEMO.with(|memo| remember(&mut memo.borrow_mut().assumed, key, assumed.clone()));
+        assumed
+    }
+    pub fn min_sat<'a>(&'a self, items: &Inventory, rv: &mut HashMap<&'a Arc<ItemDef>, usize>) {
+        match &**self {
+            ConditionNode::NoRequirements => {},
+            ConditionNode::Unattainable => {},
+            ConditionNode::Item(item, count) => {
+                if items.count(item) > 0 {
                     let entry = rv.entry(item).or_default();
                     *entry = (*entry).max(*count);
                 }
             },
-            ItemCondition::AtLeast(threshold, req_item) => {
-                let mut total = 0;
+            ConditionNode::AtLeast(threshold, req_item) => {
                 // Reduce by contents of rv first
+                let mut total: usize = req_item.iter().map(|(item, weight)| rv.get(item).copied().unwrap_or(0) * *weight).sum();
                 for (item, weight) in req_item {
-                    if let Some(&item_count) = rv.get(item) {
-                        total += item_count * *weight;
-                    }
-                }
-                if total >= *threshold {
-                    return;
-                }
-                
-                for (item, weight) in req_item {
-                    if let Some(&item_count) = items.get(item) {
-                        let mut item_count = item_count;
-                        item_count -= rv.get(item).copied().unwrap_or(0);
-                        total += item_count * *weight;
-                        let entry = rv.entry(item).or_default();
-                        *entry = (*entry).max(item_count);
-                    }
                     if total >= *threshold {
                         break;
                     }
+                    let held = items.count(item);
+                    let chosen = rv.get(item).copied().unwrap_or(0);
+                    let extra = held.saturating_sub(chosen).min((*threshold - total).div_ceil(*weight));
+                    if extra > 0 {
+                        rv.insert(item, chosen + extra);
+                        total += extra * *weight;
+                    }
                 }
             }
-            ItemCondition::And(conds) => {
+            ConditionNode::And(conds) => {
                 for cond in conds {
                     cond.min_sat(items, rv);
                 }
             },
-            ItemCondition::Or(conds) => {
+            ConditionNode::Or(conds) => {
                 let new_rv = conds.iter().map(|cond| {
                     let mut temp_rv = rv.clone();
                     cond.min_sat(items, &mut temp_rv);
                     temp_rv
                 }).min_by_key(|temp_rv| {
-                    let mut cost = 0;
-                    for (item, count) in &*rv  {
-                        cost += *count - temp_rv.get(item).copied().unwrap_or(0);
-                    }
-                    cost
+                    // Branches only ever add to rv, so count what they add
+                    temp_rv.iter().map(|(item, count)| *count - rv.get(item).copied().unwrap_or(0)).sum::<usize>()
                 });
                 if let Some(new_rv) = new_rv {
                     *rv = new_rv;
@@ -331,94 +631,182 @@ impl ItemCondition {
         }
     }
 
-    pub fn prune_sat(&self, items: &HashMap<&Rc<ItemDef>, usize>) -> Result<ItemCondition, ()> {
-        match self {
-            ItemCondition::NoRequirements => Ok(self.clone()),
-            ItemCondition::Unattainable => Err(()),
-            ItemCondition::Item(item, count) => {
-                if items.get(item).copied().unwrap_or(0) >= *count {
-                    Ok(self.clone())
+    pub fn prune_sat(&self, items: &Inventory) -> Option<ItemCondition> {
+        match &**self {
+            ConditionNode::NoRequirements => Some(self.clone()),
+            ConditionNode::Unattainable => None,
+            ConditionNode::Item(item, count) => {
+                if items.count(item) >= *count {
+                    Some(self.clone())
                 } else {
-                    Err(())
+                    None
                 }
             }
-            ItemCondition::AtLeast(threshold, req_items) => {
+            ConditionNode::AtLeast(threshold, req_items) => {
+                // Keep the held items, with their weights, until there are enough of them
                 let mut total = 0;
-                let mut rv: Vec<(Rc<ItemDef>, usize)> = vec![];
+                let mut rv: Vec<(Arc<ItemDef>, usize)> = vec![];
                 for (req_item, weight) in req_items {
-                    total += items.get(req_item).copied().unwrap_or(0) * *weight;
+                    let held = items.count(req_item);
+                    if held == 0 {
+                        continue;
+                    }
+                    total += held * *weight;
+                    rv.push((req_item.clone(), *weight));
                     if total >= *threshold {
-                        let diff = total - *threshold;
-                        rv.push((req_item.clone(), *weight - (diff / weight)));
-                        return Ok(ItemCondition::AtLeast(*threshold, rv))
-                    } else {
-                        rv.push((req_item.clone(), *weight));
+                        return Some(ItemCondition::at_least(*threshold, rv))
                     }
                 }
-                Err(())
+                None
             }
-            ItemCondition::And(conds) => {
-                let conds = conds.iter().map(|c| c.prune_sat(items)).collect::<Result<_, _>>()?;
-                Ok(ItemCondition::And(conds))
+            ConditionNode::And(conds) => {
+                let conds = conds.iter().map(|c| c.prune_sat(items)).collect::<Option<Vec<_>>>()?;
+                Some(ItemCondition::and(conds))
             },
-            ItemCondition::Or(conds) => {
-                let conds: Vec<_> = conds.iter().map(|c| c.prune_sat(items)).filter_map(|r| r.ok()).collect();
-                match conds.len() {
-                    0 => Ok(ItemCondition::Unattainable),
-                    1 => Ok(conds[0].clone()),
-                    _ => Ok(ItemCondition::Or(conds))
-                }
+            ConditionNode::Or(conds) => {
+                Some(ItemCondition::or(conds.iter().filter_map(|c| c.prune_sat(items))))
             },
         }
     }
+    /// Every inclusion-minimal multiset of items that satisfies the condition, smallest first.
+    /// Sets of more than `cap` items are left out, which also keeps large thresholds in check.
+    pub fn minimal_sets(&self, cap: usize) -> Vec<ItemSet> {
+        self.sets_within(cap, None)
+    }
+    /// Like [`minimal_sets`](Self::minimal_sets), but only the sets the inventory holds. Much
+    /// cheaper for thresholds, which would otherwise try every count of every item.
+    pub fn minimal_held_sets(&self, items: &Inventory, cap: usize) -> Vec<ItemSet> {
+        self.sets_within(cap, Some(items))
+    }
+    fn sets_within(&self, cap: usize, held: Option<&Inventory>) -> Vec<ItemSet> {
+        let limit = |item: &ItemDef| held.map_or(usize::MAX, |items| items.count(item));
+        match &**self {
+            ConditionNode::NoRequirements => vec![ItemSet::new()],
+            ConditionNode::Unattainable => vec![],
+            ConditionNode::Item(item, count) if *count <= cap.min(limit(item)) => vec![ItemSet::from([(item.clone(), *count)])],
+            ConditionNode::Item(_, _) => vec![],
+            ConditionNode::AtLeast(threshold, req_items) => {
+                // None of these contain another, since they'd have an item to spare
+                let mut sets = vec![];
+                let limits: Vec<_> = req_items.iter().map(|(item, _)| limit(item)).collect();
+                at_least_sets(*threshold, req_items, &limits, &mut vec![0; req_items.len()], 0, cap, &mut sets);
+                sets.sort_by(|a, b| (set_size(a), a).cmp(&(set_size(b), b)));
+                sets
+            }
+            ConditionNode::And(conds) => conds.iter().fold(vec![ItemSet::new()], |sets, cond| {
+                let cond_sets = cond.sets_within(cap, held);
+                let mut combined = vec![];
+                for set in &sets {
+                    for cond_set in &cond_sets {
+                        let mut set = set.clone();
+                        for (item, count) in cond_set {
+                            let entry = set.entry(item.clone()).or_default();
+                            *entry = (*entry).max(*count);
+                        }
+                        if set_size(&set) <= cap {
+                            combined.push(set);
+                        }
+                    }
+                }
+                minimize(combined)
+            }),
+            ConditionNode::Or(conds) => minimize(conds.iter().flat_map(|cond| cond.sets_within(cap, held)).collect()),
+        }
+    }
     pub fn would_be_satisfied_by(&self, item: &ItemDef) -> bool {
-        match self {
-            ItemCondition::NoRequirements => true,
-            ItemCondition::Unattainable => false,
-            ItemCondition::Item(cond_item, count) => item == &**cond_item && *count == 1,
-            ItemCondition::AtLeast(threshold, req_items) => {
+        match &**self {
+            ConditionNode::NoRequirements => true,
+            ConditionNode::Unattainable => false,
+            ConditionNode::Item(cond_item, count) => item == &**cond_item && *count == 1,
+            ConditionNode::AtLeast(threshold, req_items) => {
                 req_items.iter().find(|(i, _)| &**i == item).map(|(_, weight)| *weight).unwrap_or(0) >= *threshold
             },
-            ItemCondition::And(conds) => conds.iter().all(|cond| cond.would_be_satisfied_by(item)),
-            ItemCondition::Or(conds) => conds.iter().any(|cond| cond.would_be_satisfied_by(item)),
+            ConditionNode::And(conds) => conds.iter().all(|cond| cond.would_be_satisfied_by(item)),
+            ConditionNode::Or(conds) => conds.iter().any(|cond| cond.would_be_satisfied_by(item)),
         }
     }
     pub fn satisfied(&self) -> bool {
-        self == &Self::NoRequirements
-    }
-    // pub fn satisfied(&self, items: &HashMap<Rc<ItemDef>, usize>) -> bool {
-    //     match self {
-    //         Self::NoRequirements => true,
-    //         Self::Unattainable => false,
-    //         Self::AtLeast(threshold, req_items) => {
-    //             let mut total = 0;
-    //             for (id, weight) in req_items {
-    //                 total += items.get(id).copied().unwrap_or(0) * *weight;
-    //             }
-
-    //             total >= *threshold
-    //         }
-    //         Self::Item(id, count) => items.get(id).copied().unwrap_or(0) >= *count,
-    //         Self::Or(conds) => conds.iter().any(|cond| cond.satisfied(items)),
-    //         Self::And(conds) => conds.iter().all(|cond| cond.satisfied(items)),
-    //     }
-    // }
+        matches!(**self, ConditionNode::NoRequirements)
+    }
+    /// Whether the inventory meets the condition, without assuming anything into it
+    pub fn satisfied_by(&self, items: &Inventory) -> bool {
+        match &**self {
+            ConditionNode::NoRequirements => true,
+            ConditionNode::Unattainable => false,
+            ConditionNode::AtLeast(threshold, req_items) => {
+                let mut total = 0;
+                for (id, weight) in req_items {
+                    total += items.count(id) * *weight;
+                }
+                total >= *threshold
+            }
+            ConditionNode::Item(id, count) => items.count(id) >= *count,
+            ConditionNode::Or(conds) => conds.iter().any(|cond| cond.satisfied_by(items)),
+            ConditionNode::And(conds) => conds.iter().all(|cond| cond.satisfied_by(items)),
+        }
+    }
 
 }
 
+fn merge_max(held: &mut Counts, more: &Counts) {
+    for (&item, &count) in more {
+        let entry = held.entry(item).or_default();
+        *entry = count.max(*entry);
+    }
+}
+
+fn set_size(set: &ItemSet) -> usize {
+    set.values().sum()
+}
+
+/// Sorts sets smallest first, and drops every set that contains another
+fn minimize(mut sets: Vec<ItemSet>) -> Vec<ItemSet> {
+    sets.sort_by(|a, b| (set_size(a), a).cmp(&(set_size(b), b)));
+    sets.dedup();
+    let mut minimal: Vec<ItemSet> = vec![];
+    for set in sets {
+        let contains = |smaller: &ItemSet| smaller.iter().all(|(item, count)| set.get(item).is_some_and(|n| n >= count));
+        if !minimal.iter().any(contains) {
+            minimal.push(set);
+        }
+    }
+    minimal
+}
+
+/// Picks counts for `items[idx..]`, recording every choice that reaches the threshold
+/// without a spare item
+/// `limits` caps the count of each item
+fn at_least_sets(threshold: usize, items: &[(Arc<ItemDef>, usize)], limits: &[usize], counts: &mut Vec<usize>, idx: usize, cap: usize, rv: &mut Vec<ItemSet>) {
+    let total: usize = items.iter().zip(counts.iter()).map(|((_, weight), n)| weight * n).sum();
+    if total >= threshold {
+        if items.iter().zip(counts.iter()).all(|((_, weight), &n)| n == 0 || total - weight < threshold) {
+            rv.push(items.iter().zip(counts.iter()).filter(|(_, &n)| n > 0).map(|((item, _), &n)| (item.clone(), n)).collect());
+        }
+        return;
+    }
+    let Some((_, weight)) = items.get(idx) else { return };
+    let size: usize = counts.iter().sum();
+    let most = if *weight == 0 { 0 } else { (threshold - total).div_ceil(*weight).min(cap - size).min(limits[idx]) };
+    for n in 0..=most {
+        counts[idx] = n;
+        at_least_sets(threshold, items, limits, counts, idx + 1, cap, rv);
+    }
+    counts[idx] = 0;
+}
+
 impl std::fmt::Display for ItemCondition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::NoRequirements => write!(f, "-")?,
-            Self::Unattainable => write!(f, "!")?,
-            Self::Item(item, count) => {
+        match &**self {
+            ConditionNode::NoRequirements => write!(f, "-")?,
+            ConditionNode::Unattainable => write!(f, "!")?,
+            ConditionNode::Item(item, count) => {
                 if *count > 1 {
                     write!(f, "{}*{}", item.name, count)?;
                 } else {
                     write!(f, "{}", item.name)?;
                 }
             }
-            Self::AtLeast(threshold, items) => {
+            ConditionNode::AtLeast(threshold, items) => {
                 write!(f, "({} <= {})", threshold, items.iter().map(|(item, weight)| {
                     if *weight > 1 {
                         format!("{} * {}", item, weight)
@@ -427,13 +815,117 @@ impl std::fmt::Display for ItemCondition {
                     }
                 }).join_with(" + "))?;
             }
-            Self::And(conds) => {
+            ConditionNode::And(conds) => {
                 write!(f, "({})", conds.iter().join_with(" & "))?;
             }
-            Self::Or(conds) => {
+            ConditionNode::Or(conds) => {
                 write!(f, "({})", conds.iter().join_with(" | "))?;
             },
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[test]
+fn minimal_sets_of_data_yaml() {
+    let (prelogic, pool) = crate::LogicLoader::from_reader(include_str!("../../data.yaml").as_bytes()).unwrap();
+    let (logic, _) = prelogic.build(&pool);
+    let flag = |name: &str| logic.flags.iter().find(|(flag, _)| flag.name == name).unwrap().1.clone();
+    let location = |name: &str| logic.locations.iter().find(|(loc, _)| loc.name == name).unwrap().1.clone();
+    let show = |sets: Vec<ItemSet>| -> Vec<String> {
+        sets.iter()
+            .map(|set| set.iter().map(|(item, count)| if *count > 1 { format!("{}*{}", item.name, count) } else { item.name.clone() }).join_with(" ").to_string())
+            .collect()
+    };
+
+    // (|Items.RocsCape, (&Helpers.HasSword, Helpers.HasSpin), Items.Flippers, (&Items.BombBag, Helpers.CanSplit3))
+    // Splitting needs the sword and a spin anyway, so the bomb bag branch is never minimal
+    assert_eq!(show(flag("AccessWestField").minimal_sets(10)), [
+        "Flippers", "RocsCape",
+        "SpinAttack SmithSword", "LongSpin SmithSword", "FastSplit SmithSword", "FastSpin SmithSword", "GreatSpin SmithSword",
+    ]);
+    assert_eq!(show(flag("HasBeam").minimal_sets(10)), ["PerilBeam SmithSword", "SmithSword SwordBeam"]);
+    // (|Helpers.HasSword, Helpers.HasLightBow, Items.BombBag, Items.LanternOff)
+    assert_eq!(show(flag("CanDestroyTrees").minimal_sets(10)), ["BombBag", "SmithSword", "LanternOff", "Bow*2"]);
+    assert_eq!(show(flag("CanDestroyTrees").minimal_sets(1)), ["BombBag", "SmithSword", "LanternOff"]);
+
+    // (+2, Items.WaterElement, Items.FireElement, Items.WindElement, Items.EarthElement)
+    // Counts in the pool don't matter, so two of the same element count too
+    let sets = location("PedItem1").minimal_sets(10);
+    assert_eq!(sets.len(), 4 + 6);
+    assert!(sets.iter().all(|set| set_size(set) == 2));
+    assert!(location("PedItem1").minimal_sets(1).is_empty());
+    // Any seven of eleven scrolls, repeats included
+    let sets = flag("GotScrolls").minimal_sets(20);
+    assert_eq!(sets.len(), 19448);
+    assert!(sets.iter().all(|set| set_size(set) == 7));
+
+    // CanSplit4 alone is five swords and a spin
+    assert_eq!(flag("CanSplit4").minimal_sets(6).len(), 5);
+    assert!(flag("CanSplit4").minimal_sets(5).is_empty());
+}
+
+#[test]
+fn at_least_weights_and_min_sat() {
+    let build = super::new_build();
+    let item = |name: &str, index| Arc::new(ItemDef { name: name.to_string(), category: super::ItemCategory::Major, restriction: None, weight: 1, show_in_graph: true, build, index });
+    let (big_rupee, rupee, sword) = (item("BigRupee", 0), item("Rupee", 1), item("Sword", 2));
+    let wallet = ItemCondition::at_least(10, vec![(rupee.clone(), 1), (big_rupee.clone(), 5)]);
+    let sets = wallet.minimal_sets(10);
+    let sizes: Vec<_> = sets.iter().map(|set| (set.get(&rupee).copied(), set.get(&big_rupee).copied())).collect();
+    assert_eq!(sizes, [(None, Some(2)), (Some(5), Some(1)), (Some(10), None)]);
+
+    let inventory = |counts: &[(&Arc<ItemDef>, usize)]| {
+        let mut inventory = Inventory::new(3);
+        for (item, count) in counts {
+            inventory.add(item, *count);
+        }
+        inventory
+    };
+    // Pruning keeps weights as weights
+    let held = inventory(&[(&rupee, 6), (&big_rupee, 1)]);
+    assert_eq!(wallet.prune_sat(&held), Some(wallet.clone()));
+    let held = inventory(&[(&rupee, 3), (&big_rupee, 4)]);
+    assert_eq!(wallet.prune_sat(&held), Some(ItemCondition::item(big_rupee.clone(), 2)));
+    let mut rv = HashMap::new();
+    wallet.min_sat(&held, &mut rv);
+    assert_eq!(rv, [(&big_rupee, 2)].into_iter().collect());
+    // Only the sets that fit in what's held
+    let held = inventory(&[(&rupee, 5), (&big_rupee, 1)]);
+    let sets: Vec<_> = wallet.minimal_held_sets(&held, 10).iter().map(|set| (set.get(&rupee).copied(), set.get(&big_rupee).copied())).collect();
+    assert_eq!(sets, [(Some(5), Some(1))]);
+
+    // The branch that adds the fewest items wins
+    let held = inventory(&[(&rupee, 10), (&big_rupee, 4), (&sword, 1)]);
+    let cond = ItemCondition::or([wallet, ItemCondition::item(sword.clone(), 1)]);
+    let mut rv = HashMap::new();
+    cond.min_sat(&held, &mut rv);
+    assert_eq!(rv, [(&sword, 1)].into_iter().collect());
+}
+
+#[test]
+fn canonical_form_and_absorption() {
+    let build = super::new_build();
+    let item = |name: &str, index| Arc::new(ItemDef { name: name.to_string(), category: super::ItemCategory::Major, restriction: None, weight: 1, show_in_graph: true, build, index });
+    let (a, b, c) = (ItemCondition::item(item("A", 0), 1), ItemCondition::item(item("B", 1), 1), ItemCondition::item(item("C", 2), 1));
+
+    // Structurally equal conditions share a node, whatever order they were built in
+    let ab = ItemCondition::and([a.clone(), b.clone()]);
+    assert!(Arc::ptr_eq(&ab.0, &ItemCondition::and([b.clone(), ItemCondition::no_requirements(), a.clone(), a.clone()]).0));
+    assert_eq!(ItemCondition::or([ab.clone(), c.clone()]), ItemCondition::or([c.clone(), ItemCondition::unattainable(), ab.clone()]));
+
+    assert_eq!(ItemCondition::and([a.clone(), ItemCondition::or([a.clone(), b.clone()])]), a);
+    assert_eq!(ItemCondition::or([a.clone(), ab.clone()]), a);
+    let a_or_b = ItemCondition::or([a.clone(), b.clone()]);
+    assert_eq!(ItemCondition::and([a_or_b.clone(), ItemCondition::or([a.clone(), b.clone(), c.clone()])]), a_or_b);
+    assert_eq!(ItemCondition::and([c.clone(), ItemCondition::or([ab.clone(), ItemCondition::and([b.clone(), c.clone()])])]), ItemCondition::and([b.clone(), c.clone()]));
+
+    let two_a = ItemCondition::item(item("A", 0), 2);
+    assert_eq!(ItemCondition::and([a.clone(), two_a.clone()]), two_a);
+    assert_eq!(ItemCondition::or([a.clone(), two_a.clone()]), a);
+    assert_eq!(ItemCondition::and([ab.clone(), ItemCondition::at_least(2, vec![(item("A", 0), 1), (item("B", 1), 1), (item("C", 2), 1)])]), ab);
+    assert_eq!(ItemCondition::at_least(3, vec![(item("A", 0), 1)]), ItemCondition::item(item("A", 0), 3));
+    assert_eq!(ItemCondition::at_least(1, vec![(item("A", 0), 1), (item("B", 1), 2)]), a_or_b);
+
+    assert_eq!(ItemCondition::and([a.clone(), ItemCondition::or([b.clone(), c.clone()])]).assumed(&item("B", 1), 1), a);
+    assert!(ab.assumed(&item("A", 0), 1).assumed(&item("B", 1), 1).satisfied());
+}
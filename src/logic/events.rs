@@ -0,0 +1,397 @@
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+
+use serde::Serialize;
+
+use super::{Flag, ItemCategory, ItemCondition, ItemDef, Location, Restriction};
+
+/// Receives every [`AllocationEvent`] as the [`Allocator`](super::Allocator) produces it.
+pub trait AllocationObserver {
+    fn on_event(&mut self, event: &AllocationEvent);
+
+    /// Whether to send the events that take work to build, like the requirement behind each
+    /// unlock. Observers that return false still get every other event.
+    fn enabled(&self) -> bool {
+        true
+    }
+}
+
+/// Discards all events
+impl AllocationObserver for () {
+    fn on_event(&mut self, _event: &AllocationEvent) {}
+
+    fn enabled(&self) -> bool {
+        false
+    }
+}
+
+/// Keeps a log of all events
+impl AllocationObserver for Vec<AllocationEvent> {
+    fn on_event(&mut self, event: &AllocationEvent) {
+        self.push(event.clone());
+    }
+}
+
+impl<T: AllocationObserver + ?Sized> AllocationObserver for Box<T> {
+    fn on_event(&mut self, event: &AllocationEvent) {
+        (**self).on_event(event);
+    }
+
+    fn enabled(&self) -> bool {
+        (**self).enabled()
+    }
+}
+
+/// Lets the caller hold on to an observer after handing it to the allocator
+impl<T: AllocationObserver + ?Sized> AllocationObserver for Rc<RefCell<T>> {
+    fn on_event(&mut self, event: &AllocationEvent) {
+        self.borrow_mut().on_event(event);
+    }
+
+    fn enabled(&self) -> bool {
+        self.borrow().enabled()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind")]
+pub enum PlacementReason {
+    /// Placed early in an already open location, since it can't affect progression
+    Backfill { weight: u32 },
+    /// Placing the item opens at least one new location
+    Unlock { weight: u32 },
+    /// The item is part of some unsatisfied requirement
+    Missing,
+    /// Reachable with every item that hasn't been placed yet
+    Assumed,
+    /// Nothing else was left to place
+    Filler,
+    /// The location keeps its vanilla item
+    Fixed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event")]
+pub enum AllocationEvent {
+    ItemPlaced {
+        item: Arc<ItemDef>,
+        location: Arc<Location>,
+        reason: PlacementReason,
+    },
+    /// An item without a restriction went into a restricted location
+    NonDungeonItemInDungeon {
+        item: Arc<ItemDef>,
+        location: Arc<Location>,
+    },
+    LocationUnlocked {
+        location: Arc<Location>,
+        requirement: ItemCondition,
+    },
+    FlagUnlocked {
+        flag: Arc<Flag>,
+        requirement: ItemCondition,
+    },
+    BackfillSkipped {
+        item: Arc<ItemDef>,
+        weight: u32,
+    },
+    PlacementSkipped {
+        item: Arc<ItemDef>,
+    },
+    /// The items placed so far satisfy the goal
+    GoalReached,
+    RoundCompleted {
+        round: usize,
+        progress: AllocationProgress,
+    },
+    /// A new allocation attempt started from scratch
+    AttemptStarted {
+        attempt: usize,
+        seed: u64,
+    },
+    /// Assumed fill found no home for an item, and starts over placing it earlier
+    AssumedFillRestarted {
+        item: Arc<ItemDef>,
+    },
+    /// The allocator got stuck and undid its most recent placements
+    Backtracked {
+        placements: usize,
+    },
+    /// The allocation finished, but the assignment check rejected it
+    CheckFailed {
+        unreachable_locations: Vec<Arc<Location>>,
+        goal_reachable: Option<bool>,
+    },
+    /// Allocation gave up; these locations could not be opened
+    Stuck {
+        closed_locations: Vec<(Arc<Location>, ItemCondition)>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Counter {
+    pub total: usize,
+    pub major: usize,
+}
+
+impl Counter {
+    pub fn add(&mut self, category: ItemCategory, count: usize) {
+        self.total += count;
+        if category == ItemCategory::Major {
+            self.major += count;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ProgressCounters {
+    pub open_locations: Counter,
+    pub closed_locations: Counter,
+    pub unassigned_items: Counter,
+    pub assigned_items: Counter,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AllocationProgress {
+    pub overall: ProgressCounters,
+    pub restrictions: Vec<(Restriction, ProgressCounters)>,
+}
+
+impl std::fmt::Display for Counter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.total, self.major)
+    }
+}
+
+impl ProgressCounters {
+    fn sections(&self) -> [(&'static str, Counter); 4] {
+        [
+            ("Open", self.open_locations),
+            ("Closed", self.closed_locations),
+            ("Unassigned items", self.unassigned_items),
+            ("Assigned items", self.assigned_items),
+        ]
+    }
+}
+
+impl std::fmt::Display for AllocationProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (name, counter)) in self.overall.sections().into_iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}: {}", name, counter)?;
+            if !self.restrictions.is_empty() {
+                write!(f, " [")?;
+                for (j, (_, counters)) in self.restrictions.iter().enumerate() {
+                    if j > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", counters.sections()[i].1)?;
+                }
+                write!(f, "]")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for AllocationEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ItemPlaced {
+                item,
+                location,
+                reason,
+            } => match reason {
+                PlacementReason::Backfill { weight } => {
+                    write!(f, "Backfilling {} ({}) in {}", item, weight, location)
+                }
+                PlacementReason::Unlock { weight } => write!(
+                    f,
+                    "Placing {} ({}) in {} to unlock new locations",
+                    item, weight, location
+                ),
+                PlacementReason::Missing => write!(
+                    f,
+                    "Placing {} in {}, hoping that it frees things up",
+                    item, location
+                ),
+                PlacementReason::Assumed => write!(
+                    f,
+                    "Placing {} in {}, reachable with all unplaced items",
+                    item, location
+                ),
+                PlacementReason::Filler => {
+                    write!(f, "Placing {} in {}, to fill up space", item, location)
+                }
+                PlacementReason::Fixed => {
+                    write!(f, "Keeping {} in {}, where it always is", item, location)
+                }
+            },
+            Self::NonDungeonItemInDungeon { item, location } => write!(
+                f,
+                "!!! Placing a non-dungeon item ({}) in a dungeon slot ({})",
+                item, location
+            ),
+            Self::LocationUnlocked {
+                location,
+                requirement,
+            } => write!(f, "  Unlocked location {}: {}", location, requirement),
+            Self::FlagUnlocked { flag, requirement } => {
+                write!(f, "  Unlocked flag {}: {}", flag.name, requirement)
+            }
+            Self::BackfillSkipped { item, weight } => write!(
+                f,
+                "(Wanted to backfill {} ({}) but couldn't find a home for it)",
+                item, weight
+            ),
+            Self::PlacementSkipped { item } => {
+                write!(f, "(Wanted to place {} but couldn't find a home for it)", item)
+            }
+            Self::GoalReached => write!(f, "Goal reached"),
+            Self::RoundCompleted { round, progress } => {
+                write!(f, "Round {}: {}", round, progress)
+            }
+            Self::AttemptStarted { attempt, seed } => {
+                write!(f, "Attempt {} with seed {}", attempt, seed)
+            }
+            Self::AssumedFillRestarted { item } => {
+                write!(f, "No home left for {}; starting over", item)
+            }
+            Self::Backtracked { placements } => {
+                write!(f, "Stuck; undoing the last {} placements", placements)
+            }
+            Self::CheckFailed {
+                unreachable_locations,
+                goal_reachable,
+            } => {
+                write!(f, "Assignments failed the check")?;
+                if *goal_reachable == Some(false) {
+                    write!(f, "\n  Goal is not reachable")?;
+                }
+                for loc in unreachable_locations {
+                    write!(f, "\n  Unreachable location: {}", loc)?;
+                }
+                Ok(())
+            }
+            Self::Stuck { closed_locations } => {
+                write!(f, "Not making progress; closed locations:")?;
+                for (loc, req) in closed_locations {
+                    write!(f, "\n  - {}\n    {}", loc, req)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[test]
+fn events_follow_the_allocation() {
+    use super::{Algorithm, AllocatorSettings};
+    use crate::{Generator, LogicLoader};
+    use std::collections::BTreeMap;
+    let yaml = "
+flags:
+  - name: Armed
+    requirements: (| Sword, Bow)
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Shield
+  - name: Rupee
+    category: Minor
+    count: 2
+locations:
+  - name: Start
+  - name: Chest
+  - name: Pedestal
+    vanilla: Shield
+    fixed: true
+  - name: Field
+    requirements: Armed
+  - name: Cave
+    requirements: Sword, Bow
+  - name: Shop
+    category: Minor
+    requirements: Shield
+goal: Sword, Bow
+";
+    /// Logs events, but doesn't ask for the ones that take work to build
+    struct Quiet(Vec<AllocationEvent>);
+    impl AllocationObserver for Quiet {
+        fn on_event(&mut self, event: &AllocationEvent) {
+            self.0.push(event.clone());
+        }
+
+        fn enabled(&self) -> bool {
+            false
+        }
+    }
+
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    for algorithm in [Algorithm::Forward, Algorithm::Assumed] {
+        let settings = AllocatorSettings { algorithm, ..Default::default() };
+        for seed in 0..5 {
+            let events = Rc::new(RefCell::new(Vec::new()));
+            let mut generator = Generator::new(&logic, &pool, settings);
+            generator.set_observer(Box::new(events.clone()));
+            let generated = generator.generate(seed).unwrap();
+            let events = events.borrow();
+            assert_eq!(events[0], AllocationEvent::AttemptStarted { attempt: 1, seed });
+
+            // Every placement is reported once, the fixed one first
+            let placed: Vec<_> = events
+                .iter()
+                .filter_map(|event| match event {
+                    AllocationEvent::ItemPlaced { item, location, reason } => Some((location.clone(), item.clone(), *reason)),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(placed.len(), generated.assignments.len());
+            let assignments: BTreeMap<_, _> = placed.iter().map(|(location, item, _)| (location.clone(), item.clone())).collect();
+            assert_eq!(assignments, generated.assignments);
+            assert_eq!((placed[0].0.name.as_str(), placed[0].2), ("Pedestal", PlacementReason::Fixed));
+
+            let count = |matches: fn(&AllocationEvent) -> bool| events.iter().filter(|event| matches(event)).count();
+            match algorithm {
+                Algorithm::Forward => {
+                    // Forward fill reports each lock it opens, and how far along each round got
+                    assert_eq!(count(|event| matches!(event, AllocationEvent::LocationUnlocked { .. })), 2);
+                    assert_eq!(count(|event| matches!(event, AllocationEvent::FlagUnlocked { .. })), 1);
+                    assert_eq!(count(|event| matches!(event, AllocationEvent::GoalReached)), 1);
+                    let rounds: Vec<_> = events
+                        .iter()
+                        .filter_map(|event| match event {
+                            AllocationEvent::RoundCompleted { round, progress } => Some((*round, progress.overall)),
+                            _ => None,
+                        })
+                        .collect();
+                    assert!(rounds.iter().enumerate().all(|(idx, (round, _))| *round == idx + 1));
+                    let last = rounds.last().unwrap().1;
+                    assert_eq!((last.unassigned_items.total, last.assigned_items.total), (0, 5));
+                }
+                Algorithm::Assumed => {
+                    for (_, item, reason) in &placed[1..] {
+                        let expected = if item.name == "Rupee" { PlacementReason::Filler } else { PlacementReason::Assumed };
+                        assert_eq!(*reason, expected, "{}", item);
+                    }
+                }
+            }
+            let json = serde_json::to_value(&events[0]).unwrap();
+            assert_eq!(json["event"], "AttemptStarted");
+
+            // Leaving out the requirements changes nothing else
+            let quiet = Rc::new(RefCell::new(Quiet(Vec::new())));
+            let mut generator = Generator::new(&logic, &pool, settings);
+            generator.set_observer(Box::new(quiet.clone()));
+            assert_eq!(generator.generate(seed).unwrap().assignments, generated.assignments);
+            let explained: Vec<_> = events
+                .iter()
+                .filter(|event| !matches!(event, AllocationEvent::LocationUnlocked { .. } | AllocationEvent::FlagUnlocked { .. }))
+                .cloned()
+                .collect();
+            assert_eq!(quiet.borrow().0, explained);
+        }
+    }
+}
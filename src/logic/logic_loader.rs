@@ -1,81 +1,132 @@
-use std::{io::Read, collections::{HashMap, HashSet}};
+use std::{io::Read, collections::HashMap};
 
-use serde::Deserialize;
+mod source_map;
+use source_map::SourceMap;
 
-use crate::logic_parse::{parse_reqs, gen_reqs2};
+use serde::{Deserialize, Serialize};
 
-use super::{ItemId, ItemCategory, PreLogic, Restriction};
+use crate::{logic_parse::{parse_reqs, gen_reqs2, LogicParseError, LogicParseErrorKind}, Preset, RandomizerError};
+
+use super::{ItemId, ItemCategory, ParameterType, PreLogic};
 
 pub struct LogicLoader;
 
-#[derive(Debug, Clone, Deserialize)]
+/// The schema of a YAML logic file
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(rename_all="snake_case")]
-struct LogicData {
-    parameters: Option<Vec<ParameterData>>,
-    flags: Vec<FlagData>,
-    item_pool: Vec<ItemData>,
-    locations: Vec<LocationData>
-}
-#[derive(Debug, Clone, Deserialize)]
-struct ParameterData {
-    name: String,
+pub(crate) struct LogicData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) parameters: Option<Vec<ParameterData>>,
+    pub(crate) flags: Vec<FlagData>,
+    pub(crate) item_pool: Vec<ItemData>,
+    pub(crate) locations: Vec<LocationData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) goal: Option<String>
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct ParameterData {
+    pub(crate) name: String,
     #[serde(rename="type")]
-    typ: ParameterType,
-    default: Option<String>
+    pub(crate) typ: ParameterType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) default: Option<String>
 }
 
-#[derive(Debug, Clone, Deserialize)]
-enum ParameterType {
-    Boolean
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub(crate) struct ItemData {
+    pub(crate) name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) category: Option<ItemCategory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) restriction: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) count: Option<ItemCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) weight: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) requirements: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) show_in_graph: Option<bool>,
+    /// Parameter expression; the item is left out of the pool unless it holds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) when: Option<String>,
 }
-
-#[derive(Debug, Clone, Deserialize)]
-struct ItemData {
-    name: String,
-    category: Option<ItemCategory>,
-    restriction: Option<String>,
-    count: Option<u32>,
-    weight: Option<u32>,
-    requirements: Option<String>,
-    show_in_graph: Option<bool>
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub(crate) enum ItemCount {
+    Fixed(u32),
+    /// Name of an integer parameter
+    Parameter(String),
 }
-#[derive(Debug, Clone, Deserialize)]
-struct FlagData {
-    name: String,
-    requirements: Option<String>
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct FlagData {
+    pub(crate) name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) requirements: Option<String>
 }
-#[derive(Debug, Clone, Deserialize)]
-struct LocationData {
-    name: String,
-    category: Option<ItemCategory>,
-    requirements: Option<String>,
-    restriction: Option<String>,    
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub(crate) struct LocationData {
+    pub(crate) name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) category: Option<ItemCategory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) requirements: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) restriction: Option<String>,
+    /// Parameter expression; nothing is placed in the location unless it holds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) when: Option<String>,
+    /// Where the game keeps the location's item, for tools that patch the ROM; the
+    /// randomizer itself doesn't use it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) address: Option<String>,
+    /// The item the location holds in the unrandomized game
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) vanilla: Option<String>,
+    /// Keep the vanilla item here instead of shuffling it; one copy is taken from the item pool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) fixed: Option<bool>,
 }
 
 impl LogicLoader {
-    pub fn from_reader(reader: impl Read) -> (PreLogic, Vec<ItemId>) {
+    /// Loads a logic file with every parameter at its default value
+    pub fn from_reader(reader: impl Read) -> Result<(PreLogic, Vec<ItemId>), RandomizerError> {
+        Self::from_reader_with_preset(reader, &Preset::default())
+    }
+
+    /// Loads a logic file with the parameters of `preset`. The item pool and the locations
+    /// can depend on parameters, so they have to be known up front.
+    pub fn from_reader_with_preset(mut reader: impl Read, preset: &Preset) -> Result<(PreLogic, Vec<ItemId>), RandomizerError> {
+        let mut source = String::new();
+        reader.read_to_string(&mut source)?;
+        Self::from_source(&source, preset)
+    }
+
+    pub fn from_source(source: &str, preset: &Preset) -> Result<(PreLogic, Vec<ItemId>), RandomizerError> {
         let mut logic = PreLogic::new();
-        let data: LogicData = serde_yaml::from_reader(reader).unwrap();
+        let data: LogicData = serde_yaml::from_str(source)?;
+        let source_map = SourceMap::new(source);
         let mut items = HashMap::new();
         let mut flags = HashMap::new();
         let mut locations = HashMap::new();
-        let mut parameters = HashSet::new();
-        let mut restrictions = HashMap::new();
-        let mut restriction_id = 0;
-        let mut get_restriction = |name: &str| {
-            *restrictions.entry(name.to_string()).or_insert_with(|| {
-                restriction_id += 1;
-                Restriction(restriction_id)
-            })
-        };
+        let mut parameters = HashMap::new();
 
         let params = data.parameters.unwrap_or_default();
         for param in &params {
-            logic.set_parameter(&param.name, param.default.as_deref() == Some("True"));
-            parameters.insert(param.name.as_str());
+            let value = match &param.default {
+                Some(default) => param.typ.parse(default).ok_or_else(|| RandomizerError::InvalidParameterValue {
+                    name: param.name.clone(),
+                    value: default.clone(),
+                    expected: param.typ.to_string(),
+                })?,
+                None => param.typ.default_value(),
+            };
+            logic.add_parameter(&param.name, param.typ.clone(), value);
+            parameters.insert(param.name.as_str(), &param.typ);
         }
+        preset.apply(&mut logic)?;
         for item in &data.item_pool {
-            let restriction = item.restriction.as_deref().map(&mut get_restriction);
+            let restriction = item.restriction.as_deref().map(|r| logic.add_restriction(r));
             let id = logic.add_item(&item.name, item.category.unwrap_or(ItemCategory::Major), restriction, item.weight, item.show_in_graph.unwrap_or(false));
             items.insert(item.name.as_str(), id);
         }
@@ -84,46 +135,543 @@ impl LogicLoader {
             flags.insert(flag.name.as_str(), id);
         }
         for location in &data.locations {
-            let restriction = location.restriction.as_deref().map(&mut get_restriction);
+            let restriction = location.restriction.as_deref().map(|r| logic.add_restriction(r));
             let id = logic.add_location(location.name.as_str(), location.category.unwrap_or(ItemCategory::Major), restriction);
             locations.insert(location.name.as_str(), id);
         }
-        for item in &data.item_pool {
+        for name in &preset.exclude {
+            let id = *locations.get(name.as_str()).ok_or_else(|| RandomizerError::UnknownExcludedLocation(name.clone()))?;
+            logic.exclude_location(id);
+        }
+        let parse = |path: &str, requirements: Option<&str>| {
+            let requirements = requirements.unwrap_or("");
+            parse_reqs(requirements)
+                .and_then(|reqs| gen_reqs2(&reqs, requirements, &items, &flags, &locations, &parameters))
+                .map_err(|e| source_map.place(path, requirements, e))
+        };
+        let parse_when = |path: &str, when: &str| {
+            parse(path, Some(when)).and_then(|cond| {
+                if cond.is_static() {
+                    Ok(cond)
+                } else {
+                    let e = LogicParseError::new(LogicParseErrorKind::NotStatic(when.to_string()), when, 0..when.len());
+                    Err(source_map.place(path, when, e))
+                }
+            })
+        };
+        for (idx, item) in data.item_pool.iter().enumerate() {
             let id = items[item.name.as_str()];
-            let (reqs, _) = parse_reqs(item.requirements.as_deref().unwrap_or(""));
-            let conditions = gen_reqs2(&reqs, &items, &flags, &locations, &parameters).map_err(|e| {
-                eprintln!("Error parsing item {}", item.name);
-                panic!("{}", e);
-            }).unwrap();
+            let conditions = parse(&format!("item_pool/{}/requirements", idx), item.requirements.as_deref())
+                .map_err(|source| RandomizerError::ItemRequirement { name: item.name.clone(), source: Box::new(source) })?;
             logic.add_item_requirement(id, conditions);
         }
-        for flag in &data.flags {
+        for (idx, flag) in data.flags.iter().enumerate() {
             let id = flags[flag.name.as_str()];
-            let (reqs, _) = parse_reqs(flag.requirements.as_deref().unwrap_or(""));
-            let conditions = gen_reqs2(&reqs, &items, &flags, &locations, &parameters).map_err(|e| {
-                eprintln!("Error parsing flag {}", flag.name);
-                panic!("{}", e);
-            }).unwrap();
+            let conditions = parse(&format!("flags/{}/requirements", idx), flag.requirements.as_deref())
+                .map_err(|source| RandomizerError::FlagRequirement { name: flag.name.clone(), source: Box::new(source) })?;
             logic.add_flag_requirement(id, conditions);
         }
-        for location in &data.locations {
+        for (idx, location) in data.locations.iter().enumerate() {
             let id = locations[location.name.as_str()];
-            let (reqs, _) = parse_reqs(location.requirements.as_deref().unwrap_or(""));
-            let conditions = gen_reqs2(&reqs, &items, &flags, &locations, &parameters).map_err(|e| {
-                eprintln!("Error parsing location {}", location.name);
-                panic!("{}", e);
-            }).unwrap();
+            let conditions = parse(&format!("locations/{}/requirements", idx), location.requirements.as_deref())
+                .map_err(|source| RandomizerError::LocationRequirement { name: location.name.clone(), source: Box::new(source) })?;
             logic.add_location_requirement(id, conditions);
+            if let Some(when) = &location.when {
+                let when = parse_when(&format!("locations/{}/when", idx), when)
+                    .map_err(|source| RandomizerError::LocationWhen { name: location.name.clone(), source: Box::new(source) })?;
+                if !logic.evaluate_static(&when) {
+                    // Kept around, since requirements can still refer to reaching it
+                    logic.disable_location(id);
+                }
+            }
+            match (&location.vanilla, location.fixed.unwrap_or(false)) {
+                (Some(vanilla), fixed) => {
+                    let item = *items.get(vanilla.as_str()).ok_or_else(|| RandomizerError::UnknownVanillaItem {
+                        location: location.name.clone(),
+                        item: vanilla.clone(),
+                    })?;
+                    if fixed {
+                        logic.fix_item(id, item);
+                    }
+                }
+                (None, true) => return Err(RandomizerError::FixedWithoutVanilla(location.name.clone())),
+                (None, false) => {}
+            }
         }
-        
+        if let Some(goal) = &data.goal {
+            let conditions = parse("goal", Some(goal))
+                .map_err(|source| RandomizerError::GoalRequirement(Box::new(source)))?;
+            logic.set_goal(conditions);
+        }
+
         let mut item_pool = vec![];
-        for item in &data.item_pool {
+        for (idx, item) in data.item_pool.iter().enumerate() {
+            if let Some(when) = &item.when {
+                let when = parse_when(&format!("item_pool/{}/when", idx), when)
+                    .map_err(|source| RandomizerError::ItemWhen { name: item.name.clone(), source: Box::new(source) })?;
+                if !logic.evaluate_static(&when) {
+                    continue;
+                }
+            }
+            let count = match &item.count {
+                None => 1,
+                Some(ItemCount::Fixed(count)) => *count as i64,
+                Some(ItemCount::Parameter(name)) => {
+                    let name = name.strip_prefix("Parameter.").unwrap_or(name);
+                    match logic.get_parameter(name) {
+                        Some(param) if matches!(param.typ, ParameterType::Integer { .. }) => logic.parameter_number(name).unwrap_or_default(),
+                        _ => return Err(RandomizerError::InvalidItemCount { name: item.name.clone(), count: name.to_string() }),
+                    }
+                }
+            };
             let id = *items.get(item.name.as_str()).unwrap();
-            for _ in 0..item.count.unwrap_or(1) {
+            for _ in 0..count {
                 item_pool.push(id);
             }
         }
-        println!("{:#?}", restrictions);
-        (logic, item_pool)
+        Ok((logic, item_pool))
+    }
+}
+#[test]
+fn unknown_requirement_reports_location() {
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+locations:
+  - name: Chest
+    requirements: Swrod
+";
+    match LogicLoader::from_reader(yaml.as_bytes()) {
+        Err(RandomizerError::LocationRequirement { name, source }) => {
+            assert_eq!(name, "Chest");
+            assert_eq!((source.line, source.column), (Some(7), 19));
+            assert_eq!(source.snippet, "Swrod");
+        }
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Unknown name was accepted"),
+    }
+}
+#[test]
+fn syntax_errors_in_folded_requirements_are_placed() {
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Shield
+locations:
+  - name: Chest
+    requirements: >
+      Sword,
+      (+2 Sword, Shield)
+";
+    match LogicLoader::from_reader(yaml.as_bytes()) {
+        Err(RandomizerError::LocationRequirement { source, .. }) => {
+            assert_eq!((source.line, source.column), (Some(10), 11));
+            assert_eq!(source.diagnostic(), "10 |       (+2 Sword, Shield)\n   |           ^^^^^");
+        }
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Missing comma was accepted"),
+    }
+}
+#[test]
+fn negated_parameters() {
+    use super::ItemCondition;
+    let yaml = "
+parameters:
+  - name: Glitched
+    type: Boolean
+    default: False
+flags: []
+item_pool:
+  - name: Sword
+locations:
+  - name: Chest
+    requirements: (| Sword, (! Glitched))
+  - name: Ledge
+    requirements: (& Sword, (! Glitched))
+";
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, _) = prelogic.build(&pool);
+    let requirements: Vec<_> = logic.locations.iter().map(|(_, req)| req.clone()).collect();
+    let sword = logic.items[0].0.clone();
+    assert_eq!(requirements, vec![ItemCondition::no_requirements(), ItemCondition::item(sword, 1)]);
+
+    let yaml = yaml.replace("(& Sword, (! Glitched))", "(! Sword)");
+    match LogicLoader::from_reader(yaml.as_bytes()) {
+        Err(RandomizerError::LocationRequirement { name, source }) => {
+            assert_eq!(name, "Ledge");
+            assert_eq!(source.snippet, "Sword");
+        }
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Negated item was accepted"),
+    }
+}
+#[test]
+fn enum_and_integer_parameters() {
+    use super::ItemCondition;
+    let yaml = "
+parameters:
+  - name: ElementSetting
+    type:
+      Enum:
+        values: [Vanilla, Shuffled, Removed]
+    default: Removed
+  - name: FigurineCount
+    type:
+      Integer:
+        min: 0
+        max: 136
+    default: 3
+flags: []
+item_pool:
+  - name: Figurine
+    count: 136
+locations:
+  - name: Element
+    requirements: Parameter.ElementSetting == 2
+  - name: ShuffledElement
+    requirements: ElementSetting == Shuffled
+  - name: FigurineHouse
+    requirements: (+FigurineCount, Figurine)
+";
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, _) = prelogic.build(&pool);
+    let figurine = logic.items[0].0.clone();
+    let requirements: Vec<_> = logic.locations.iter().map(|(_, req)| req.clone()).collect();
+    assert_eq!(requirements, vec![
+        ItemCondition::no_requirements(),
+        ItemCondition::unattainable(),
+        ItemCondition::at_least(3, vec![(figurine, 1)]),
+    ]);
+
+    let bad = yaml.replace("ElementSetting == Shuffled", "ElementSetting == Shuffle");
+    match LogicLoader::from_reader(bad.as_bytes()) {
+        Err(RandomizerError::LocationRequirement { source, .. }) => assert_eq!(source.line, Some(22)),
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Unknown enum value was accepted"),
+    }
+    let bad = yaml.replace("(+FigurineCount", "(+ElementSetting");
+    assert!(LogicLoader::from_reader(bad.as_bytes()).is_err());
+    let bad = yaml.replace("default: 3", "default: 300");
+    assert!(matches!(LogicLoader::from_reader(bad.as_bytes()), Err(RandomizerError::InvalidParameterValue { .. })));
+}
+#[test]
+fn item_pool_depends_on_parameters() {
+    let yaml = "
+parameters:
+  - name: Keysanity
+    type: Boolean
+  - name: Rupees
+    type:
+      Integer:
+        min: 0
+        max: 10
+    default: 4
+flags: []
+item_pool:
+  - name: Sword
+  - name: Key
+    when: Keysanity
+  - name: Rupee
+    count: Rupees
+locations:
+  - name: Chest
+  - name: KeyChest
+    when: Keysanity
+";
+    let names = |preset: &Preset| {
+        let (prelogic, pool) = LogicLoader::from_reader_with_preset(yaml.as_bytes(), preset).unwrap();
+        let (logic, pool) = prelogic.build(&pool);
+        let items: Vec<_> = pool.iter().map(|(item, _)| item.name.clone()).collect();
+        (items.join(","), logic.locations.len())
+    };
+    assert_eq!(names(&Preset::default()), ("Sword,Rupee,Rupee,Rupee,Rupee".to_string(), 1));
+    let mut preset = Preset::default();
+    preset.set("Keysanity", "true");
+    preset.set("Rupees", "1");
+    assert_eq!(names(&preset), ("Sword,Key,Rupee".to_string(), 2));
+
+    let bad = yaml.replace("when: Keysanity\n  - name: Rupee", "when: Sword\n  - name: Rupee");
+    match LogicLoader::from_reader(bad.as_bytes()) {
+        Err(RandomizerError::ItemWhen { name, source }) => {
+            assert_eq!(name, "Key");
+            assert_eq!((source.line, source.column), (Some(15), 11));
+        }
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Item in when was accepted"),
+    }
+}
+#[test]
+fn fixed_items_stay_in_place() {
+    use super::{Algorithm, AllocatorSettings};
+    use crate::Generator;
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Shield
+  - name: Rupee
+locations:
+  - name: Start
+  - name: Cave
+    requirements: Sword
+  - name: Pedestal
+    requirements: Sword
+    vanilla: Shield
+    fixed: true
+  - name: Boss
+    requirements: Shield
+";
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    assert_eq!(logic.fixed.len(), 1);
+    for algorithm in [Algorithm::Forward, Algorithm::Assumed] {
+        let settings = AllocatorSettings { algorithm, ..Default::default() };
+        let generated = Generator::new(&logic, &pool, settings).generate(1).unwrap();
+        let assignments: Vec<_> = generated.assignments.iter().map(|(loc, item)| (loc.name.as_str(), item.name.as_str())).collect();
+        assert!(assignments.contains(&("Pedestal", "Shield")));
+        assert!(assignments.contains(&("Start", "Sword")));
+        let spheres: Vec<_> = generated.report.spheres.iter().map(|sphere| sphere.items.len()).collect();
+        assert_eq!(&spheres[..2], [1, 2]);
+    }
+
+    let bad = yaml.replace("    vanilla: Shield\n", "");
+    assert!(matches!(LogicLoader::from_reader(bad.as_bytes()), Err(RandomizerError::FixedWithoutVanilla(_))));
+    let bad = yaml.replace("vanilla: Shield", "vanilla: Sheild");
+    assert!(matches!(LogicLoader::from_reader(bad.as_bytes()), Err(RandomizerError::UnknownVanillaItem { .. })));
+}
+#[test]
+fn excluded_locations_only_get_junk() {
+    use super::{Algorithm, AllocatorSettings};
+    use crate::{AssignmentChecker, Generator};
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Rupee
+    count: 2
+locations:
+  - name: Start
+  - name: Chest
+  - name: Field
+  - name: Cave
+    requirements: Sword, Bow
+";
+    let mut preset = Preset::default();
+    preset.exclude("Chest");
+    let (prelogic, pool) = LogicLoader::from_reader_with_preset(yaml.as_bytes(), &preset).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    for algorithm in [Algorithm::Forward, Algorithm::Assumed] {
+        let settings = AllocatorSettings { algorithm, ..Default::default() };
+        for seed in 0..10 {
+            let generated = Generator::new(&logic, &pool, settings).generate(seed).unwrap();
+            let chest = generated.assignments.iter().find(|(loc, _)| loc.name == "Chest").unwrap();
+            assert_eq!(chest.1.name, "Rupee");
+        }
+    }
+
+    let item = |name: &str| logic.items.iter().find(|(item, _)| item.name == name).unwrap().0.clone();
+    let assignments = logic.locations.iter().zip(["Rupee", "Sword", "Bow", "Rupee"]).map(|((loc, _), name)| (loc.clone(), item(name))).collect();
+    let report = AssignmentChecker::from_logic(&logic).check_assignments(&assignments);
+    assert!(report.passed());
+    assert_eq!(report.excluded_progression_items.len(), 1);
+    assert!(!report.satisfies(super::Accessibility::Full));
+
+    preset.exclude("Chset");
+    assert!(matches!(LogicLoader::from_reader_with_preset(yaml.as_bytes(), &preset), Err(RandomizerError::UnknownExcludedLocation(_))));
+}
+#[test]
+fn restricted_items_keep_their_room() {
+    use super::{Algorithm, AllocatorSettings};
+    use crate::Generator;
+    // The maps only fit in the dungeon, so the sword and bow must take the two locations
+    // outside it, although either could go in the dungeon too
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Rupee
+  - name: Map
+    category: Minor
+    restriction: Dungeon
+    count: 4
+locations:
+  - name: Start
+  - name: Field
+  - name: Dungeon1
+    restriction: Dungeon
+  - name: Dungeon2
+    restriction: Dungeon
+  - name: Dungeon3
+    restriction: Dungeon
+  - name: Dungeon4
+    restriction: Dungeon
+  - name: Cave
+    requirements: Sword, Bow
+";
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    for algorithm in [Algorithm::Forward, Algorithm::Assumed] {
+        let settings = AllocatorSettings { algorithm, ..Default::default() };
+        let generator = Generator::new(&logic, &pool, settings);
+        for seed in 0..20 {
+            assert!(generator.generate(seed).is_ok(), "{:?} failed on seed {}", algorithm, seed);
+        }
+    }
+}
+#[test]
+fn placements_follow_restrictions_and_categories() {
+    use super::{Algorithm, AllocatorSettings};
+    use crate::Generator;
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Rupee
+    category: Minor
+    count: 3
+  - name: Key
+    category: Minor
+    restriction: Dungeon
+  - name: BigKey
+    restriction: Dungeon
+locations:
+  - name: Start
+  - name: Pot
+    category: Minor
+  - name: Grass
+    category: Minor
+  - name: Lobby
+    restriction: Dungeon
+  - name: Vault
+    restriction: Dungeon
+  - name: Barrel
+    category: Minor
+    restriction: Dungeon
+  - name: Crate
+    category: Minor
+    restriction: Dungeon
+    requirements: Key
+  - name: Boss
+    restriction: Dungeon
+    requirements: BigKey, Sword
+  - name: Field
+    requirements: Bow
+";
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    for algorithm in [Algorithm::Forward, Algorithm::Assumed] {
+        for match_category in [false, true] {
+            let settings = AllocatorSettings { algorithm, match_category, ..Default::default() };
+            let generator = Generator::new(&logic, &pool, settings).with_policy(crate::RetryPolicy { max_attempts: 10, backtrack: 0 });
+            for seed in 0..10 {
+                let generated = generator.generate(seed).unwrap();
+                for (loc, item) in &generated.assignments {
+                    assert!(!match_category || loc.category == item.category, "{} in {} ({:?})", item, loc, algorithm);
+                    assert!(item.restriction.is_none() || item.restriction == loc.restriction, "{} in {} ({:?})", item, loc, algorithm);
+                }
+            }
+        }
+    }
+}
+/// A flag, a threshold, an item with requirements of its own and a goal; shared by the tests
+/// of evaluating requirements
+#[cfg(test)]
+pub(crate) const SHOP_LOGIC: &str = "
+flags:
+  - name: CanFight
+    requirements: (| Sword, Bow)
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Rupee
+    count: 5
+  - name: Shield
+    requirements: Rupee*3
+locations:
+  - name: Start
+  - name: Cave
+    requirements: CanFight
+  - name: Shop
+    requirements: (+4, Rupee), Sword
+goal: Locations.Cave, Shield
+";
+#[test]
+fn reachable_with_inventory() {
+    use super::Inventory;
+    let (prelogic, pool) = LogicLoader::from_reader(SHOP_LOGIC.as_bytes()).unwrap();
+    let (logic, _) = prelogic.build(&pool);
+    let item = |name: &str| logic.items.iter().find(|(item, _)| item.name == name).unwrap().0.clone();
+    let names = |inventory: &Inventory| {
+        let reachable = logic.reachable(inventory);
+        let locations: Vec<_> = reachable.locations.iter().map(|l| l.name.as_str()).collect();
+        (locations.join(","), reachable.flags.len(), reachable.items.len(), reachable.goal)
+    };
+    let mut inventory = logic.inventory();
+    assert_eq!(names(&inventory), ("Start".to_string(), 0, 3, Some(false)));
+    inventory.add(&item("Bow"), 1);
+    inventory.add(&item("Rupee"), 3);
+    assert_eq!(names(&inventory), ("Start,Cave".to_string(), 1, 4, Some(false)));
+    inventory.add(&item("Sword"), 1);
+    inventory.add(&item("Rupee"), 1);
+    inventory.add(&item("Shield"), 1);
+    assert_eq!(names(&inventory), ("Start,Cave,Shop".to_string(), 1, 4, Some(true)));
+    // Nothing was assumed into the logic along the way
+    assert_eq!(names(&logic.inventory()), ("Start".to_string(), 0, 3, Some(false)));
+}
+#[test]
+fn items_compare_by_build_and_index() {
+    use super::ConditionNode;
+    use std::{collections::HashSet, sync::Arc};
+    let (prelogic, pool) = LogicLoader::from_reader(SHOP_LOGIC.as_bytes()).unwrap();
+    let (logic, _) = prelogic.build(&pool);
+    let mut items: Vec<_> = logic.items.iter().map(|(item, _)| item.clone()).collect();
+    items.reverse();
+    items.sort();
+    assert!(items.iter().enumerate().all(|(i, item)| item.index == i));
+    let mut renamed = (*items[0]).clone();
+    renamed.name.push('!');
+    assert_eq!(&renamed, &*items[0]);
+    assert!(logic.locations.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+    // Another build has its own items, even of the same file, and conditions made of them
+    let (again, _) = prelogic.build(&pool);
+    assert_ne!(again.items[0].0, logic.items[0].0);
+    let shop = |logic: &super::Logic| logic.locations.iter().find(|(loc, _)| loc.name == "Shop").unwrap().1.clone();
+    assert_ne!(shop(&again), shop(&logic));
+
+    // Indices line up between different logics too, which mustn't mix up their requirements
+    fn mentions(node: &ConditionNode, items: &mut HashSet<*const super::ItemDef>) {
+        match node {
+            ConditionNode::NoRequirements | ConditionNode::Unattainable => {}
+            ConditionNode::Item(item, _) => {
+                items.insert(Arc::as_ptr(item));
+            }
+            ConditionNode::AtLeast(_, weights) => items.extend(weights.iter().map(|(item, _)| Arc::as_ptr(item))),
+            ConditionNode::And(conds) | ConditionNode::Or(conds) => conds.iter().for_each(|cond| mentions(cond, items)),
+        }
+    }
+    // Both stay alive, so the interner holds on to the first one's conditions
+    let logics: Vec<_> = [include_str!("../../data.yaml"), include_str!("../../supermetroid.yml")]
+        .into_iter()
+        .map(|source| {
+            let (prelogic, pool) = LogicLoader::from_reader(source.as_bytes()).unwrap();
+            prelogic.build(&pool).0
+        })
+        .collect();
+    for logic in &logics {
+        let own: HashSet<_> = logic.items.iter().map(|(item, _)| Arc::as_ptr(item)).collect();
+        let mut mentioned = HashSet::new();
+        let requirements = (logic.locations.iter().map(|(_, req)| req))
+            .chain(logic.flags.iter().map(|(_, req)| req))
+            .chain(&logic.goal);
+        for req in requirements {
+            mentions(req, &mut mentioned);
+        }
+        assert!(mentioned.is_subset(&own));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use yaml_rust::{
+    parser::{MarkedEventReceiver, Parser},
+    scanner::{Marker, TScalarStyle},
+    Event,
+};
+
+use crate::logic_parse::LogicParseError;
+
+/// Where each expression starts in a logic file, so errors in them can be reported by
+/// line and column. serde_yaml throws positions away, so the file is scanned a second
+/// time with the underlying parser.
+pub(super) struct SourceMap<'a> {
+    source: &'a str,
+    /// Path of a value, such as `locations/3/requirements` or `goal` -> where it starts
+    expressions: HashMap<String, (Marker, TScalarStyle)>,
+}
+
+enum Frame {
+    Mapping { key: Option<String> },
+    Sequence { index: usize },
+}
+
+#[derive(Default)]
+struct Scanner {
+    stack: Vec<Frame>,
+    expressions: HashMap<String, (Marker, TScalarStyle)>,
+}
+
+impl Scanner {
+    /// Called after a value (scalar, mapping or sequence) was read in the current frame
+    fn value_done(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping { key }) => *key = None,
+            Some(Frame::Sequence { index }) => *index += 1,
+            None => {}
+        }
+    }
+
+    fn record(&mut self, mark: Marker, style: TScalarStyle) {
+        let path = match self.stack.as_slice() {
+            [Frame::Mapping { key: Some(section) }, Frame::Sequence { index }, Frame::Mapping { key: Some(field) }] => {
+                format!("{}/{}/{}", section, index, field)
+            }
+            [Frame::Mapping { key: Some(section) }] => section.clone(),
+            _ => return,
+        };
+        self.expressions.insert(path, (mark, style));
+    }
+}
+
+impl MarkedEventReceiver for Scanner {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, style, ..) => {
+                if let Some(Frame::Mapping { key: key @ None }) = self.stack.last_mut() {
+                    *key = Some(value);
+                } else {
+                    self.record(mark, style);
+                    self.value_done();
+                }
+            }
+            Event::MappingStart(_) => self.stack.push(Frame::Mapping { key: None }),
+            Event::SequenceStart(_) => self.stack.push(Frame::Sequence { index: 0 }),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.value_done();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let mut scanner = Scanner::default();
+        // serde_yaml has already rejected invalid files; anything found before an error is still useful
+        let _ = Parser::new(source.chars()).load(&mut scanner, false);
+        SourceMap {
+            source,
+            expressions: scanner.expressions,
+        }
+    }
+
+    /// Moves `error`, which is for the expression `value` found at `path`, to its place in the file
+    pub fn place(&self, path: &str, value: &str, error: LogicParseError) -> LogicParseError {
+        match self.position(path, value, error.offset) {
+            Some((line, column)) => {
+                let context = self.source.lines().nth(line - 1).unwrap_or_default();
+                error.at(line, column, context)
+            }
+            None => error,
+        }
+    }
+
+    /// 1-based line and column of byte `offset` of `value` in the file
+    fn position(&self, path: &str, value: &str, offset: usize) -> Option<(usize, usize)> {
+        let (mark, style) = self.expressions.get(path)?;
+        let mut source = self.source.chars().skip(mark.index()).peekable();
+        let (mut line, mut column) = (mark.line(), mark.col() + 1);
+        let mut advance = |source: &mut std::iter::Peekable<std::iter::Skip<std::str::Chars>>| {
+            if source.next() == Some('\n') {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        };
+        if let TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted = style {
+            advance(&mut source);
+        }
+
+        // Folding and indentation change whitespace, but nothing else
+        let mut chars = value.char_indices().peekable();
+        while let Some(&(i, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            while source.peek().is_some_and(|c| c.is_whitespace()) {
+                advance(&mut source);
+            }
+            if i >= offset {
+                break;
+            }
+            if source.peek() != Some(&c) {
+                return None;
+            }
+            advance(&mut source);
+            chars.next();
+        }
+        Some((line, column))
+    }
+}
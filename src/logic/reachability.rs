@@ -0,0 +1,624 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+    sync::Arc,
+};
+
+use super::{BitSet, ConditionNode, Conditionals, Flag, Inventory, ItemCondition, ItemDef, Location};
+
+/// Something that has a requirement standing in the way
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Unlock {
+    Location(Arc<Location>),
+    Flag(Arc<Flag>),
+    /// Being able to use an item, for items with requirements of their own
+    Item(Arc<ItemDef>),
+    Goal,
+}
+
+/// What a node's counter counts
+#[derive(Debug, Clone, Copy)]
+enum NodeKind {
+    /// Collected items, weighted
+    Items,
+    /// Children that are met, all of which need to be
+    All,
+    /// Children that are met, one of which needs to be
+    Any,
+}
+
+/// The tracked conditions compiled to flat tables, which never change and are shared between
+/// clones. Every distinct condition node gets a counter, and is met once it reaches `need`.
+/// Items, locations and flags are looked up by their index.
+struct Index {
+    need: Vec<usize>,
+    kinds: Vec<NodeKind>,
+    /// Where each node's inputs are: in `weights` for `Items`, in `children` otherwise
+    inputs: Vec<Range<usize>>,
+    /// Item indices and the weight each one counts for
+    weights: Vec<(usize, usize)>,
+    children: Vec<usize>,
+    parents: Vec<Vec<usize>>,
+    /// The unlocks each node is the whole requirement of
+    roots: Vec<Vec<usize>>,
+    /// The leaves that count each item, with the weight they give it
+    leaves: Vec<Vec<(usize, usize)>>,
+    /// Every item a requirement mentions or that's tracked, by index
+    item_defs: Vec<Option<Arc<ItemDef>>>,
+    /// The unlocks whose requirement mentions each item
+    mentions: Vec<Vec<usize>>,
+    unlocks: Vec<Unlock>,
+    /// Each unlock's requirement as given, and its node
+    conditions: Vec<ItemCondition>,
+    nodes: Vec<usize>,
+    /// Each unlock's position in `unlocks`
+    locations: Vec<Option<usize>>,
+    flags: Vec<Option<usize>>,
+    items: Vec<Option<usize>>,
+    goal: Option<usize>,
+}
+
+/// The entry for `index`, growing the table if it doesn't have one yet
+fn entry<T: Clone + Default>(table: &mut Vec<T>, index: usize) -> &mut T {
+    if index >= table.len() {
+        table.resize(index + 1, T::default());
+    }
+    &mut table[index]
+}
+
+impl Index {
+    fn add_node(&mut self, cond: &ItemCondition, ids: &mut HashMap<ItemCondition, usize>) -> usize {
+        if let Some(&id) = ids.get(cond) {
+            return id;
+        }
+        let children: Vec<_> = match &**cond {
+            ConditionNode::And(conds) | ConditionNode::Or(conds) => {
+                conds.iter().map(|c| self.add_node(c, ids)).collect()
+            }
+            _ => vec![],
+        };
+        let id = self.need.len();
+        self.parents.push(vec![]);
+        self.roots.push(vec![]);
+        let weights: Vec<_> = match &**cond {
+            ConditionNode::Item(item, _) => vec![(item.clone(), 1)],
+            ConditionNode::AtLeast(_, items) => items.clone(),
+            _ => vec![],
+        };
+        let weights: Vec<_> = weights
+            .into_iter()
+            .map(|(item, weight)| {
+                let index = item.index;
+                *entry(&mut self.item_defs, index) = Some(item);
+                (index, weight)
+            })
+            .collect();
+        let (kind, need) = match &**cond {
+            ConditionNode::NoRequirements => (NodeKind::All, 0),
+            // Nothing ever adds to its counter
+            ConditionNode::Unattainable => (NodeKind::Items, 1),
+            ConditionNode::Item(_, count) => (NodeKind::Items, *count),
+            ConditionNode::AtLeast(threshold, _) => (NodeKind::Items, *threshold),
+            ConditionNode::And(_) => (NodeKind::All, children.len()),
+            ConditionNode::Or(_) => (NodeKind::Any, 1),
+        };
+        self.need.push(need);
+        self.kinds.push(kind);
+        match kind {
+            NodeKind::Items => {
+                for &(item, weight) in &weights {
+                    entry(&mut self.leaves, item).push((id, weight));
+                }
+                let start = self.weights.len();
+                self.weights.extend(weights);
+                self.inputs.push(start..self.weights.len());
+            }
+            NodeKind::All | NodeKind::Any => {
+                for &child in &children {
+                    self.parents[child].push(id);
+                }
+                let start = self.children.len();
+                self.children.extend(children);
+                self.inputs.push(start..self.children.len());
+            }
+        }
+        ids.insert(cond.clone(), id);
+        id
+    }
+
+    fn position(&self, unlock: &Unlock) -> Option<usize> {
+        let (positions, index) = match unlock {
+            Unlock::Location(loc) => (&self.locations, loc.index),
+            Unlock::Flag(flag) => (&self.flags, flag.index),
+            Unlock::Item(item) => (&self.items, item.index),
+            Unlock::Goal => return self.goal,
+        };
+        positions
+            .get(index)
+            .copied()
+            .flatten()
+            .filter(|&position| self.unlocks[position] == *unlock)
+    }
+
+    fn position_mut(&mut self, unlock: &Unlock) -> &mut Option<usize> {
+        match unlock {
+            Unlock::Location(loc) => entry(&mut self.locations, loc.index),
+            Unlock::Flag(flag) => entry(&mut self.flags, flag.index),
+            Unlock::Item(item) => entry(&mut self.items, item.index),
+            Unlock::Goal => &mut self.goal,
+        }
+    }
+}
+
+/// Tracks which requirements the collected items meet. Adding an item only touches the
+/// counters of the conditions that mention it, so whatever it unlocks comes out without a
+/// sweep over everything. Clones share the compiled conditions and only copy the counters.
+///
+/// Items, locations and flags must come from the same [`PreLogic::build`](super::PreLogic::build),
+/// so that their indices are distinct.
+#[derive(Clone)]
+pub struct ReachabilityEngine {
+    index: Arc<Index>,
+    counters: Vec<usize>,
+    met: Vec<bool>,
+    released: Vec<bool>,
+    collected: Inventory,
+    /// Every item collected, in order
+    history: Vec<Arc<ItemDef>>,
+    /// What's left of each unlock's requirement, and how much of `history` it accounts for.
+    /// They only catch up when asked for, since sweeps never look at them.
+    residuals: Vec<(ItemCondition, usize)>,
+}
+
+impl ReachabilityEngine {
+    /// Tracks the locations, flags, item requirements and goal. Items may be listed more than
+    /// once, as in an item pool.
+    pub fn new(
+        locations: &Conditionals<Location>,
+        flags: &Conditionals<Flag>,
+        items: &Conditionals<ItemDef>,
+        goal: Option<&ItemCondition>,
+    ) -> Self {
+        let mut index = Index {
+            need: vec![],
+            kinds: vec![],
+            inputs: vec![],
+            weights: vec![],
+            children: vec![],
+            parents: vec![],
+            roots: vec![],
+            leaves: vec![],
+            item_defs: vec![],
+            mentions: vec![],
+            unlocks: vec![],
+            conditions: vec![],
+            nodes: vec![],
+            locations: vec![],
+            flags: vec![],
+            items: vec![],
+            goal: None,
+        };
+        let conditions = (locations
+            .iter()
+            .map(|(loc, req)| (Unlock::Location(loc.clone()), req)))
+        .chain(
+            flags
+                .iter()
+                .map(|(flag, req)| (Unlock::Flag(flag.clone()), req)),
+        )
+        .chain(
+            items
+                .iter()
+                .map(|(item, req)| (Unlock::Item(item.clone()), req)),
+        )
+        .chain(goal.map(|goal| (Unlock::Goal, goal)));
+        let mut ids = HashMap::new();
+        for (unlock, req) in conditions {
+            if index.position(&unlock).is_some() {
+                continue;
+            }
+            if let Unlock::Item(item) = &unlock {
+                *entry(&mut index.item_defs, item.index) = Some(item.clone());
+            }
+            let position = index.unlocks.len();
+            let root = index.add_node(req, &mut ids);
+            index.roots[root].push(position);
+            let mut mentioned = BTreeMap::new();
+            req.missing(&mut mentioned);
+            for item in mentioned.into_keys() {
+                entry(&mut index.mentions, item.index).push(position);
+            }
+            *index.position_mut(&unlock) = Some(position);
+            index.unlocks.push(unlock);
+            index.conditions.push(req.clone());
+            index.nodes.push(root);
+        }
+        Self::start(Arc::new(index))
+    }
+
+    fn start(index: Arc<Index>) -> Self {
+        let mut me = ReachabilityEngine {
+            counters: vec![0; index.need.len()],
+            met: vec![false; index.unlocks.len()],
+            released: vec![false; index.unlocks.len()],
+            collected: Inventory::new(index.item_defs.len()),
+            history: vec![],
+            residuals: index
+                .conditions
+                .iter()
+                .map(|req| (req.clone(), 0))
+                .collect(),
+            index,
+        };
+        let mut unlocked = vec![];
+        for node in 0..me.index.need.len() {
+            if me.index.need[node] == 0 {
+                me.node_met(node, &mut unlocked);
+            }
+        }
+        me
+    }
+
+    /// The same requirements, with nothing collected
+    pub fn restart(&self) -> Self {
+        Self::start(self.index.clone())
+    }
+
+    /// Passes a newly met node on to its parents, and records the unlocks it was holding back
+    fn node_met(&mut self, node: usize, unlocked: &mut Vec<usize>) {
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            for &position in &self.index.roots[node] {
+                if !self.met[position] {
+                    self.met[position] = true;
+                    unlocked.push(position);
+                }
+            }
+            for &parent in &self.index.parents[node] {
+                self.counters[parent] += 1;
+                if self.counters[parent] == self.index.need[parent] {
+                    stack.push(parent);
+                }
+            }
+        }
+    }
+
+    /// Collects one more of `item`, and returns what it unlocks, in the order they were given
+    /// to `new`
+    pub fn add_item(&mut self, item: &Arc<ItemDef>) -> Vec<Unlock> {
+        let unlocked = self.count_item(item);
+        self.collect(item);
+        unlocked
+            .into_iter()
+            .map(|position| self.index.unlocks[position].clone())
+            .collect()
+    }
+
+    /// Like [`add_item`](Self::add_item), along with what each unlock still required before
+    pub fn add_item_explained(&mut self, item: &Arc<ItemDef>) -> Vec<(Unlock, ItemCondition)> {
+        let unlocked: Vec<_> = self
+            .count_item(item)
+            .into_iter()
+            .map(|position| {
+                (
+                    self.index.unlocks[position].clone(),
+                    self.residual(position),
+                )
+            })
+            .collect();
+        self.collect(item);
+        unlocked
+    }
+
+    /// Adds the item to the counters, and returns the positions of the unlocks it meets
+    fn count_item(&mut self, item: &ItemDef) -> Vec<usize> {
+        let index = self.index.clone();
+        let mut unlocked = vec![];
+        for &(leaf, weight) in index.leaves.get(item.index).into_iter().flatten() {
+            let before = self.counters[leaf];
+            self.counters[leaf] += weight;
+            if before < index.need[leaf] && self.counters[leaf] >= index.need[leaf] {
+                self.node_met(leaf, &mut unlocked);
+            }
+        }
+        unlocked.sort_unstable();
+        unlocked
+    }
+
+    /// Adds the item to the inventory, which changes what's left of the requirements that
+    /// mention it
+    fn collect(&mut self, item: &Arc<ItemDef>) {
+        // Nothing tracked can tell whether items it never mentions were collected
+        if self.index.item_defs.get(item.index).is_some_and(Option::is_some) {
+            self.collected.add(item, 1);
+        }
+        self.history.push(item.clone());
+        if cfg!(debug_assertions) {
+            for position in self.mentions(item).to_vec() {
+                debug_assert!(
+                    self.released[position]
+                        || self.met[position] == self.residual(position).satisfied(),
+                    "Counters disagree about {:?}",
+                    self.index.unlocks[position]
+                );
+            }
+        }
+    }
+
+    /// The unlocks whose requirement mentions the item, in order
+    fn mentions(&self, item: &ItemDef) -> &[usize] {
+        self.index
+            .mentions
+            .get(item.index)
+            .map_or(&[], |positions| &positions[..])
+    }
+
+    fn residual(&mut self, position: usize) -> ItemCondition {
+        let (req, seen) = &mut self.residuals[position];
+        if !self.released[position] {
+            for item in &self.history[*seen..] {
+                let mentions = self.index.mentions.get(item.index).map_or(&[][..], |p| &p[..]);
+                if mentions.binary_search(&position).is_ok() {
+                    req.assume_item(item, 1);
+                }
+            }
+        }
+        *seen = self.history.len();
+        req.clone()
+    }
+
+    /// Whether the requirement is tracked at all
+    pub fn tracks(&self, unlock: &Unlock) -> bool {
+        self.index.position(unlock).is_some()
+    }
+
+    /// Whether the requirement is met; false for anything that isn't tracked
+    pub fn is_met(&self, unlock: &Unlock) -> bool {
+        self.index
+            .position(unlock)
+            .is_some_and(|position| self.met[position])
+    }
+
+    /// Whether the requirement would be met with one more of `item`; false for anything
+    /// that isn't tracked
+    pub fn would_be_met_with(&self, unlock: &Unlock, item: &ItemDef) -> bool {
+        self.index.position(unlock).is_some_and(|position| {
+            self.met[position] || self.node_would_be_met(self.index.nodes[position], item.index)
+        })
+    }
+
+    /// Everything one more of `item` would unlock, in the order they were given to `new`
+    pub fn unlocked_by(&self, item: &ItemDef) -> Vec<&Unlock> {
+        let index = &*self.index;
+        // Counters would go up like in `count_item`, so follow them without touching any
+        let mut extra: HashMap<usize, usize> = HashMap::new();
+        let mut stack: Vec<_> = index
+            .leaves
+            .get(item.index)
+            .into_iter()
+            .flatten()
+            .filter(|&&(leaf, weight)| {
+                self.counters[leaf] < index.need[leaf]
+                    && self.counters[leaf] + weight >= index.need[leaf]
+            })
+            .map(|&(leaf, _)| leaf)
+            .collect();
+        let mut unlocked = vec![];
+        while let Some(node) = stack.pop() {
+            unlocked.extend(
+                index.roots[node]
+                    .iter()
+                    .filter(|&&position| !self.met[position]),
+            );
+            for &parent in &index.parents[node] {
+                let extra = extra.entry(parent).or_default();
+                *extra += 1;
+                if self.counters[parent] + *extra == index.need[parent] {
+                    stack.push(parent);
+                }
+            }
+        }
+        unlocked.sort_unstable();
+        unlocked
+            .into_iter()
+            .map(|&position| &index.unlocks[position])
+            .collect()
+    }
+
+    fn node_would_be_met(&self, node: usize, item: usize) -> bool {
+        let index = &*self.index;
+        if self.counters[node] >= index.need[node] {
+            return true;
+        }
+        let inputs = index.inputs[node].clone();
+        match index.kinds[node] {
+            NodeKind::Items => {
+                let extra: usize = index.weights[inputs]
+                    .iter()
+                    .filter(|(i, _)| *i == item)
+                    .map(|(_, weight)| weight)
+                    .sum();
+                self.counters[node] + extra >= index.need[node]
+            }
+            NodeKind::All => index.children[inputs]
+                .iter()
+                .all(|&child| self.node_would_be_met(child, item)),
+            NodeKind::Any => index.children[inputs]
+                .iter()
+                .any(|&child| self.node_would_be_met(child, item)),
+        }
+    }
+
+    /// What's left of the requirement, if it's tracked
+    pub fn requirement(&mut self, unlock: &Unlock) -> Option<ItemCondition> {
+        self.index
+            .position(unlock)
+            .map(|position| self.residual(position))
+    }
+
+    /// Adds the items the requirements still need to `rv`, each with how many more of it a
+    /// mention asks for. This is [`ItemCondition::missing`] of what's left of each requirement,
+    /// worked out from the counters rather than by rewriting the conditions, and parts that
+    /// requirements share are only looked at once.
+    pub fn missing<'a, 'u>(
+        &'a self,
+        unlocks: impl IntoIterator<Item = &'u Unlock>,
+        rv: &mut impl Extend<(&'a Arc<ItemDef>, usize)>,
+    ) {
+        let index = &*self.index;
+        let mut seen = BitSet::new(index.need.len());
+        let mut stack = vec![];
+        for unlock in unlocks {
+            match index.position(unlock) {
+                Some(position) if !self.met[position] => stack.push(index.nodes[position]),
+                _ => continue,
+            }
+            while let Some(node) = stack.pop() {
+                if self.counters[node] >= index.need[node] || !seen.insert(node) {
+                    continue;
+                }
+                let inputs = index.inputs[node].clone();
+                match index.kinds[node] {
+                    NodeKind::Items => {
+                        let left = index.need[node] - self.counters[node];
+                        rv.extend(index.weights[inputs].iter().map(|&(item, weight)| {
+                            let item = index.item_defs[item].as_ref().expect("Every leaf's items are known");
+                            (item, left.div_ceil(weight))
+                        }))
+                    }
+                    // Mentions come out in order, like they would walking the condition
+                    NodeKind::All | NodeKind::Any => stack.extend(index.children[inputs].iter().rev()),
+                }
+            }
+        }
+    }
+
+    /// The collected items that some tracked requirement mentions; no others make a difference
+    pub fn inventory(&self) -> &Inventory {
+        &self.collected
+    }
+
+    /// Everything whose requirement is met, in the order they were given to `new`
+    pub fn unlocked(&self) -> impl Iterator<Item = &Unlock> {
+        self.index
+            .unlocks
+            .iter()
+            .zip(&self.met)
+            .filter(|(_, met)| **met)
+            .map(|(unlock, _)| unlock)
+    }
+
+    /// Treats the requirement as met from now on, whatever it still needs
+    pub fn release(&mut self, unlock: &Unlock) {
+        if let Some(position) = self.index.position(unlock) {
+            self.met[position] = true;
+            self.released[position] = true;
+            self.residuals[position].0 = ItemCondition::no_requirements();
+        }
+    }
+}
+
+#[test]
+fn engine_unlocks_incrementally() {
+    let (prelogic, pool) = crate::LogicLoader::from_reader(super::SHOP_LOGIC.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let item = |name: &str| {
+        pool.iter()
+            .find(|(item, _)| item.name == name)
+            .unwrap()
+            .0
+            .clone()
+    };
+    let location = |name: &str| {
+        let (loc, _) = logic
+            .locations
+            .iter()
+            .find(|(loc, _)| loc.name == name)
+            .unwrap();
+        Unlock::Location(loc.clone())
+    };
+    let names = |unlocked: Vec<Unlock>| -> Vec<String> {
+        unlocked
+            .into_iter()
+            .map(|unlock| match unlock {
+                Unlock::Location(loc) => loc.name.clone(),
+                Unlock::Flag(flag) => flag.name.clone(),
+                Unlock::Item(item) => item.name.clone(),
+                Unlock::Goal => "Goal".to_string(),
+            })
+            .collect()
+    };
+
+    let mut reach =
+        ReachabilityEngine::new(&logic.locations, &logic.flags, &pool, logic.goal.as_ref());
+    assert_eq!(reach.unlocked().count(), 4);
+    assert!(reach.is_met(&location("Start")) && !reach.is_met(&location("Shop")));
+    assert_eq!(names(reach.add_item(&item("Sword"))), ["Cave", "CanFight"]);
+    // Neither is re-reported
+    assert!(reach.add_item(&item("Bow")).is_empty());
+    for _ in 0..2 {
+        assert!(reach.add_item(&item("Rupee")).is_empty());
+    }
+    assert_eq!(
+        reach.requirement(&location("Shop")),
+        Some(ItemCondition::item(item("Rupee"), 2))
+    );
+    assert!(!reach.would_be_met_with(&location("Shop"), &item("Rupee")));
+    let unlocked = reach.add_item_explained(&item("Rupee"));
+    assert_eq!(
+        unlocked,
+        [(
+            Unlock::Item(item("Shield")),
+            ItemCondition::item(item("Rupee"), 1)
+        )]
+    );
+    assert!(reach.would_be_met_with(&location("Shop"), &item("Rupee")));
+    assert!(!reach.would_be_met_with(&location("Shop"), &item("Bow")));
+    assert_eq!(names(reach.add_item(&item("Rupee"))), ["Shop"]);
+    assert_eq!(names(reach.add_item(&item("Shield"))), ["Goal"]);
+
+    let mut reach = reach.restart();
+    assert_eq!(reach.unlocked().count(), 4);
+    reach.release(&location("Shop"));
+    assert!(reach.requirement(&location("Shop")).unwrap().satisfied());
+    for _ in 0..4 {
+        reach.add_item(&item("Rupee"));
+    }
+    assert_eq!(names(reach.add_item(&item("Sword"))), ["Cave", "CanFight"]);
+}
+
+#[test]
+fn engine_agrees_with_reachable() {
+    use std::collections::HashSet;
+    let (prelogic, pool) =
+        crate::LogicLoader::from_reader(include_str!("../../data.yaml").as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let mut reach = ReachabilityEngine::new(
+        &logic.locations,
+        &logic.flags,
+        &logic.items,
+        logic.goal.as_ref(),
+    );
+    let mut inventory = logic.inventory();
+    for (item, _) in &pool {
+        reach.add_item(item);
+        inventory.add(item, 1);
+        let reachable = logic.reachable(&inventory);
+        let expected: HashSet<_> = (reachable.locations.into_iter().map(Unlock::Location))
+            .chain(reachable.flags.into_iter().map(Unlock::Flag))
+            .chain(reachable.items.into_iter().map(Unlock::Item))
+            .chain(reachable.goal.filter(|goal| *goal).map(|_| Unlock::Goal))
+            .collect();
+        assert_eq!(reach.unlocked().cloned().collect::<HashSet<_>>(), expected);
+        // The counters see everything that's left of the conditions as missing. They can also
+        // see items that absorption took out, like the extra items of an `|` branch that needs more
+        for (location, _) in &logic.locations {
+            let unlock = Unlock::Location(location.clone());
+            let residual = reach.requirement(&unlock).unwrap();
+            let (mut from_counters, mut from_residual) = (HashSet::new(), HashSet::new());
+            reach.missing([&unlock], &mut from_counters);
+            residual.missing(&mut from_residual);
+            assert!(from_residual.is_subset(&from_counters), "{}", location);
+        }
+    }
+}
@@ -1,246 +1,334 @@
-use std::collections::{HashSet, HashMap};
+use std::{collections::HashMap, ops::Range};
 
-use heck::ToSnakeCase;
+use crate::logic::{Comparison, Condition, ItemId, FlagId, LocationId, ParameterType, Threshold};
 
-use crate::logic::{Condition, ItemId, FlagId, LocationId};
+/// Byte range of a term within its requirement expression
+pub type Span = Range<usize>;
 
-pub fn parse_logic(s: &str) {
-    let lines = s.lines()
-        .map(|line| line.trim())
-        .filter(|line| !(line.is_empty() || line.starts_with('#')));
-    let mut items = HashSet::new();
-    for line in lines {
-        if line.starts_with('!') {
-            continue;
-        }
-        
-        let bits: Vec<_> = line.split(';').map(|s| s.trim()).collect();
-        
-        if bits.len() < 2 {
-            println!("{:?}", bits);
-            continue;
-
-        }
-        let location: Vec<_> = bits[0].split(':').collect();
-
-        if bits[1] == "Helper" {
-            let (reqs, _) = parse_reqs(bits[3]);
-            get_items(&reqs, &mut items);
-            let req_str = gen_reqs(&reqs);
-            let var_name = bits[0].split(':').next().expect("Always at least one piece");
-            // println!("let flag_{} = cond_fact.add_flag(\"{}\", {});", var_name, bits[0], req_str);
-//             println!("
-//   - name: {}
-//     requirements: {}", var_name, bits[3]);
-        } else if bits[1] == "Major" || bits[1] == "Minor"  || bits[1] == "DungeonItem" {
-            let (reqs, _) = parse_reqs(bits[3]);
-            get_items(&reqs, &mut items);
-            let req_str = gen_reqs(&reqs);
-            let var_name = location[0].to_snake_case();
-            let category = if location.len() > 1 {
-                format!("ItemCategory::Class({})", location[1])
-            } else {
-                format!("ItemCategory::{}", bits[1])
-            };
-            // println!("let loc_{} = cond_fact.add_location(\"{}\", {}, {});", var_name, bits[0], req_str, category);
-                        print!("
-  - name: {}
-    category: {}
-    requirements: {}", location[0], bits[1], bits[3]);
-            if location.len() > 1 {
-            println!("
-    restriction: {}", location[1]);
-            }
-        }
-    }
-    for item in items {
-        // println!("let {} = cond_fact.add_item(\"{}\", ItemCategory::Major);", item.to_snake_case(), item);
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term<'a> {
+    /// A name, optionally with a count: `Prefix.Name*count`
+    Lit { name: &'a str, count: Option<u32>, span: Span },
+    /// A parameter compared with a value: `Parameter.Name == value`
+    Compare { name: &'a str, comparison: Comparison, value: &'a str, span: Span },
+    And(Vec<Term<'a>>, Span),
+    Or(Vec<Term<'a>>, Span),
+    Count(Bound<'a>, Vec<Term<'a>>, Span),
+    Not(Box<Term<'a>>, Span),
 }
 
+/// The threshold of a `(+N, ...)` term
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Term<'a> {
-    Lit(&'a str),
-    And(Vec<Term<'a>>),
-    Or(Vec<Term<'a>>),
-    Count(u32, Vec<Term<'a>>)
+pub enum Bound<'a> {
+    Number(u32),
+    /// An integer parameter
+    Name(&'a str, Span),
 }
 
-fn get_items(terms: &[Term], collected: &mut HashSet<String>) {
-    for term in terms {
-        match term {
-            Term::Lit(s) => if let Some(mut item) = s.strip_prefix("Items.") {
-                item = item.split(':').next().expect("We always have at least *one* string piece");
-                if !collected.contains(item) {
-                    collected.insert(item.to_string());
-                }
-            },
-            Term::And(terms) => get_items(terms, collected),
-            Term::Or(terms) => get_items(terms, collected),
-            Term::Count(_, terms) => get_items(terms, collected),
+impl std::fmt::Display for Bound<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bound::Number(n) => write!(f, "{}", n),
+            Bound::Name(name, _) => write!(f, "{}", name),
         }
     }
 }
 
-fn gen_reqs(terms: &[Term<'_>]) -> String {
-    match terms {
-        [] => "Condition::NoRequirements".into(),
-        [ref a] => gen_req(a),
-        _ => gen_req(&Term::And(terms.to_vec()))
+impl Term<'_> {
+    pub fn span(&self) -> Span {
+        match self {
+            Term::Lit { span, .. } | Term::Compare { span, .. } | Term::And(_, span) | Term::Or(_, span) | Term::Count(_, _, span) | Term::Not(_, span) => span.clone(),
+        }
     }
 }
 
-fn gen_req(term: &Term) -> String {
-    match term {
-        Term::Lit(s) => {
-            if let Some(mut item) = s.strip_prefix("Items.") {
-                let mut bits = item.split(':');
-                item = bits.next().expect("We always have at least *one* string piece");
-                if let Some(count) = bits.next() {
-                    format!("Condition::Item({}, {})", item.to_snake_case(), count)
-                } else {
-                    item.to_snake_case()
+impl std::fmt::Display for Term<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let write_terms = |f: &mut std::fmt::Formatter<'_>, terms: &[Term]| {
+            for (i, term) in terms.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
                 }
-            } else if let Some(helper) = s.strip_prefix("Helpers.") {
-                format!("flag_{}", helper.to_snake_case())
-            } else if let Some(location) = s.strip_prefix("Locations.") {
-                format!("loc_{}", location.to_snake_case())
-            } else {
-                eprintln!("?? {}", s);
-                s.to_snake_case()
+                write!(f, "{}", term)?;
             }
-        },
-        Term::And(terms) => {
-            let mut s = "cond_and!(".to_string();
-            let mut first = true;
-            for term in terms {
-                if !first {
-                    s.push_str(", ");
-                }
-                first = false;
-                s.push_str(&gen_req(term));                
+            write!(f, ")")
+        };
+        match self {
+            Term::Lit { name, count: None, .. } => write!(f, "{}", name),
+            Term::Lit { name, count: Some(count), .. } => write!(f, "{}*{}", name, count),
+            Term::Compare { name, comparison, value, .. } => write!(f, "{} {} {}", name, comparison, value),
+            Term::And(terms, _) => {
+                write!(f, "(& ")?;
+                write_terms(f, terms)
             }
-            s.push(')');
-            s
-        },
-        Term::Or(terms) => 
-        {
-            let mut s = "cond_or!(".to_string();
-            let mut first = true;
-            for term in terms {
-                if !first {
-                    s.push_str(", ");
-                }
-                first = false;
-                s.push_str(&gen_req(term));
+            Term::Or(terms, _) => {
+                write!(f, "(| ")?;
+                write_terms(f, terms)
             }
-            s.push(')');
-            s
-        },
-        Term::Count(threshold, terms) => {
-            let mut s = format!("Condition::AtLeast({}, vec![", threshold);
-            let mut first = true;
-            for term in terms {
-                if !first {
-                    s.push_str(", ");
-                }
-                first = false;
-                match term {
-                    Term::Lit(lit) => {
-                        let mut it = lit.split(':');
-                        let name = it.next().unwrap();
-                        s.push('(');
-                        if let Some(item) = name.strip_prefix("Items.") {
-                            s.push_str(&item.to_snake_case());
-                        }
-                        let weight = it.next();
-                        s.push_str(", ");
-                        s.push_str(weight.unwrap_or("1"));
-                        s.push(')');
+            Term::Count(threshold, terms, _) => {
+                write!(f, "(+{}, ", threshold)?;
+                write_terms(f, terms)
+            }
+            Term::Not(term, _) => write!(f, "(! {})", term),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    Open,
+    Close,
+    Or,
+    And,
+    Plus,
+    Not,
+    Comma,
+    Star,
+    Compare(Comparison),
+    /// A name or a number
+    Word(&'a str),
+    End,
+}
 
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | ':' | '-')
+}
+
+fn tokenize(s: &str) -> Result<Vec<(Token<'_>, Span)>, LogicParseError> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '|' => Token::Or,
+            '&' => Token::And,
+            '+' => Token::Plus,
+            ',' => Token::Comma,
+            '*' => Token::Star,
+            '!' | '=' | '<' | '>' => {
+                let followed_by_eq = chars.next_if(|&(_, c)| c == '=').is_some();
+                let comparison = match (c, followed_by_eq) {
+                    ('!', false) => {
+                        tokens.push((Token::Not, start..start + 1));
+                        continue;
+                    }
+                    ('!', true) => Comparison::Ne,
+                    ('=', true) => Comparison::Eq,
+                    ('<', false) => Comparison::Lt,
+                    ('<', true) => Comparison::Le,
+                    ('>', false) => Comparison::Gt,
+                    ('>', true) => Comparison::Ge,
+                    _ => return Err(LogicParseError::new(LogicParseErrorKind::UnexpectedCharacter(c), s, start..start + 1)),
+                };
+                let end = start + if followed_by_eq { 2 } else { 1 };
+                tokens.push((Token::Compare(comparison), start..end));
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            c if is_word_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !is_word_char(c) {
+                        break;
                     }
-                    _ => panic!("Count terms can only contain item literals")
+                    end = i + c.len_utf8();
+                    chars.next();
                 }
+                tokens.push((Token::Word(&s[start..end]), start..end));
+                continue;
             }
-            s.push_str("])");
-            s
-        }
+            c => return Err(LogicParseError::new(LogicParseErrorKind::UnexpectedCharacter(c), s, start..start + c.len_utf8())),
+        };
+        tokens.push((token, start..start + 1));
     }
+    tokens.push((Token::End, s.len()..s.len()));
+    Ok(tokens)
 }
 
-pub fn parse_reqs(mut s: &str) -> (Vec<Term>, &str) {
-    s = s.trim();
-    if s.is_empty() {
-        return (vec![], s)
+/// Recursive descent parser for requirement expressions:
+///
+/// ```text
+/// requirements := (term (',' term)*)?
+/// term         := '(' '|' terms ')' | '(' '&' terms ')' | '(' '+' (number | name) ',' terms ')' | '(' '!' term ')'
+///               | name ('*' number)? | name ('==' | '!=' | '<' | '<=' | '>' | '>=') value
+/// terms        := term (',' term)*
+/// ```
+struct Parser<'a> {
+    expression: &'a str,
+    tokens: Vec<(Token<'a>, Span)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> (Token<'a>, Span) {
+        let token = self.tokens[self.pos].clone();
+        if token.0 != Token::End {
+            self.pos += 1;
+        }
+        token
     }
-    let mut terms = vec![];
-    while !s.is_empty() {
-        // println!("{}", s);
-        if s.starts_with("(|") {
-            let (subterms, new_s) = parse_reqs(&s[2..]);
-            s = new_s;
-            terms.push(Term::Or(subterms));
-        } else if s.starts_with("(&") {
-            let (subterms, new_s) = parse_reqs(&s[2..]);
-            s = new_s;
-            terms.push(Term::And(subterms));
-        } else if s.starts_with("(+") {
-            if let Some(comma_pos) = s.find(',') {
-                // println!("{}", &s[2..comma_pos]);
-                let count_res = s[2..comma_pos].trim().parse::<u32>();
-                
-                let count = match count_res {
-                    Ok(c) => c,
-                    Err(_) => 0
-                };
-                let (subterms, new_s) = parse_reqs(&s[comma_pos + 1..]);
-                s = new_s;
-                terms.push(Term::Count(count, subterms));
-            }
+
+    fn peek(&self) -> &Token<'a> {
+        &self.tokens[self.pos].0
+    }
+
+    /// Where the last consumed token ended
+    fn end(&self) -> usize {
+        self.tokens[..self.pos].last().map_or(0, |(_, span)| span.end)
+    }
+
+    fn error(&self, kind: LogicParseErrorKind, span: Span) -> LogicParseError {
+        LogicParseError::new(kind, self.expression, span)
+    }
+
+    fn expected(&self, expected: &'static str, span: Span) -> LogicParseError {
+        let found = if span.is_empty() {
+            "the end of the requirements".to_string()
         } else {
-            if let Some(end) = s.find(|c| matches!(c, ',' | ')')) {
-                let term = s[..end].trim();
-                if !term.is_empty() {
-                    terms.push(Term::Lit(term));
-                }                
-                s = &s[end..];
-            } else {
-                terms.push(Term::Lit(s.trim()));
-                s = &s[s.len()..];
+            format!("'{}'", &self.expression[span.clone()])
+        };
+        self.error(LogicParseErrorKind::Expected { expected, found }, span)
+    }
+
+    /// Parses comma separated terms, up to the `)` matching `open`, or the end of the
+    /// expression if there is no `open`
+    fn terms(&mut self, open: Option<&Span>) -> Result<Vec<Term<'a>>, LogicParseError> {
+        let mut terms = vec![self.term()?];
+        loop {
+            let (token, span) = self.next();
+            match (token, open) {
+                (Token::Comma, _) => terms.push(self.term()?),
+                (Token::Close, Some(_)) | (Token::End, None) => return Ok(terms),
+                (Token::End, Some(open)) => return Err(self.error(LogicParseErrorKind::UnclosedParen, open.clone())),
+                (Token::Close, None) => return Err(self.error(LogicParseErrorKind::UnmatchedParen, span)),
+                (_, Some(_)) => return Err(self.expected("',' or ')'", span)),
+                (_, None) => return Err(self.expected("','", span)),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Term<'a>, LogicParseError> {
+        let (token, span) = self.next();
+        match token {
+            Token::Open => {
+                let (op, op_span) = self.next();
+                match op {
+                    Token::Or => {
+                        let terms = self.terms(Some(&span))?;
+                        Ok(Term::Or(terms, span.start..self.end()))
+                    }
+                    Token::And => {
+                        let terms = self.terms(Some(&span))?;
+                        Ok(Term::And(terms, span.start..self.end()))
+                    }
+                    Token::Plus => {
+                        let threshold = match self.next() {
+                            (Token::Word(word), span) => match word.parse() {
+                                Ok(n) => Bound::Number(n),
+                                Err(_) => Bound::Name(word, span),
+                            },
+                            (_, span) => return Err(self.expected("a number or integer parameter for the threshold", span)),
+                        };
+                        let (comma, comma_span) = self.next();
+                        if comma != Token::Comma {
+                            return Err(self.expected("',' after the threshold", comma_span));
+                        }
+                        let terms = self.terms(Some(&span))?;
+                        Ok(Term::Count(threshold, terms, span.start..self.end()))
+                    }
+                    Token::Not => {
+                        let term = self.term()?;
+                        match self.next() {
+                            (Token::Close, _) => Ok(Term::Not(Box::new(term), span.start..self.end())),
+                            (Token::End, _) => Err(self.error(LogicParseErrorKind::UnclosedParen, span)),
+                            (_, span) => Err(self.expected("')' after the negated term", span)),
+                        }
+                    }
+                    _ => Err(self.expected("'|', '&', '+' or '!' after '('", op_span)),
+                }
             }
-            s = s.trim_start_matches(',');
-            if let Some(s) = s.strip_prefix(')') {
-                return (terms, s.trim());
+            Token::Word(name) => {
+                let count = match *self.peek() {
+                    Token::Star => {
+                        self.next();
+                        Some(self.number("a number for the count")?)
+                    }
+                    Token::Compare(comparison) => {
+                        self.next();
+                        return match self.next() {
+                            (Token::Word(value), _) => Ok(Term::Compare { name, comparison, value, span: span.start..self.end() }),
+                            (_, span) => Err(self.expected("a value to compare with", span)),
+                        };
+                    }
+                    _ => None,
+                };
+                Ok(Term::Lit { name, count, span: span.start..self.end() })
             }
-            
+            _ => Err(self.expected("a requirement", span)),
         }
-        s = s.trim();
     }
 
-    (terms, s)
+    fn number(&mut self, expected: &'static str) -> Result<u32, LogicParseError> {
+        let (token, span) = self.next();
+        match token {
+            Token::Word(word) => word.parse().map_err(|_| self.expected(expected, span)),
+            _ => Err(self.expected(expected, span)),
+        }
+    }
+}
+
+/// Parses a comma separated list of requirements, which must all be met
+pub fn parse_reqs(s: &str) -> Result<Vec<Term<'_>>, LogicParseError> {
+    let tokens = tokenize(s)?;
+    let mut parser = Parser { expression: s, tokens, pos: 0 };
+    if *parser.peek() == Token::End {
+        return Ok(vec![]);
+    }
+    parser.terms(None)
 }
 
 #[test]
 fn parse_palace() {
     let s = "Locations.AccessUpperClouds, Helpers.CanSplit3, (|Items.RocsCape, Items.BombBag, Items.GustJar, Helpers.HasBoomerang, Helpers.HasBow)";
-    let (terms, remaining) = parse_reqs(s);
-    assert_eq!(remaining, "");
-    use Term::*;
-    assert_eq!(terms, vec![Lit("Locations.AccessUpperClouds"), Lit("Helpers.CanSplit3"), Or(vec![Lit("Items.RocsCape"), Lit("Items.BombBag"), Lit("Items.GustJar"), Lit("Helpers.HasBoomerang"), Lit("Helpers.HasBow")])]);
+    let terms = parse_reqs(s).unwrap();
+    let terms: Vec<_> = terms.iter().map(ToString::to_string).collect();
+    assert_eq!(terms, vec!["Locations.AccessUpperClouds", "Helpers.CanSplit3", "(| Items.RocsCape, Items.BombBag, Items.GustJar, Helpers.HasBoomerang, Helpers.HasBow)"]);
 }
 
+#[test]
+fn parse_errors_point_at_the_problem() {
+    let cases = [
+        ("(+3 Bottle)", "Expected ',' after the threshold, found 'Bottle'", 5),
+        ("(+, Bottle)", "Expected a number or integer parameter for the threshold, found ','", 3),
+        ("Bottle*x", "Expected a number for the count, found 'x'", 8),
+        ("(| Bottle, Lamp", "Unclosed '('", 1),
+        ("Bottle, Lamp)", "Unmatched ')'", 13),
+        ("Bottle Lamp", "Expected ',', found 'Lamp'", 8),
+        ("Bottle, ", "Expected a requirement, found the end of the requirements", 9),
+        ("Bottle; Lamp", "Unexpected character ';'", 7),
+        ("(! Glitch, Bottle)", "Expected ')' after the negated term, found ','", 10),
+        ("Setting = 3", "Unexpected character '='", 9),
+        ("Setting >=", "Expected a value to compare with, found the end of the requirements", 11),
+    ];
+    for (expression, message, column) in cases {
+        let err = parse_reqs(expression).unwrap_err();
+        assert_eq!(err.kind.to_string(), message, "{}", expression);
+        assert_eq!(err.column, column, "{}", expression);
+    }
+}
 
-pub fn gen_reqs2(terms: &[Term<'_>], items: &HashMap<&str, ItemId>, flags: &HashMap<&str, FlagId>, locations: &HashMap<&str, LocationId>, parameters: &HashSet<&str>) -> Result<Condition, LogicParseError> {
+pub fn gen_reqs2(terms: &[Term<'_>], expression: &str, items: &HashMap<&str, ItemId>, flags: &HashMap<&str, FlagId>, locations: &HashMap<&str, LocationId>, parameters: &HashMap<&str, &ParameterType>) -> Result<Condition, LogicParseError> {
     match terms {
         [] => Ok(Condition::NoRequirements),
         [ref a] => gen_req2(a, items, flags, locations, parameters),
-        _ => gen_req2(&Term::And(terms.to_vec()), items, flags, locations, parameters)
-    }
+        [first, .., last] => gen_req2(&Term::And(terms.to_vec(), first.span().start..last.span().end), items, flags, locations, parameters)
+    }.map_err(|(kind, span)| LogicParseError::new(kind, expression, span))
 }
 
 use thiserror::Error;
 #[derive(Debug, Error)]
-pub enum LogicParseError {
+pub enum LogicParseErrorKind {
     #[error("Unknown item {0}")]
     UnrecognizedItem(String),
     #[error("Unknown location {0}")]
@@ -255,84 +343,194 @@ pub enum LogicParseError {
     AmbiguousName(String),
     #[error("Thresholds require item literals, not more complex expressions")]
     ThresholdRequireItems,
+    #[error("Parameter {0} isn't a boolean; compare it with a value instead")]
+    NonBooleanParameter(String),
+    #[error("Invalid value to compare parameter {parameter} with; expected {expected}")]
+    InvalidOperand { parameter: String, expected: Box<str> },
+    #[error("Threshold must be a number or an integer parameter: {0}")]
+    InvalidThreshold(String),
+    #[error("Only parameters can be used here: {0}")]
+    NotStatic(String),
+    #[error("Only parameters can be negated: {0}")]
+    NegatedNonParameter(String),
+    #[error("Only items can have a count: {0}")]
+    CountedNonItem(String),
+    #[error("Unexpected character '{0}'")]
+    UnexpectedCharacter(char),
+    #[error("Expected {expected}, found {found}")]
+    Expected { expected: &'static str, found: String },
+    #[error("Unclosed '('")]
+    UnclosedParen,
+    #[error("Unmatched ')'")]
+    UnmatchedParen,
+}
+
+/// A problem with a requirement expression, and where it is
+#[derive(Debug)]
+pub struct LogicParseError {
+    pub kind: LogicParseErrorKind,
+    /// The offending part of the expression
+    pub snippet: String,
+    /// 1-based line in the logic file, if the expression came from one
+    pub line: Option<usize>,
+    /// 1-based column of the snippet, in the logic file if `line` is set, or the expression otherwise
+    pub column: usize,
+    /// Byte offset of the snippet in the expression
+    pub offset: usize,
+    /// The line the snippet is on
+    context: Box<str>,
+}
+
+impl LogicParseError {
+    pub fn new(kind: LogicParseErrorKind, expression: &str, span: Span) -> Self {
+        let line_start = expression[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = expression[span.start..].find('\n').map_or(expression.len(), |i| span.start + i);
+        LogicParseError {
+            kind,
+            snippet: expression[span.clone()].to_string(),
+            line: None,
+            column: expression[line_start..span.start].chars().count() + 1,
+            offset: span.start,
+            context: expression[line_start..line_end].into(),
+        }
+    }
+
+    /// Moves the error to a position in a file; `context` is the full text of that line
+    pub fn at(self, line: usize, column: usize, context: &str) -> Self {
+        LogicParseError {
+            line: Some(line),
+            column,
+            context: context.into(),
+            ..self
+        }
+    }
+
+    /// The line containing the problem, with the snippet underlined
+    pub fn diagnostic(&self) -> String {
+        let gutter = self.line.map(|line| line.to_string()).unwrap_or_default();
+        let indent: String = self.context.chars().take(self.column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let rest = self.context.chars().count().saturating_sub(self.column - 1);
+        let carets = self.snippet.lines().next().unwrap_or("").chars().count().min(rest).max(1);
+        format!("{} | {}\n{} | {}{}", gutter, self.context, " ".repeat(gutter.len()), indent, "^".repeat(carets))
+    }
+}
+
+impl std::fmt::Display for LogicParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => writeln!(f, "{} at line {}, column {}", self.kind, line, self.column)?,
+            None => writeln!(f, "{} at column {}", self.kind, self.column)?,
+        }
+        write!(f, "{}", self.diagnostic())
+    }
 }
 
-fn add_item(item: &str, items: &HashMap<&str, ItemId>) -> Result<Condition, LogicParseError> {
-    let mut bits = item.split('*');
-    let item = bits.next().expect("We always have at least *one* string piece");
+impl std::error::Error for LogicParseError {}
+
+type TermError = (LogicParseErrorKind, Span);
+
+fn add_item(item: &str, count: Option<u32>, items: &HashMap<&str, ItemId>) -> Result<Condition, LogicParseErrorKind> {
     let id = items.get(item);
-    let id = id.ok_or_else(|| LogicParseError::UnrecognizedItem(item.to_string()))?;
-    Ok(if let Some(count) = bits.next() {
-        Condition::Item(*id, count.parse::<usize>().unwrap())
-    } else {
-        Condition::Item(*id, 1)
-    })
+    let id = id.ok_or_else(|| LogicParseErrorKind::UnrecognizedItem(item.to_string()))?;
+    Ok(Condition::Item(*id, count.unwrap_or(1) as usize))
 }
-fn add_location(loc: &str, locations: &HashMap<&str, LocationId>) -> Result<Condition, LogicParseError> {
+fn add_location(loc: &str, locations: &HashMap<&str, LocationId>) -> Result<Condition, LogicParseErrorKind> {
     let id = locations.get(loc);
-    let id = id.ok_or_else(|| LogicParseError::UnrecognizedLocation(loc.to_string()))?;
+    let id = id.ok_or_else(|| LogicParseErrorKind::UnrecognizedLocation(loc.to_string()))?;
     Ok(Condition::Location(*id))
 }
-fn add_flag(flag: &str, flags: &HashMap<&str, FlagId>) -> Result<Condition, LogicParseError> {
+fn add_flag(flag: &str, flags: &HashMap<&str, FlagId>) -> Result<Condition, LogicParseErrorKind> {
     let id = flags.get(flag);
-    let id = id.ok_or_else(|| LogicParseError::UnrecognizedFlag(flag.to_string()))?;
+    let id = id.ok_or_else(|| LogicParseErrorKind::UnrecognizedFlag(flag.to_string()))?;
     Ok(Condition::Flag(*id))
 }
 
-fn add_parameter(name: &str, parameters: &HashSet<&str>) -> Result<Condition, LogicParseError> {
-    if parameters.contains(name) {
-        Ok(Condition::Parameter(name.into()))
-    } else {
-        Err(LogicParseError::UnrecognizedParameter(name.into()))
+fn add_parameter(name: &str, parameters: &HashMap<&str, &ParameterType>) -> Result<Condition, LogicParseErrorKind> {
+    match parameters.get(name) {
+        Some(ParameterType::Boolean) => Ok(Condition::Parameter(name.into())),
+        Some(_) => Err(LogicParseErrorKind::NonBooleanParameter(name.into())),
+        None => Err(LogicParseErrorKind::UnrecognizedParameter(name.into())),
     }
 }
 
-fn gen_req2(term: &Term, items: &HashMap<&str, ItemId>, flags: &HashMap<&str, FlagId>, locations: &HashMap<&str, LocationId>, parameters: &HashSet<&str>) -> Result<Condition, LogicParseError> {
+/// Looks up a parameter by name, with or without the `Parameter.` prefix
+fn parameter_type<'p>(name: &str, parameters: &HashMap<&str, &'p ParameterType>) -> Result<&'p ParameterType, LogicParseErrorKind> {
+    let name = name.strip_prefix("Parameter.").unwrap_or(name);
+    parameters.get(name).copied().ok_or_else(|| LogicParseErrorKind::UnrecognizedParameter(name.into()))
+}
+
+fn gen_req2(term: &Term, items: &HashMap<&str, ItemId>, flags: &HashMap<&str, FlagId>, locations: &HashMap<&str, LocationId>, parameters: &HashMap<&str, &ParameterType>) -> Result<Condition, TermError> {
     Ok(match term {
-        Term::Lit(s) => {
+        Term::Lit { name: s, count, span } => {
+            // Only items can be counted
+            let uncounted = |c: Condition| match count {
+                Some(_) => Err(LogicParseErrorKind::CountedNonItem(s.to_string())),
+                None => Ok(c),
+            };
             if let Some(item) = s.strip_prefix("Items.") {
-                add_item(item, items)?
+                add_item(item, *count, items)
             } else if let Some(helper) = s.strip_prefix("Helpers.") {
-                add_flag(helper, flags)?
+                add_flag(helper, flags).and_then(uncounted)
             } else if let Some(location) = s.strip_prefix("Locations.") {
-                add_location(location, locations)?
+                add_location(location, locations).and_then(uncounted)
             } else if let Some(parameter) = s.strip_prefix("Parameter.") {
-                add_parameter(parameter, parameters)?
+                add_parameter(parameter, parameters).and_then(uncounted)
             } else {
-                match (add_item(s, items), add_flag(s, flags), add_location(s, locations), add_parameter(s, parameters)) {
-                    (Ok(c), Err(_), Err(_), Err(_)) => c,
-                    (Err(_), Ok(c), Err(_), Err(_)) => c,
-                    (Err(_), Err(_), Ok(c), Err(_)) => c,
-                    (Err(_), Err(_), Err(_), Ok(c)) => c,
-                    (Err(_), Err(_), Err(_), Err(_)) => return Err(LogicParseError::UnrecognizedName(s.to_string())),
-                    (_, _, _, _) => return Err(LogicParseError::AmbiguousName(s.to_string()))
+                match (add_item(s, *count, items), add_flag(s, flags), add_location(s, locations), add_parameter(s, parameters)) {
+                    (Ok(c), Err(_), Err(_), Err(_)) => Ok(c),
+                    (Err(_), Ok(c), Err(_), Err(_)) => uncounted(c),
+                    (Err(_), Err(_), Ok(c), Err(_)) => uncounted(c),
+                    (Err(_), Err(_), Err(_), Ok(c)) => uncounted(c),
+                    (Err(_), Err(_), Err(_), Err(_)) => Err(LogicParseErrorKind::UnrecognizedName(s.to_string())),
+                    (_, _, _, _) => Err(LogicParseErrorKind::AmbiguousName(s.to_string()))
                 }
-            }
+            }.map_err(|kind| (kind, span.clone()))?
+        },
+        Term::Compare { name, comparison, value, span } => {
+            let typ = parameter_type(name, parameters).map_err(|kind| (kind, span.clone()))?;
+            let name = name.strip_prefix("Parameter.").unwrap_or(name);
+            // The value is the last token of the term
+            let value_span = span.end - value.len()..span.end;
+            let value = typ.parse_operand(value).ok_or_else(|| (LogicParseErrorKind::InvalidOperand {
+                parameter: name.to_string(),
+                expected: typ.to_string().into(),
+            }, value_span))?;
+            Condition::Compare(name.to_string(), *comparison, value)
         },
-        Term::And(terms) => {
+        Term::And(terms, _) => {
             Condition::And(terms.iter().map(|term| gen_req2(term, items, flags, locations, parameters)).collect::<Result<_, _>>()?)
         },
-        Term::Or(terms) => 
+        Term::Or(terms, _) => 
         {
             Condition::Or(terms.iter().map(|term| gen_req2(term, items, flags, locations, parameters)).collect::<Result<_, _>>()?)
         },
-        Term::Count(threshold, terms) => {
+        Term::Count(threshold, terms, _) => {
             let items = terms.iter().map(|term| match term {
-                Term::Lit(lit) => {
-                    let mut it = lit.split('*');
-                    let name = it.next().unwrap();
-                    
+                Term::Lit { name, count, span } => {
                     let id = (if let Some(item) = name.strip_prefix("Items.") {
                         items.get(item)
                     } else {
                         items.get(name)
-                    }).ok_or_else(|| LogicParseError::UnrecognizedItem(name.to_string()))?;
-                    let weight = it.next().map(|s| s.parse::<usize>().unwrap());
-                    Ok((*id, weight.unwrap_or(1)))
+                    }).ok_or_else(|| (LogicParseErrorKind::UnrecognizedItem(name.to_string()), span.clone()))?;
+                    Ok((*id, count.unwrap_or(1) as usize))
                 }
-                _ => Err(LogicParseError::ThresholdRequireItems)
+                _ => Err((LogicParseErrorKind::ThresholdRequireItems, term.span()))
             }).collect::<Result<Vec<_>, _>>();
-            Condition::AtLeast(*threshold as _, items?)
+            let threshold = match threshold {
+                Bound::Number(n) => Threshold::Fixed(*n as usize),
+                Bound::Name(name, span) => match parameter_type(name, parameters) {
+                    Ok(ParameterType::Integer { .. }) => Threshold::Parameter(name.strip_prefix("Parameter.").unwrap_or(name).to_string()),
+                    _ => return Err((LogicParseErrorKind::InvalidThreshold(name.to_string()), span.clone())),
+                },
+            };
+            Condition::AtLeast(threshold, items?)
+        }
+        Term::Not(term, _) => {
+            let cond = gen_req2(term, items, flags, locations, parameters)?;
+            if !cond.is_static() {
+                return Err((LogicParseErrorKind::NegatedNonParameter(term.to_string()), term.span()));
+            }
+            Condition::Not(Box::new(cond))
         }
     })
-}
\ No newline at end of file
+}
@@ -1,58 +1,24 @@
-use std::{io::Error, path::Path, collections::{HashMap, HashSet}, hash::Hash};
-mod logic_parse;
-mod header;
-mod logic;
-
-macro_rules! cond_or {
-    ($($e:expr),*) => {
-        Condition::Or(vec![$((Condition::from($e))),*])
-    };
-}
-macro_rules! cond_and {
-    ($($e:expr),*) => {
-        Condition::And(vec![$(Condition::from($e)),*])
-    };
-}
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum TileEntityType {
-    None = 0x00,
-    TestA = 0x01,
-    Chest = 0x02,
-    BigChest = 0x03,
-    TestB = 0x04,
-    TestC = 0x05,
-}
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum RomVersion {
-    EU,
-    JP,
-    US,
-    Unknown
-}
-struct Rom {
-    buf: Vec<u8>,
-    version: RomVersion
-}
-
-impl Rom {
-    fn new(fname: &Path) -> Result<Self, std::io::Error> {
-        let buf = std::fs::read(fname)?;
-        let version = match &buf[0xAC..0xAC + 4] {
-            b"BZMP" => RomVersion::EU,
-            b"BZMJ" => RomVersion::JP,
-            b"BZME" => RomVersion::US,
-            _ => RomVersion::Unknown
-        };
-        Ok(Self { buf, version })
-    }
-    fn read_at<const N: usize>(&self, pos: usize) -> [u8; N] {
-        let mut rv = [0; N];
-        rv[..N].clone_from_slice(&self.buf[pos..(N + pos)]);
-        rv
-    }
-    
-}
+use minish_random::{
+    logic::{
+        Accessibility, Algorithm, AllocationEvent, AllocationObserver, AllocatorSettings, CheckReport,
+        Conditionals, ItemDef,
+    },
+    preset::parse_setting,
+    BatchStats, Generator, LegacyImport, Logic, LogicLoader, Plando, PlandoPlacements, Preset,
+    RandomizerError, RetryPolicy, Spoiler, SpoilerFormat, StatsFormat,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use structopt::StructOpt;
 
 #[derive(Debug, Clone, StructOpt)]
 struct Args {
@@ -63,21 +29,296 @@ struct Args {
     match_category: bool,
     #[structopt(long)]
     temperature: Option<u32>,
+    /// Which locations must be reachable: full, beatable (the goal, with only junk locked away)
+    /// or minimal (just the goal)
+    #[structopt(long, default_value = "full")]
+    accessibility: Accessibility,
+    /// Placement algorithm: forward or assumed
+    #[structopt(long, default_value = "forward")]
+    algorithm: Algorithm,
+    /// Seed for the random number generator; a random one is picked if omitted
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// Print every decision the allocator makes
+    #[structopt(long, conflicts_with = "quiet")]
+    verbose: bool,
+    /// Only print the seed and any errors
+    #[structopt(long)]
+    quiet: bool,
+    /// Write every allocator event to this file as one JSON object per line
+    #[structopt(long, parse(from_os_str))]
+    trace_json: Option<PathBuf>,
+    /// Write a spoiler log to this file; YAML if it ends in .yml or .yaml, JSON otherwise
+    #[structopt(long, parse(from_os_str))]
+    spoiler: Option<PathBuf>,
+    /// Where to write the playthrough's dependency graph
+    #[structopt(long, parse(from_os_str), default_value = "graph.dot")]
+    graph: PathBuf,
+    /// How many attempts to make before giving up; restarts and backtracks both count
+    #[structopt(long, default_value = "20")]
+    max_attempts: usize,
+    /// When the allocator gets stuck, undo this many placements instead of restarting
+    #[structopt(long, default_value = "0")]
+    backtrack: usize,
+    /// Read parameter values from this file; a spoiler log works too
+    #[structopt(long, parse(from_os_str))]
+    preset: Option<PathBuf>,
+    /// Set a parameter, overriding the preset; can be repeated
+    #[structopt(long = "set", value_name = "Name=value", parse(try_from_str = parse_setting), number_of_values = 1)]
+    set: Vec<(String, String)>,
+    /// Never place progression in this location, on top of the preset's exclude list;
+    /// can be repeated
+    #[structopt(long = "exclude", value_name = "Location", number_of_values = 1)]
+    exclude: Vec<String>,
+    /// Place items by hand: a YAML file mapping location names to an item, or to a list of
+    /// items to pick one from
+    #[structopt(long, parse(from_os_str))]
+    plando: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-use logic::{PreLogic, Allocator, Item, LogicLoader, AssignmentChecker};
-use logic_parse::parse_logic;
-use structopt::StructOpt;
-fn main() {
-    // Rom::new(Path::new("foo.rom"));
-    let args = Args::from_args();
-    let f = std::fs::File::open(&args.logic_path).unwrap();
-    let (logic, item_pool_ids) = LogicLoader::from_reader(f);
-    let (logic, item_pool) = logic.build(&item_pool_ids);
-    let mut allocator = Allocator::new(item_pool, logic.locations.clone(), logic.flags.clone(), args.sadistic, args.match_category, args.temperature.unwrap_or(5));
-    let assignments = allocator.allocate(&mut rand::thread_rng());
-    // parse_logic(include_str!("default.logic.txt"));
-    let checker = AssignmentChecker::new(logic.locations, logic.flags);
-    checker.check_assignments(&assignments);
+#[derive(Debug, Clone, StructOpt)]
+enum Command {
+    /// Generate many seeds and report statistics about them; --seed sets the first seed.
+    /// Each seed gets --max-attempts tries: failure_rate counts the seeds that used them all up,
+    /// and stuck_rate the tries where the allocator gave up
+    GenerateMany {
+        #[structopt(long)]
+        count: usize,
+        /// How many seeds to generate at once
+        #[structopt(long, default_value = "1")]
+        threads: usize,
+        /// Write statistics to this file; CSV if it ends in .csv, JSON otherwise.
+        /// Printed to stdout as JSON if omitted
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Convert a legacy .logic.txt file into YAML logic, for the settings chosen with --set
+    /// and --preset; --seed picks the items of its !replace directives
+    ImportLegacy {
+        /// Where to write the YAML logic; printed to stdout if omitted
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// What beats the game, in the legacy requirement syntax
+        #[structopt(long, default_value = "Helpers.BeatVaati")]
+        goal: String,
+    },
+}
+
+impl Args {
+    fn preset(&self) -> Result<Preset, RandomizerError> {
+        let mut preset = match &self.preset {
+            Some(path) => Preset::from_reader(open(path)?)?,
+            None => Preset::default(),
+        };
+        for (name, value) in &self.set {
+            preset.set(name, value);
+        }
+        for location in &self.exclude {
+            preset.exclude(location);
+        }
+        Ok(preset)
+    }
+
+    /// Reads the plando file, if any, and checks it against the logic
+    fn plando(&self, logic: &Logic, item_pool: &Conditionals<ItemDef>) -> Result<Option<PlandoPlacements>, RandomizerError> {
+        match &self.plando {
+            Some(path) => {
+                let plando = Plando::from_reader(open(path)?)?;
+                Ok(Some(plando.resolve(logic, item_pool, self.allocator_settings())?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            backtrack: self.backtrack,
+        }
+    }
+
+    fn allocator_settings(&self) -> AllocatorSettings {
+        AllocatorSettings {
+            prefer_new_locations: self.sadistic,
+            match_category: self.match_category,
+            temperature: self.temperature.unwrap_or(5),
+            accessibility: self.accessibility,
+            algorithm: self.algorithm,
+        }
+    }
+}
+
+struct TextRenderer;
+impl AllocationObserver for TextRenderer {
+    fn on_event(&mut self, event: &AllocationEvent) {
+        println!("{}", event);
+    }
+}
+
+struct JsonTrace(BufWriter<File>);
+impl AllocationObserver for JsonTrace {
+    fn on_event(&mut self, event: &AllocationEvent) {
+        let res = serde_json::to_writer(&mut self.0, event)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(self.0));
+        if let Err(e) = res {
+            eprintln!("Unable to write trace: {}", e);
+        }
+    }
+}
 
+struct Observers(Vec<Box<dyn AllocationObserver>>);
+impl AllocationObserver for Observers {
+    fn on_event(&mut self, event: &AllocationEvent) {
+        for observer in &mut self.0 {
+            observer.on_event(event);
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.0.iter().any(|observer| observer.enabled())
+    }
+}
+
+/// Reports failed attempts when the full event log isn't being printed
+struct FailureRenderer;
+impl AllocationObserver for FailureRenderer {
+    fn on_event(&mut self, event: &AllocationEvent) {
+        match event {
+            AllocationEvent::Stuck { .. } => eprintln!("Allocation got stuck"),
+            AllocationEvent::Backtracked { .. } | AllocationEvent::CheckFailed { .. } => eprintln!("{}", event),
+            _ => {}
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        false
+    }
+}
+
+fn print_report(report: &CheckReport) {
+    for sphere in &report.spheres {
+        for flag in &sphere.flags {
+            print!("{{{}}}, ", flag.name);
+        }
+        for (item, loc) in &sphere.items {
+            print!("{} ({}), ", item, loc);
+        }
+        println!();
+    }
+    for flag in &report.unsatisfied_flags {
+        println!("Unsatisfied flag: {}", flag.name);
+    }
+}
+
+/// Opens an input file, naming it in the error if that fails
+fn open(path: impl AsRef<Path>) -> Result<File, RandomizerError> {
+    let path = path.as_ref();
+    File::open(path).map_err(|source| RandomizerError::ReadFile { path: path.to_owned(), source })
+}
+
+fn load_logic(path: &str, preset: &Preset) -> Result<(Logic, Conditionals<ItemDef>), RandomizerError> {
+    let f = open(path)?;
+    let (logic, item_pool_ids) = LogicLoader::from_reader_with_preset(f, preset)?;
+    Ok(logic.build(&item_pool_ids))
+}
+
+fn generate_many(args: &Args, count: usize, threads: usize, output: Option<&Path>) -> anyhow::Result<()> {
+    let (logic, item_pool) = load_logic(&args.logic_path, &args.preset()?)?;
+    let base_seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    eprintln!("Seeds: {} to {}", base_seed, base_seed.wrapping_add(count.saturating_sub(1) as u64));
+    let seeds: Vec<u64> = (0..count).map(|i| base_seed.wrapping_add(i as u64)).collect();
+    let mut generator = Generator::new(&logic, &item_pool, args.allocator_settings())
+        .with_policy(args.policy())
+        .with_threads(NonZeroUsize::new(threads).unwrap_or(NonZeroUsize::MIN));
+    if let Some(plando) = args.plando(&logic, &item_pool)? {
+        generator = generator.with_plando(plando);
+    }
+    let mut stats = BatchStats::new(&logic);
+    for result in generator.generate_parallel(&seeds) {
+        match result {
+            Ok(generated) => stats.record_success(&generated),
+            Err(RandomizerError::AttemptsExhausted { attempts, backtracks, check_failures }) => {
+                stats.record_failure(attempts, backtracks, check_failures)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    match output {
+        Some(path) => stats.write(BufWriter::new(File::create(path)?), StatsFormat::from_path(path))?,
+        None => stats.write(std::io::stdout().lock(), StatsFormat::Json)?,
+    }
+    Ok(())
+}
+
+fn import_legacy(args: &Args, output: Option<&Path>, goal: &str) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(&args.logic_path).map_err(|source| RandomizerError::ReadFile {
+        path: args.logic_path.clone().into(),
+        source,
+    })?;
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    eprintln!("Seed: {}", seed);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let import = LegacyImport::from_source(&source, &args.preset()?, goal, &mut rng)?;
+    // Don't write anything that wouldn't load, or that no seed could be generated from
+    import.check(args.allocator_settings())?;
+    match output {
+        Some(path) => import.write_yaml(BufWriter::new(File::create(path)?))?,
+        None => import.write_yaml(std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+    match &args.command {
+        Some(Command::GenerateMany { count, threads, output }) => {
+            return generate_many(&args, *count, *threads, output.as_deref());
+        }
+        Some(Command::ImportLegacy { output, goal }) => return import_legacy(&args, output.as_deref(), goal),
+        None => {}
+    }
+    let (logic, item_pool) = load_logic(&args.logic_path, &args.preset()?)?;
+    let trace = match &args.trace_json {
+        Some(path) => Some(Rc::new(RefCell::new(JsonTrace(BufWriter::new(File::create(path)?))))),
+        None => None,
+    };
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut generator = Generator::new(&logic, &item_pool, args.allocator_settings()).with_policy(args.policy());
+    if let Some(plando) = args.plando(&logic, &item_pool)? {
+        generator = generator.with_plando(plando);
+    }
+    let mut observers: Vec<Box<dyn AllocationObserver>> = vec![];
+    if args.verbose {
+        observers.push(Box::new(TextRenderer));
+    } else {
+        observers.push(Box::new(FailureRenderer));
+    }
+    if let Some(trace) = &trace {
+        observers.push(Box::new(trace.clone()));
+    }
+    generator.set_observer(Box::new(Observers(observers)));
+    println!("Seed: {}", seed);
+    let generated = generator.generate(seed)?;
+    if generated.attempts > 1 {
+        println!(
+            "Succeeded after {} attempts ({} backtracks) with seed {}",
+            generated.attempts, generated.backtracks, generated.attempt_seed
+        );
+    }
+    if !args.quiet {
+        println!("\n\nAssignments: ");
+        for (loc, item) in &generated.assignments {
+            println!("  {} -> {}", loc, item);
+        }
+        print_report(&generated.report);
+    }
+    std::fs::write(&args.graph, generated.report.graph.as_bytes())?;
+    if let Some(path) = &args.spoiler {
+        let spoiler = Spoiler::new(&logic, &generated);
+        spoiler.write(BufWriter::new(File::create(path)?), SpoilerFormat::from_path(path))?;
+    }
+    Ok(())
 }
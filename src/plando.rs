@@ -0,0 +1,283 @@
+use std::{collections::BTreeMap, io::Read, sync::Arc};
+
+use rand::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    logic::{AllocatorSettings, Conditionals, ItemDef, Location, Placements},
+    Logic, RandomizerError,
+};
+
+/// Items placed by hand, from a plando file mapping location names to an item name, or to a
+/// list of items to pick one from
+///
+/// ```yaml
+/// IntroItem1: Ocarina
+/// IntroItem2: [Bow, Boomerang]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Plando {
+    /// Location name -> item names
+    pub placements: BTreeMap<String, PlandoItems>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum PlandoItems {
+    Item(String),
+    AnyOf(Vec<String>),
+}
+
+impl PlandoItems {
+    fn names(&self) -> &[String] {
+        match self {
+            PlandoItems::Item(name) => std::slice::from_ref(name),
+            PlandoItems::AnyOf(names) => names,
+        }
+    }
+}
+
+impl Plando {
+    pub fn from_reader(reader: impl Read) -> Result<Self, RandomizerError> {
+        Ok(serde_yaml::from_reader(reader)?)
+    }
+
+    /// Checks the plando against the logic: every name has to exist, every item has to be
+    /// allowed in its location, and no item can be placed more often than the pool holds it
+    pub fn resolve(
+        &self,
+        logic: &Logic,
+        item_pool: &Conditionals<ItemDef>,
+        settings: AllocatorSettings,
+    ) -> Result<PlandoPlacements, RandomizerError> {
+        let mut available: BTreeMap<Arc<ItemDef>, usize> = BTreeMap::new();
+        for (item, _) in item_pool {
+            *available.entry(item.clone()).or_default() += 1;
+        }
+        for (_, item) in &logic.fixed {
+            if let Some(count) = available.get_mut(item) {
+                *count = count.saturating_sub(1);
+            }
+        }
+
+        let mut placements = vec![];
+        for (location_name, items) in &self.placements {
+            let location = logic
+                .locations
+                .iter()
+                .map(|(loc, _)| loc)
+                .find(|loc| &loc.name == location_name)
+                .ok_or_else(|| RandomizerError::PlandoUnknownLocation(location_name.clone()))?;
+            let mismatch = |item: &ItemDef, reason: String| RandomizerError::PlandoMismatch {
+                location: location_name.clone(),
+                item: item.name.clone(),
+                reason,
+            };
+            let restriction_name = |loc: &Location| {
+                loc.restriction
+                    .and_then(|r| logic.restrictions.get(&r))
+                    .map_or("no restriction".to_string(), |name| {
+                        format!("restriction {}", name)
+                    })
+            };
+            let mut options = vec![];
+            for item_name in items.names() {
+                let item = logic
+                    .items
+                    .iter()
+                    .map(|(item, _)| item)
+                    .find(|item| &item.name == item_name)
+                    .ok_or_else(|| RandomizerError::PlandoUnknownItem {
+                        location: location_name.clone(),
+                        item: item_name.clone(),
+                    })?;
+                if logic.fixed.iter().any(|(loc, _)| loc == location) {
+                    return Err(mismatch(
+                        item,
+                        "the location keeps its vanilla item".to_string(),
+                    ));
+                }
+                if item.restriction.is_some() && item.restriction != location.restriction {
+                    let restriction = item.restriction.and_then(|r| logic.restrictions.get(&r));
+                    return Err(mismatch(
+                        item,
+                        format!(
+                            "the item has restriction {}, but the location has {}",
+                            restriction.map_or("", String::as_str),
+                            restriction_name(location)
+                        ),
+                    ));
+                }
+                if settings.match_category && item.category != location.category {
+                    return Err(mismatch(
+                        item,
+                        format!(
+                            "the item is {:?}, but the location is {:?}",
+                            item.category, location.category
+                        ),
+                    ));
+                }
+                options.push(item.clone());
+            }
+            placements.push((location.clone(), options));
+        }
+
+        // Single items are placed first, so their counts have to fit on their own
+        let mut placed: BTreeMap<&Arc<ItemDef>, usize> = BTreeMap::new();
+        for (_, options) in &placements {
+            if let [item] = &options[..] {
+                *placed.entry(item).or_default() += 1;
+            }
+        }
+        for (item, placed) in &placed {
+            let count = available.get(*item).copied().unwrap_or(0);
+            if *placed > count {
+                return Err(RandomizerError::PlandoTooMany {
+                    item: item.name.clone(),
+                    placed: *placed,
+                    count,
+                });
+            }
+        }
+        for (location, options) in &placements {
+            let left = |item: &Arc<ItemDef>| {
+                available.get(item).copied().unwrap_or(0) > placed.get(item).copied().unwrap_or(0)
+            };
+            if options.len() != 1 && !options.iter().any(left) {
+                return Err(RandomizerError::PlandoNoItemLeft(location.name.clone()));
+            }
+        }
+
+        Ok(PlandoPlacements {
+            vanilla: logic.fixed.clone(),
+            placements,
+            available,
+        })
+    }
+}
+
+/// A plando that was checked against the logic
+#[derive(Debug, Clone)]
+pub struct PlandoPlacements {
+    /// Items the logic itself keeps in place
+    vanilla: Placements,
+    /// Each location with the items it may get
+    placements: Vec<(Arc<Location>, Vec<Arc<ItemDef>>)>,
+    /// How many of each item the pool has, besides the vanilla ones
+    available: BTreeMap<Arc<ItemDef>, usize>,
+}
+
+impl PlandoPlacements {
+    /// Picks an item for every location with a choice, and returns every fixed placement,
+    /// including the logic's own. Choices only pick items the pool still has left.
+    pub fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<Placements, RandomizerError> {
+        let mut available = self.available.clone();
+        let mut fixed = self.vanilla.clone();
+        let (single, choices): (Vec<_>, Vec<_>) = self
+            .placements
+            .iter()
+            .partition(|(_, options)| options.len() == 1);
+        for (location, options) in single.into_iter().chain(choices) {
+            let options: Vec<_> = options
+                .iter()
+                .filter(|item| available.get(*item).is_some_and(|&count| count > 0))
+                .collect();
+            let item = options
+                .choose(rng)
+                .ok_or_else(|| RandomizerError::PlandoNoItemLeft(location.name.clone()))?;
+            if let Some(count) = available.get_mut(*item) {
+                *count -= 1;
+            }
+            fixed.push((location.clone(), (*item).clone()));
+        }
+        Ok(fixed)
+    }
+}
+
+#[test]
+fn plando_is_validated() {
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Bow
+  - name: Key
+    restriction: Dungeon
+  - name: Rupee
+    count: 2
+locations:
+  - name: Start
+  - name: Field
+  - name: Cave
+    requirements: Sword
+  - name: Door
+    restriction: Dungeon
+    requirements: Sword
+  - name: Boss
+    requirements: Key, Bow
+goal: Key, Bow
+";
+    let (logic, pool) = crate::LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = logic.build(&pool);
+    let settings = AllocatorSettings::default();
+    let resolve = |plando: &str| {
+        Plando::from_reader(plando.as_bytes())
+            .unwrap()
+            .resolve(&logic, &pool, settings)
+    };
+
+    let plando = resolve("Cave: Bow\nStart: [Rupee, Sword]\nField: [Rupee, Sword]").unwrap();
+    for seed in 0..10 {
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        let fixed = plando.choose(&mut rng).unwrap();
+        let mut placed: Vec<_> = fixed
+            .iter()
+            .map(|(loc, item)| format!("{}={}", loc.name, item.name))
+            .collect();
+        placed.sort();
+        assert!(placed.contains(&"Cave=Bow".to_string()));
+        assert!(placed.iter().filter(|p| p.ends_with("Sword")).count() <= 1);
+    }
+
+    assert!(matches!(
+        resolve("Chest: Bow"),
+        Err(RandomizerError::PlandoUnknownLocation(_))
+    ));
+    assert!(matches!(
+        resolve("Cave: Bwo"),
+        Err(RandomizerError::PlandoUnknownItem { .. })
+    ));
+    assert!(matches!(
+        resolve("Cave: Key"),
+        Err(RandomizerError::PlandoMismatch { .. })
+    ));
+    assert!(matches!(
+        resolve("Cave: Sword\nField: Sword"),
+        Err(RandomizerError::PlandoTooMany {
+            placed: 2,
+            count: 1,
+            ..
+        })
+    ));
+    assert!(matches!(
+        resolve("Cave: Bow\nField: [Bow]\nStart: Sword"),
+        Err(RandomizerError::PlandoTooMany { .. })
+    ));
+
+    // Fixed placements are fine as long as the goal stays reachable, unlike a key behind
+    // the boss it's needed for
+    let plando = resolve("Door: Key").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let fixed = plando.choose(&mut rng).unwrap();
+    assert!(crate::Allocator::new(&logic, pool.clone(), fixed, settings).is_ok());
+    let plando = resolve("Boss: Bow").unwrap();
+    let fixed = plando.choose(&mut rng).unwrap();
+    match crate::Allocator::new(&logic, pool.clone(), fixed, settings) {
+        Err(RandomizerError::ImpossiblePool(message)) => {
+            assert!(message.contains("fixed placements"), "{}", message)
+        }
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Unreachable goal was accepted"),
+    }
+}
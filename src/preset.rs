@@ -0,0 +1,113 @@
+use std::{collections::BTreeMap, io::Read};
+
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use crate::{PreLogic, RandomizerError};
+
+/// Parameter values chosen by the user, from a preset file and `--set` options, and
+/// locations they never want to find progression in
+///
+/// A preset file has the same `parameters` map and `exclude` list as a spoiler log, so the
+/// settings of a generated seed can be reused by passing its spoiler as the preset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preset {
+    /// Parameter name -> value, as written by the user
+    pub parameters: BTreeMap<String, String>,
+    /// Names of locations that may only hold items no requirement mentions
+    pub exclude: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PresetData {
+    #[serde(default)]
+    parameters: BTreeMap<String, Value>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+impl Preset {
+    pub fn from_reader(reader: impl Read) -> Result<Self, RandomizerError> {
+        let data: PresetData = serde_yaml::from_reader(reader)?;
+        let parameters = data
+            .parameters
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::Bool(b) => b.to_string(),
+                    Value::Number(n) => n.to_string(),
+                    Value::String(s) => s,
+                    _ => return Err(RandomizerError::InvalidSetting(name)),
+                };
+                Ok((name, value))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Preset {
+            parameters,
+            exclude: data.exclude,
+        })
+    }
+
+    /// Overrides a parameter, such as from a `Name=value` command line option
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.parameters.insert(name.into(), value.into());
+    }
+
+    /// Excludes a location, such as from an `--exclude` command line option
+    pub fn exclude(&mut self, location: impl Into<String>) {
+        let location = location.into();
+        if !self.exclude.contains(&location) {
+            self.exclude.push(location);
+        }
+    }
+
+    /// Sets every parameter of the preset on the logic, checking that the logic declares
+    /// it and that the value fits its type
+    pub fn apply(&self, logic: &mut PreLogic) -> Result<(), RandomizerError> {
+        for (name, value) in &self.parameters {
+            logic.set_parameter(name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a `Name=value` setting
+pub fn parse_setting(s: &str) -> Result<(String, String), RandomizerError> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(RandomizerError::InvalidSetting(s.to_string())),
+    }
+}
+
+#[test]
+fn preset_values_are_validated() {
+    let yaml = "
+parameters:
+  - name: Glitched
+    type: Boolean
+  - name: Hearts
+    type:
+      Integer:
+        min: 3
+        max: 20
+flags: []
+item_pool: []
+locations: []
+";
+    let preset = Preset::from_reader("parameters:\n  Glitched: true\n  Hearts: 7\n".as_bytes()).unwrap();
+    let (mut logic, pool) = crate::LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    preset.apply(&mut logic).unwrap();
+    let (logic, _) = logic.build(&pool);
+    assert_eq!(logic.parameters["Glitched"], crate::logic::ParameterValue::Boolean(true));
+    assert_eq!(logic.parameters["Hearts"], crate::logic::ParameterValue::Integer(7));
+
+    let (mut logic, _) = crate::LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let mut preset = Preset::default();
+    preset.set("Hearts", "30");
+    assert!(matches!(preset.apply(&mut logic), Err(RandomizerError::InvalidParameterValue { .. })));
+    let mut preset = Preset::default();
+    preset.set("Heart", "3");
+    assert!(matches!(preset.apply(&mut logic), Err(RandomizerError::UnknownParameter(_))));
+}
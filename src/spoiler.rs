@@ -0,0 +1,184 @@
+use std::{collections::BTreeMap, io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    logic::{AllocatorSettings, ParameterValue},
+    Generated, Logic, RandomizerError,
+};
+
+/// A structured description of a generated seed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Spoiler {
+    pub seed: u64,
+    pub metadata: SpoilerMetadata,
+    pub settings: AllocatorSettings,
+    /// Effective value of every parameter; the spoiler can be passed back as a preset
+    pub parameters: BTreeMap<String, ParameterValue>,
+    /// Locations that were kept free of progression
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Location name -> item name, for locations without a restriction
+    pub unrestricted: BTreeMap<String, String>,
+    /// Restriction name -> location name -> item name
+    pub restrictions: BTreeMap<String, BTreeMap<String, String>>,
+    pub spheres: Vec<SpoilerSphere>,
+}
+
+/// How the seed came to be
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SpoilerMetadata {
+    /// Seed of the attempt that produced these assignments
+    pub attempt_seed: u64,
+    pub attempts: usize,
+    pub backtracks: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SpoilerSphere {
+    /// Flags unlocked by the items of earlier spheres
+    pub flags: Vec<String>,
+    /// Location name -> item name
+    pub items: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpoilerFormat {
+    Json,
+    Yaml,
+}
+
+impl SpoilerFormat {
+    /// Picks YAML for `.yml`/`.yaml` files, and JSON for everything else
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yml" | "yaml") => SpoilerFormat::Yaml,
+            _ => SpoilerFormat::Json,
+        }
+    }
+}
+
+impl Spoiler {
+    pub fn new(logic: &Logic, generated: &Generated) -> Self {
+        let mut unrestricted = BTreeMap::new();
+        let mut restrictions: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        for (loc, item) in &generated.assignments {
+            let group = match loc.restriction.and_then(|r| logic.restrictions.get(&r)) {
+                Some(restriction) => restrictions.entry(restriction.clone()).or_default(),
+                None => &mut unrestricted,
+            };
+            group.insert(loc.name.clone(), item.name.clone());
+        }
+        let spheres = generated
+            .report
+            .spheres
+            .iter()
+            .map(|sphere| SpoilerSphere {
+                flags: sphere.flags.iter().map(|f| f.name.clone()).collect(),
+                items: sphere
+                    .items
+                    .iter()
+                    .map(|(item, loc)| (loc.name.clone(), item.name.clone()))
+                    .collect(),
+            })
+            .collect();
+        Spoiler {
+            seed: generated.seed,
+            metadata: SpoilerMetadata {
+                attempt_seed: generated.attempt_seed,
+                attempts: generated.attempts,
+                backtracks: generated.backtracks,
+            },
+            settings: generated.settings,
+            parameters: logic.parameters.clone(),
+            exclude: logic
+                .locations
+                .iter()
+                .filter(|(loc, _)| loc.excluded)
+                .map(|(loc, _)| loc.name.clone())
+                .collect(),
+            unrestricted,
+            restrictions,
+            spheres,
+        }
+    }
+
+    pub fn write(&self, writer: impl Write, format: SpoilerFormat) -> Result<(), RandomizerError> {
+        match format {
+            SpoilerFormat::Json => serde_json::to_writer_pretty(writer, self)?,
+            SpoilerFormat::Yaml => serde_yaml::to_writer(writer, self)?,
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn spoiler_groups_by_restriction() {
+    use crate::{logic::AllocatorSettings, Generator, LogicLoader, Preset};
+    let yaml = "
+parameters:
+  - name: Glitched
+    type: Boolean
+    default: False
+flags: []
+item_pool:
+  - name: Sword
+  - name: Key
+    restriction: Dungeon
+  - name: Rupee
+    count: 2
+locations:
+  - name: Start
+  - name: Field
+  - name: Door
+    restriction: Dungeon
+    requirements: Sword
+  - name: Boss
+    restriction: Dungeon
+    requirements: Key
+goal: Key
+";
+    let mut preset = Preset::default();
+    preset.exclude("Field");
+    let (prelogic, pool) = LogicLoader::from_reader_with_preset(yaml.as_bytes(), &preset).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let generated = Generator::new(&logic, &pool, AllocatorSettings::default()).generate(7).unwrap();
+    let spoiler = Spoiler::new(&logic, &generated);
+    assert_eq!(spoiler.seed, 7);
+    assert_eq!(spoiler.metadata.attempts, 1);
+    assert_eq!(spoiler.exclude, ["Field"]);
+    fn names(locations: &BTreeMap<String, String>) -> Vec<&str> {
+        locations.keys().map(String::as_str).collect()
+    }
+    assert_eq!(names(&spoiler.unrestricted), ["Field", "Start"]);
+    let groups: Vec<_> = spoiler.restrictions.iter().map(|(group, locations)| (group.as_str(), names(locations))).collect();
+    assert_eq!(groups, [("Dungeon", vec!["Boss", "Door"])]);
+    assert_eq!(spoiler.spheres.len(), generated.report.spheres.len());
+    let placed: usize = spoiler.spheres.iter().map(|sphere| sphere.items.len()).sum();
+    assert_eq!(placed, 4);
+
+    let mut json = Vec::new();
+    spoiler.write(&mut json, SpoilerFormat::Json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["parameters"]["Glitched"], false);
+    assert_eq!(json["restrictions"]["Dungeon"].as_object().unwrap().len(), 2);
+    assert_eq!(json["unrestricted"].as_object().unwrap().len(), 2);
+    let mut yaml_spoiler = Vec::new();
+    spoiler.write(&mut yaml_spoiler, SpoilerFormat::Yaml).unwrap();
+    let reread: serde_yaml::Value = serde_yaml::from_slice(&yaml_spoiler).unwrap();
+    assert_eq!(serde_json::to_value(reread).unwrap(), json);
+    // The spoiler works as a preset
+    let preset = Preset::from_reader(yaml_spoiler.as_slice()).unwrap();
+    let (prelogic, pool) = LogicLoader::from_reader_with_preset(yaml.as_bytes(), &preset).unwrap();
+    let excluded: Vec<_> = prelogic.build(&pool).0.locations.iter().filter(|(loc, _)| loc.excluded).map(|(loc, _)| loc.name.clone()).collect();
+    assert_eq!(excluded, spoiler.exclude);
+
+    // Whatever the restrictions are called, they don't mix with the unrestricted locations
+    let clash = yaml.replace("Dungeon", "General");
+    let (prelogic, pool) = LogicLoader::from_reader(clash.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let generated = Generator::new(&logic, &pool, AllocatorSettings::default()).generate(7).unwrap();
+    let spoiler = Spoiler::new(&logic, &generated);
+    assert_eq!(names(&spoiler.unrestricted), ["Field", "Start"]);
+    assert_eq!(names(&spoiler.restrictions["General"]), ["Boss", "Door"]);
+}
@@ -0,0 +1,288 @@
+use std::{collections::BTreeMap, io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{logic::ItemCategory, Generated, Logic, RandomizerError};
+
+/// Aggregate statistics over many generated seeds
+///
+/// Only names are kept, so stats gathered on different threads (each with their own
+/// copy of the logic) can be merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    pub seeds: usize,
+    /// Seeds where the allocator gave up on every attempt
+    pub allocation_failures: usize,
+    /// Seeds where some attempt finished, but no assignments passed the check
+    pub check_failures: usize,
+    /// Attempts over all seeds, successful or not; every restart and backtrack counts as one
+    pub attempts: usize,
+    /// How many of the attempts were backtracks
+    pub backtracks: usize,
+    /// Attempts that finished, but failed the assignment check
+    pub attempt_check_failures: usize,
+    /// Total number of spheres over all successful seeds
+    pub spheres: usize,
+    /// Item name -> sphere index -> number of times a major item landed in that sphere
+    pub item_spheres: BTreeMap<String, BTreeMap<usize, usize>>,
+    /// Location name -> number of successful seeds where it held a major item
+    pub location_majors: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Csv,
+    Json,
+}
+
+impl StatsFormat {
+    /// Picks CSV for `.csv` files, and JSON for everything else
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => StatsFormat::Csv,
+            _ => StatsFormat::Json,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Summary<'a> {
+    seeds: usize,
+    allocation_failures: usize,
+    check_failures: usize,
+    failure_rate: f64,
+    attempts: usize,
+    backtracks: usize,
+    attempt_check_failures: usize,
+    stuck_attempts: usize,
+    stuck_rate: f64,
+    average_spheres: f64,
+    item_spheres: &'a BTreeMap<String, BTreeMap<usize, usize>>,
+    location_major_probability: BTreeMap<&'a str, f64>,
+}
+
+impl BatchStats {
+    /// Starts with every location of the logic at zero, so never-major locations still show up
+    pub fn new(logic: &Logic) -> Self {
+        BatchStats {
+            location_majors: logic
+                .locations
+                .iter()
+                .map(|(loc, _)| (loc.name.clone(), 0))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Records a seed that ran out of attempts, with what became of them
+    pub fn record_failure(&mut self, attempts: usize, backtracks: usize, check_failures: usize) {
+        self.seeds += 1;
+        if check_failures == 0 {
+            self.allocation_failures += 1;
+        } else {
+            self.check_failures += 1;
+        }
+        self.record_attempts(attempts, backtracks, check_failures);
+    }
+
+    pub fn record_success(&mut self, generated: &Generated) {
+        self.seeds += 1;
+        self.record_attempts(generated.attempts, generated.backtracks, generated.check_failures);
+        let report = &generated.report;
+        self.spheres += report.spheres.len();
+        for (idx, sphere) in report.spheres.iter().enumerate() {
+            for (item, loc) in &sphere.items {
+                if item.category != ItemCategory::Major {
+                    continue;
+                }
+                *self
+                    .item_spheres
+                    .entry(item.name.clone())
+                    .or_default()
+                    .entry(idx)
+                    .or_default() += 1;
+                *self.location_majors.entry(loc.name.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    fn record_attempts(&mut self, attempts: usize, backtracks: usize, check_failures: usize) {
+        self.attempts += attempts;
+        self.backtracks += backtracks;
+        self.attempt_check_failures += check_failures;
+    }
+
+    pub fn merge(&mut self, other: BatchStats) {
+        self.seeds += other.seeds;
+        self.allocation_failures += other.allocation_failures;
+        self.check_failures += other.check_failures;
+        self.record_attempts(other.attempts, other.backtracks, other.attempt_check_failures);
+        self.spheres += other.spheres;
+        for (item, spheres) in other.item_spheres {
+            let entry = self.item_spheres.entry(item).or_default();
+            for (idx, count) in spheres {
+                *entry.entry(idx).or_default() += count;
+            }
+        }
+        for (loc, count) in other.location_majors {
+            *self.location_majors.entry(loc).or_default() += count;
+        }
+    }
+
+    pub fn successes(&self) -> usize {
+        self.seeds - self.allocation_failures - self.check_failures
+    }
+
+    /// Share of seeds that failed on every attempt the retry policy allowed; see
+    /// [`BatchStats::stuck_rate`] for how often a single attempt gives up
+    pub fn failure_rate(&self) -> f64 {
+        ratio(self.allocation_failures + self.check_failures, self.seeds)
+    }
+
+    /// Attempts where the allocator stopped making progress, whether that led to a restart, a
+    /// backtrack, or the seed failing
+    pub fn stuck_attempts(&self) -> usize {
+        self.attempts - self.successes() - self.attempt_check_failures
+    }
+
+    /// Share of attempts where the allocator stopped making progress
+    pub fn stuck_rate(&self) -> f64 {
+        ratio(self.stuck_attempts(), self.attempts)
+    }
+
+    pub fn average_spheres(&self) -> f64 {
+        ratio(self.spheres, self.successes())
+    }
+
+    /// Chance that each location holds a major item, over successful seeds
+    pub fn location_major_probability(&self) -> BTreeMap<&str, f64> {
+        self.location_majors
+            .iter()
+            .map(|(loc, &count)| (loc.as_str(), ratio(count, self.successes())))
+            .collect()
+    }
+
+    pub fn write(&self, mut writer: impl Write, format: StatsFormat) -> Result<(), RandomizerError> {
+        match format {
+            StatsFormat::Json => {
+                let summary = Summary {
+                    seeds: self.seeds,
+                    allocation_failures: self.allocation_failures,
+                    check_failures: self.check_failures,
+                    failure_rate: self.failure_rate(),
+                    attempts: self.attempts,
+                    backtracks: self.backtracks,
+                    attempt_check_failures: self.attempt_check_failures,
+                    stuck_attempts: self.stuck_attempts(),
+                    stuck_rate: self.stuck_rate(),
+                    average_spheres: self.average_spheres(),
+                    item_spheres: &self.item_spheres,
+                    location_major_probability: self.location_major_probability(),
+                };
+                serde_json::to_writer_pretty(&mut writer, &summary)?;
+                writeln!(writer)?;
+            }
+            StatsFormat::Csv => {
+                // One long table, so every statistic fits in the same set of columns
+                writeln!(writer, "statistic,name,sphere,value")?;
+                writeln!(writer, "seeds,,,{}", self.seeds)?;
+                writeln!(writer, "allocation_failures,,,{}", self.allocation_failures)?;
+                writeln!(writer, "check_failures,,,{}", self.check_failures)?;
+                writeln!(writer, "failure_rate,,,{}", self.failure_rate())?;
+                writeln!(writer, "attempts,,,{}", self.attempts)?;
+                writeln!(writer, "backtracks,,,{}", self.backtracks)?;
+                writeln!(writer, "attempt_check_failures,,,{}", self.attempt_check_failures)?;
+                writeln!(writer, "stuck_attempts,,,{}", self.stuck_attempts())?;
+                writeln!(writer, "stuck_rate,,,{}", self.stuck_rate())?;
+                writeln!(writer, "average_spheres,,,{}", self.average_spheres())?;
+                for (item, spheres) in &self.item_spheres {
+                    for (idx, count) in spheres {
+                        writeln!(writer, "item_sphere,{},{},{}", csv_field(item), idx, count)?;
+                    }
+                }
+                for (loc, probability) in self.location_major_probability() {
+                    writeln!(writer, "location_major_probability,{},,{}", csv_field(loc), probability)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn ratio(n: usize, d: usize) -> f64 {
+    if d == 0 {
+        0.
+    } else {
+        n as f64 / d as f64
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[test]
+fn stats_merge_and_write() {
+    use crate::{logic::AllocatorSettings, Generator, LogicLoader};
+    let yaml = "
+flags: []
+item_pool:
+  - name: Sword
+  - name: Rupee
+    category: Minor
+locations:
+  - name: Start
+  - name: Cave, upper
+    requirements: Sword
+";
+    let (prelogic, pool) = LogicLoader::from_reader(yaml.as_bytes()).unwrap();
+    let (logic, pool) = prelogic.build(&pool);
+    let generator = Generator::new(&logic, &pool, AllocatorSettings::default());
+    let mut stats = BatchStats::new(&logic);
+    stats.record_success(&generator.generate(0).unwrap());
+    // Stuck on all five attempts, two of which were backtracks
+    stats.record_failure(5, 2, 0);
+    // Stats from another thread
+    let mut other = BatchStats::new(&logic);
+    other.record_success(&generator.generate(1).unwrap());
+    // One of five attempts finished, but failed the check
+    other.record_failure(5, 0, 1);
+    stats.merge(other);
+
+    assert_eq!((stats.seeds, stats.successes()), (4, 2));
+    assert_eq!((stats.attempts, stats.backtracks, stats.stuck_attempts()), (12, 2, 9));
+
+    let mut csv = Vec::new();
+    stats.write(&mut csv, StatsFormat::Csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "statistic,name,sphere,value
+seeds,,,4
+allocation_failures,,,1
+check_failures,,,1
+failure_rate,,,0.5
+attempts,,,12
+backtracks,,,2
+attempt_check_failures,,,1
+stuck_attempts,,,9
+stuck_rate,,,0.75
+average_spheres,,,2
+item_sphere,Sword,0,2
+location_major_probability,\"Cave, upper\",,0
+location_major_probability,Start,,1
+"
+    );
+    let mut json = Vec::new();
+    stats.write(&mut json, StatsFormat::Json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["failure_rate"], 0.5);
+    assert_eq!(json["stuck_rate"], 0.75);
+    assert_eq!(json["average_spheres"], 2.0);
+    assert_eq!(json["item_spheres"]["Sword"]["0"], 2);
+    assert_eq!(json["location_major_probability"]["Cave, upper"], 0.0);
+    assert_eq!(json["location_major_probability"]["Start"], 1.0);
+}